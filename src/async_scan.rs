@@ -0,0 +1,68 @@
+use crate::{LogError, LogMatcher, ProgressTracker};
+
+impl LogMatcher {
+    /// Like [`discover_sources`](LogMatcher::discover_sources), but run on tokio's pool of
+    /// blocking threads so that the runtime is not stalled.  The matcher is moved into the
+    /// task and handed back along with the errors.
+    pub async fn discover_sources_async(self, tracker: ProgressTracker) -> (Self, Vec<LogError>) {
+        self.run_blocking(tracker, LogMatcher::discover_sources)
+            .await
+    }
+
+    /// Like [`extract_log_statements`](LogMatcher::extract_log_statements), but run on
+    /// tokio's pool of blocking threads.  The parsing is still spread across the rayon
+    /// thread pool.
+    pub async fn extract_log_statements_async(
+        self,
+        tracker: ProgressTracker,
+    ) -> (Self, Vec<LogError>) {
+        self.run_blocking(tracker, LogMatcher::extract_log_statements)
+            .await
+    }
+
+    async fn run_blocking<F>(mut self, tracker: ProgressTracker, scan: F) -> (Self, Vec<LogError>)
+    where
+        F: FnOnce(&mut LogMatcher, &ProgressTracker) -> Vec<LogError> + Send + 'static,
+    {
+        let task = tokio::task::spawn_blocking(move || {
+            let errors = scan(&mut self, &tracker);
+            (self, errors)
+        });
+        match task.await {
+            Ok(retval) => retval,
+            Err(err) => match err.try_into_panic() {
+                Ok(payload) => std::panic::resume_unwind(payload),
+                Err(err) => panic!("scan task did not finish: {}", err),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{LogMatcher, LogRefBuilder, ProgressTracker};
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_scan_async() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("main.rs"),
+            "fn main() {\n    info!(\"listening on port {}\", port);\n}\n",
+        )
+        .unwrap();
+        let mut log_matcher = LogMatcher::new();
+        log_matcher.add_root(dir.path()).unwrap();
+        let (log_matcher, errors) = log_matcher
+            .discover_sources_async(ProgressTracker::new())
+            .await;
+        assert!(errors.is_empty());
+        let (log_matcher, errors) = log_matcher
+            .extract_log_statements_async(ProgressTracker::new())
+            .await;
+        assert!(errors.is_empty());
+        let log_ref = LogRefBuilder::new().build("listening on port 8080");
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert_eq!(mapping.src_ref.unwrap().line_no, 2);
+    }
+}
@@ -0,0 +1,356 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::source_hier::SourceHierContent;
+use crate::{
+    CachedStatement, ExtractOptions, LogError, LogMatcher, MatchOptions, Matcher, SourceLanguage,
+    SourceRef, StatementsInFile, VIRTUAL_ROOT,
+};
+
+/// The version of the cache format, which needs to be bumped whenever the layout of the
+/// cache or the way that patterns are built changes.
+const CACHE_VERSION: u32 = 5;
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    options: CachedOptions,
+    roots: Vec<CachedRoot>,
+}
+
+/// The options that change which statements are extracted from a file or how their
+/// patterns are built.  The cached statements are only used if the matcher they are loaded
+/// into has the same options.
+#[derive(Serialize, Deserialize, PartialEq)]
+struct CachedOptions {
+    cpp_log_functions: Vec<String>,
+    detect_cpp_log_macros: bool,
+    identifiers: HashMap<SourceLanguage, Vec<String>>,
+    queries: HashMap<SourceLanguage, String>,
+    case_insensitive: bool,
+    flexible_whitespace: bool,
+    grouped_numbers: bool,
+}
+
+impl From<&LogMatcher> for CachedOptions {
+    fn from(log_matcher: &LogMatcher) -> Self {
+        let extract_options = log_matcher.extract_options.clone();
+        Self {
+            cpp_log_functions: extract_options.cpp_log_functions,
+            detect_cpp_log_macros: extract_options.detect_cpp_log_macros,
+            identifiers: extract_options.identifiers,
+            queries: extract_options.queries,
+            case_insensitive: log_matcher.options.case_insensitive,
+            flexible_whitespace: log_matcher.options.flexible_whitespace,
+            grouped_numbers: log_matcher.options.grouped_numbers,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedRoot {
+    path: PathBuf,
+    files: Vec<CachedFile>,
+}
+
+/// A source file that was scanned, along with the state of the file at the time so that
+/// the cached statements can be checked for staleness.  Files without any statements are
+/// also kept so they are not parsed again.
+#[derive(Serialize, Deserialize)]
+struct CachedFile {
+    path: PathBuf,
+    modified_time: SystemTime,
+    len: u64,
+    statements: Vec<CachedStatement>,
+}
+
+fn invalid_cache(path: &Path, message: String) -> LogError {
+    LogError::InvalidCache {
+        path: path.to_path_buf(),
+        source: Arc::new(<serde_json::Error as serde::de::Error>::custom(message)),
+    }
+}
+
+/// Read a file written by [`LogMatcher::save_cache`] and check its version.
+fn read_cache(path: &Path) -> Result<CacheFile, LogError> {
+    let file = File::open(path).map_err(|err| LogError::CannotReadCache {
+        path: path.to_path_buf(),
+        source: Arc::new(err),
+    })?;
+    let cache: CacheFile =
+        serde_json::from_reader(BufReader::new(file)).map_err(|err| LogError::InvalidCache {
+            path: path.to_path_buf(),
+            source: Arc::new(err),
+        })?;
+    if cache.version != CACHE_VERSION {
+        return Err(invalid_cache(
+            path,
+            format!("unsupported cache version {}", cache.version),
+        ));
+    }
+    Ok(cache)
+}
+
+impl LogMatcher {
+    /// Save the roots and the log statements that were extracted from them to the given
+    /// file, so that a later run can skip parsing the files that have not changed by
+    /// calling [`load_cache`](LogMatcher::load_cache).  The options that the statements
+    /// were extracted with are saved as well.
+    pub fn save_cache(&self, path: &Path) -> Result<(), LogError> {
+        let to_error = |err: io::Error| LogError::CannotWriteCache {
+            path: path.to_path_buf(),
+            source: Arc::new(err),
+        };
+        let mut roots = Vec::new();
+        for (root_path, coll) in &self.roots {
+            if root_path.as_os_str() == VIRTUAL_ROOT {
+                continue;
+            }
+            let mut files = Vec::new();
+            coll.tree.visit_with_path(|file_path, node| {
+                if let (
+                    Some(_),
+                    SourceHierContent::File {
+                        info,
+                        last_modified_time,
+                        len,
+                    },
+                ) = (node.last_scan_time, &node.content)
+                {
+                    files.push(CachedFile {
+                        path: file_path.to_path_buf(),
+                        modified_time: *last_modified_time,
+                        len: *len,
+                        statements: coll
+                            .files_with_statements
+                            .get(&info.id)
+                            .map(|stmts| stmts.log_statements.iter().map(Into::into).collect())
+                            .unwrap_or_default(),
+                    });
+                }
+            });
+            roots.push(CachedRoot {
+                path: root_path.clone(),
+                files,
+            });
+        }
+        let cache = CacheFile {
+            version: CACHE_VERSION,
+            options: self.into(),
+            roots,
+        };
+        let mut writer = BufWriter::new(File::create(path).map_err(to_error)?);
+        serde_json::to_writer(&mut writer, &cache).map_err(|err| to_error(err.into()))?;
+        writer.flush().map_err(to_error)
+    }
+
+    /// Create a matcher from a file written by [`save_cache`](LogMatcher::save_cache).  The
+    /// matcher gets the [`ExtractOptions`](crate::ExtractOptions) and the match options
+    /// that change the patterns, like `case_insensitive`, that the cache was saved with,
+    /// so the cached statements can be used, and the defaults for everything else.  Use
+    /// [`load_cache_into`](LogMatcher::load_cache_into) for a matcher that needs other
+    /// options, ignore patterns, or a preprocessor.
+    pub fn load_cache(path: &Path) -> Result<Self, LogError> {
+        let cache = read_cache(path)?;
+        let mut log_matcher = LogMatcher::with_options(MatchOptions {
+            case_insensitive: cache.options.case_insensitive,
+            flexible_whitespace: cache.options.flexible_whitespace,
+            grouped_numbers: cache.options.grouped_numbers,
+            ..MatchOptions::default()
+        });
+        log_matcher.set_extract_options(ExtractOptions {
+            cpp_log_functions: cache.options.cpp_log_functions.clone(),
+            detect_cpp_log_macros: cache.options.detect_cpp_log_macros,
+            identifiers: cache.options.identifiers.clone(),
+            queries: cache.options.queries.clone(),
+        })?;
+        log_matcher.apply_cache(path, cache)?;
+        Ok(log_matcher)
+    }
+
+    /// Add the roots from a file written by [`save_cache`](LogMatcher::save_cache) to this
+    /// matcher, which should already have the options, ignore patterns, and preprocessor
+    /// that it will be used with.  The roots are scanned again and the cached statements
+    /// are only used for the files that have the same modified time and size as when the
+    /// cache was saved.  If the statements were extracted with different
+    /// [`ExtractOptions`](crate::ExtractOptions), or with different match options that
+    /// change the patterns, like `case_insensitive`, none of them are used.  The
+    /// statements for the other files are extracted by the next call to
+    /// [`extract_log_statements`](LogMatcher::extract_log_statements).
+    pub fn load_cache_into(&mut self, path: &Path) -> Result<(), LogError> {
+        let cache = read_cache(path)?;
+        self.apply_cache(path, cache)
+    }
+
+    /// Add the roots and statements from a cache that was read from the given path.
+    fn apply_cache(&mut self, path: &Path, cache: CacheFile) -> Result<(), LogError> {
+        let stale = cache.options != CachedOptions::from(&*self);
+        for root in cache.roots {
+            self.add_root(&root.path)?;
+            let Some(coll) = self.roots.get_mut(&root.path) else {
+                continue;
+            };
+            coll.tree.ignore.clone_from(&self.ignore);
+            coll.tree.sync();
+            if stale {
+                continue;
+            }
+            for file in root.files {
+                let Some(info) = coll
+                    .tree
+                    .mark_scanned(&file.path, file.modified_time, file.len)
+                else {
+                    continue;
+                };
+                if file.statements.is_empty() {
+                    continue;
+                }
+                let log_statements = file
+                    .statements
+                    .into_iter()
+                    .map(SourceRef::try_from)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|err| invalid_cache(path, err.to_string()))?;
+                coll.files_with_statements.insert(
+                    info.id,
+                    StatementsInFile {
+                        path: log_statements[0].source_path.clone(),
+                        id: info.id,
+                        matcher: Matcher::new(&log_statements),
+                        log_statements,
+                    },
+                );
+            }
+        }
+        self.update_literal_index()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{LogMatcher, LogRefBuilder, MatchBackend, MatchOptions, ProgressTracker};
+    use std::fs;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let src_dir = dir.path().join("src");
+        fs::create_dir(&src_dir).unwrap();
+        fs::write(
+            src_dir.join("main.rs"),
+            "fn main() {\n    debug!(\"connected to {} in {}ms\", host, elapsed);\n}\n",
+        )
+        .unwrap();
+        fs::write(src_dir.join("util.rs"), "fn helper() {}\n").unwrap();
+        fs::write(
+            src_dir.join("stale.rs"),
+            "fn stale() {\n    info!(\"old message\");\n}\n",
+        )
+        .unwrap();
+        let tracker = ProgressTracker::new();
+        let mut log_matcher = LogMatcher::new();
+        log_matcher.add_root(&src_dir).unwrap();
+        assert!(log_matcher.discover_sources(&tracker).is_empty());
+        assert!(log_matcher.extract_log_statements(&tracker).is_empty());
+        let cache_path = dir.path().join("log2src.cache");
+        log_matcher.save_cache(&cache_path).unwrap();
+
+        sleep(Duration::from_millis(10));
+        fs::write(
+            src_dir.join("stale.rs"),
+            "fn stale() {\n    info!(\"new message!\");\n}\n",
+        )
+        .unwrap();
+
+        let mut loaded_into = LogMatcher::new();
+        loaded_into.load_cache_into(&cache_path).unwrap();
+        assert_eq!(loaded_into.summary().statements, 1);
+        let mut loaded = LogMatcher::load_cache(&cache_path).unwrap();
+        let log_ref = LogRefBuilder::new().build("connected to db1 in 12ms");
+        let mapping = loaded.match_log_statement(&log_ref).unwrap();
+        assert_eq!(mapping.src_ref.unwrap().line_no, 2);
+        assert_eq!(mapping.variables[1].value, "12");
+        // Only the file that changed is reported as needing to be parsed.
+        assert!(loaded.discover_sources(&tracker).is_empty());
+        assert!(loaded
+            .statements_for_file(&src_dir.join("stale.rs"))
+            .is_none());
+        assert!(loaded.extract_log_statements(&tracker).is_empty());
+        assert_eq!(loaded.summary().statements, 2);
+        let log_ref = LogRefBuilder::new().build("new message!");
+        assert!(loaded.match_log_statement(&log_ref).is_some());
+        let log_ref = LogRefBuilder::new().build("old message");
+        assert!(loaded.match_log_statement(&log_ref).is_none());
+
+        fs::write(&cache_path, "{\"version\":0,\"roots\":[]}").unwrap();
+        assert!(LogMatcher::load_cache(&cache_path).is_err());
+        assert!(LogMatcher::new().load_cache_into(&cache_path).is_err());
+    }
+
+    #[test]
+    fn test_load_cache_with_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let src_dir = dir.path().join("src");
+        fs::create_dir(&src_dir).unwrap();
+        fs::write(
+            src_dir.join("main.rs"),
+            "fn main() {\n    debug!(\"connected to {}\", host);\n}\n",
+        )
+        .unwrap();
+        let tracker = ProgressTracker::new();
+        let mut log_matcher = LogMatcher::new();
+        log_matcher.add_root(&src_dir).unwrap();
+        assert!(log_matcher.rescan_changed(&tracker).is_empty());
+        let cache_path = dir.path().join("log2src.cache");
+        log_matcher.save_cache(&cache_path).unwrap();
+
+        // The options of the matcher are kept when loading.
+        let mut loaded = LogMatcher::with_options(MatchOptions {
+            backend: MatchBackend::LiteralIndex,
+            ..Default::default()
+        });
+        loaded.load_cache_into(&cache_path).unwrap();
+        assert!(loaded.literal_index.is_some());
+        let log_ref = LogRefBuilder::new().build("connected to db1");
+        assert!(loaded.match_log_statement(&log_ref).is_some());
+
+        // The cached patterns are case-sensitive, so they are not used.
+        let mut loaded = LogMatcher::with_options(MatchOptions {
+            case_insensitive: true,
+            ..Default::default()
+        });
+        loaded.load_cache_into(&cache_path).unwrap();
+        assert!(loaded.is_empty());
+        assert!(loaded.extract_log_statements(&tracker).is_empty());
+        let log_ref = LogRefBuilder::new().build("CONNECTED TO db1");
+        assert!(loaded.match_log_statement(&log_ref).is_some());
+
+        let mut loaded = LogMatcher::new();
+        loaded.set_cpp_log_functions(&["LOG"]);
+        loaded.load_cache_into(&cache_path).unwrap();
+        assert!(loaded.is_empty());
+
+        // The constructor takes the options from the cache, so the statements are used.
+        let mut log_matcher = LogMatcher::with_options(MatchOptions {
+            case_insensitive: true,
+            ..Default::default()
+        });
+        log_matcher.set_cpp_log_functions(&["LOG"]);
+        log_matcher.add_root(&src_dir).unwrap();
+        assert!(log_matcher.rescan_changed(&tracker).is_empty());
+        log_matcher.save_cache(&cache_path).unwrap();
+        let loaded = LogMatcher::load_cache(&cache_path).unwrap();
+        assert!(!loaded.is_empty());
+        assert_eq!(loaded.extract_options.cpp_log_functions, ["LOG"]);
+        let log_ref = LogRefBuilder::new().build("CONNECTED TO db1");
+        assert!(loaded.match_log_statement(&log_ref).is_some());
+    }
+}
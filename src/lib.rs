@@ -2,43 +2,65 @@ use itertools::Itertools;
 use miette::Diagnostic;
 use rayon::prelude::*;
 use regex::{Captures, Regex, RegexSet};
-use serde::Serialize;
-use std::collections::HashMap;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs::File;
 use std::io;
-use std::ops::Deref;
+use std::io::BufRead;
+use std::ops::{Deref, Range};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 use std::sync::{Arc, LazyLock};
 use thiserror::Error;
-use tree_sitter::Language;
+use tree_sitter::{Language, Query};
 
+#[cfg(feature = "async")]
+mod async_scan;
+mod cache;
 mod code_source;
+mod literal_index;
 mod log_format;
 mod progress;
 mod source_hier;
 mod source_query;
 mod source_ref;
+mod suggest;
 
 // TODO: doesn't need to be exposed if we can clean up the arguments to do_mapping
+use crate::literal_index::LiteralIndex;
 use crate::progress::WorkGuard;
-use crate::source_hier::{ScanEvent, SourceFileID, SourceHierContent, SourceHierTree};
-use crate::source_ref::{CallSite, FormatArgument};
+use crate::source_hier::{
+    is_virtual_path, IgnoreList, ScanEvent, SourceHierContent, SourceHierTree,
+};
+use crate::source_ref::{CallSite, FormatArgument, REGEX_SIZE_FACTOR};
 pub use code_source::CodeSource;
 pub use log_format::LogFormat;
 pub use progress::ProgressTracker;
 pub use progress::ProgressUpdate;
 pub use progress::WorkInfo;
+pub use source_hier::SourceFileID;
 use source_query::QueryResult;
 pub use source_query::SourceQuery;
+pub use source_ref::CachedStatement;
+pub use source_ref::ContextField;
 pub use source_ref::SourceRef;
+pub use source_ref::VarType;
+pub use source_ref::GLOBAL_NAME;
+pub use suggest::{DiffOp, MatchSuggestion};
 
 #[derive(Error, Debug, Diagnostic, Clone)]
 pub enum LogError {
     #[error("unable to read line {line}")]
     UnableToReadLine { line: usize, source: Arc<io::Error> },
-    #[error("invalid log format regular expression")]
-    InvalidFormatRegex { source: regex::Error },
+    #[error("invalid log format regular expression \"{pattern}\"")]
+    InvalidLogFormat {
+        pattern: String,
+        source: regex::Error,
+    },
     #[error("unknown capture in log format: {name}")]
     #[diagnostic(help(
         "The supported captures are: timestamp, thread, level, file, line, method, and body"
@@ -47,7 +69,11 @@ pub enum LogError {
     #[error("log format is missing capture: {name}")]
     #[diagnostic(help("A log format must have a 'body' capture at a minimum"))]
     FormatMissingCapture { name: String },
-    #[error("\"{path}\" is already covered by \"{root}\"")]
+    #[error("log format is missing the '{name}' group")]
+    #[diagnostic(help("A log format must have a 'body' group at a minimum"))]
+    LogFormatMissingGroup { name: String },
+    #[error("\"{path}\" overlaps the existing root \"{root}\"")]
+    #[diagnostic(help("Roots cannot be nested, add only the outermost directory"))]
     PathExists { path: PathBuf, root: PathBuf },
     #[error("cannot read source file \"{path}\"")]
     #[diagnostic(severity(warning))]
@@ -73,11 +99,58 @@ pub enum LogError {
         path: PathBuf,
         source: Arc<io::Error>,
     },
+    #[error("invalid glob pattern \"{pattern}\"")]
+    InvalidGlob {
+        pattern: String,
+        source: Arc<glob::PatternError>,
+    },
+    #[error("cannot read cache file \"{path}\"")]
+    CannotReadCache {
+        path: PathBuf,
+        source: Arc<io::Error>,
+    },
+    #[error("cannot write cache file \"{path}\"")]
+    CannotWriteCache {
+        path: PathBuf,
+        source: Arc<io::Error>,
+    },
+    #[error("invalid cache file \"{path}\"")]
+    #[diagnostic(help(
+        "The cache might be from a different version of log2src, delete it and try again"
+    ))]
+    InvalidCache {
+        path: PathBuf,
+        source: Arc<serde_json::Error>,
+    },
+    #[error("no supported source files found in \"{root}\"")]
+    #[diagnostic(
+        severity(warning),
+        help("Make sure the path refers to a tree with supported source code")
+    )]
+    RootHasNoSupportedFiles { root: PathBuf },
+    #[error("invalid tree-sitter query for {language}")]
+    InvalidQuery {
+        language: &'static str,
+        source: Arc<tree_sitter::QueryError>,
+    },
     #[error("unsupported file type \"{name}\"")]
     UnsupportedFileType { name: String },
     #[error("no log messages found in input")]
     #[diagnostic(help("Make sure the log format matches the input"))]
     NoLogMessages,
+    #[error("cannot build the literal index")]
+    #[diagnostic(
+        severity(warning),
+        help("Messages are matched with the RegexSet backend instead")
+    )]
+    CannotBuildLiteralIndex {
+        source: Arc<aho_corasick::BuildError>,
+    },
+    #[error("invalid path for an in-memory file \"{path}\"")]
+    #[diagnostic(help(
+        "The path has to be relative to the project and cannot contain '..' components"
+    ))]
+    InvalidVirtualPath { path: PathBuf },
 }
 
 /// Collection of log statements in a single source file
@@ -87,10 +160,167 @@ pub struct StatementsInFile {
     id: SourceFileID,
     pub log_statements: Vec<SourceRef>,
     /// A single matcher for all log statements.
-    /// XXX If there are too many in the file, the RegexSet constructor
-    /// will fail with CompiledTooBig. We should probably fall back to
-    /// manually trying each one at that point...
-    pub matcher: RegexSet,
+    pub matcher: Matcher,
+}
+
+/// The statements in a file are serialized with the complete form of each statement and
+/// the combined matcher is rebuilt from their patterns when they are deserialized.
+impl Serialize for StatementsInFile {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("StatementsInFile", 3)?;
+        state.serialize_field("path", &self.path)?;
+        state.serialize_field("id", &self.id)?;
+        state.serialize_field(
+            "log_statements",
+            &self
+                .log_statements
+                .iter()
+                .map(CachedStatement::from)
+                .collect::<Vec<_>>(),
+        )?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for StatementsInFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Fields {
+            path: String,
+            id: SourceFileID,
+            log_statements: Vec<CachedStatement>,
+        }
+
+        let fields = Fields::deserialize(deserializer)?;
+        let log_statements = fields
+            .log_statements
+            .into_iter()
+            .map(SourceRef::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(serde::de::Error::custom)?;
+        Ok(StatementsInFile {
+            path: fields.path,
+            id: fields.id,
+            matcher: Matcher::new(&log_statements),
+            log_statements,
+        })
+    }
+}
+
+/// The matcher for all the log statements in a file.
+#[derive(Debug)]
+pub enum Matcher {
+    /// The patterns combined into a single set.
+    Set(RegexSet),
+    /// The individual patterns, used when there are too many statements in the file to
+    /// combine them into a set.
+    Fallback(Vec<Regex>),
+}
+
+impl Matcher {
+    fn new(log_statements: &[SourceRef]) -> Self {
+        match RegexSet::new(log_statements.iter().map(|src_ref| &src_ref.pattern)) {
+            Ok(set) => Matcher::Set(set),
+            Err(regex::Error::CompiledTooBig(_)) => Matcher::Fallback(
+                log_statements
+                    .iter()
+                    .map(|src_ref| src_ref.matcher.clone())
+                    .collect(),
+            ),
+            Err(err) => panic!("To combine patterns: {}", err),
+        }
+    }
+
+    /// Find the index of the first pattern that matches the given text.
+    pub fn first_match(&self, text: &str) -> Option<usize> {
+        match self {
+            Matcher::Set(set) => set.matches(text).iter().next(),
+            Matcher::Fallback(regexes) => regexes.iter().position(|regex| regex.is_match(text)),
+        }
+    }
+
+    /// Find the indexes of all the patterns that match the given text.
+    pub fn all_matches(&self, text: &str) -> Vec<usize> {
+        match self {
+            Matcher::Set(set) => set.matches(text).into_iter().collect(),
+            Matcher::Fallback(regexes) => regexes
+                .iter()
+                .positions(|regex| regex.is_match(text))
+                .collect(),
+        }
+    }
+}
+
+impl StatementsInFile {
+    /// Check if this file matches the file hint from a log message.  A hint with multiple
+    /// components, like `a/util.rs`, has to match the trailing components of the path so
+    /// that files with the same name in different directories can be told apart.  Other
+    /// hints only need to be part of the path, like a Java class name.
+    fn matches_file_hint(&self, hint: &str) -> bool {
+        path_matches_file_hint(&self.path, hint)
+    }
+
+    /// Rebuild the patterns for the statements based on the options that change how the
+    /// text of a message is matched.
+    fn apply_options(&mut self, options: &MatchOptions) {
+        if !options.case_insensitive && !options.flexible_whitespace && !options.grouped_numbers {
+            return;
+        }
+        for src_ref in &mut self.log_statements {
+            if options.grouped_numbers {
+                src_ref.grouped_numbers();
+            }
+            if options.case_insensitive {
+                src_ref.ignore_case();
+            }
+            if options.flexible_whitespace {
+                src_ref.flexible_whitespace();
+            }
+        }
+        self.matcher = Matcher::new(&self.log_statements);
+    }
+
+    /// Find the statements in this file that match the given message body.  They are
+    /// ranked by how close they are to the line number hint, if there is one, and then by
+    /// how specific they are, the ones with the most literal text first.  Any ties go to
+    /// the statement that comes first in the file.
+    fn matches(&self, body: &str, lineno: Option<usize>) -> Vec<&SourceRef> {
+        // The set cannot check that repeated arguments have the same value, so the
+        // statements it finds need to be verified.
+        self.matcher
+            .all_matches(body)
+            .into_iter()
+            .filter_map(|index| Some((index, self.log_statements.get(index)?)))
+            .filter(|(_index, src_ref)| src_ref.is_match(body))
+            .sorted_by_key(|(index, src_ref)| {
+                (
+                    lineno.map_or(0, |lineno| line_distance(src_ref, lineno)),
+                    Reverse(src_ref.quality),
+                    *index,
+                )
+            })
+            .map(|(_index, src_ref)| src_ref)
+            .collect()
+    }
+}
+
+/// The number of lines between the given line number and a statement, zero if the line
+/// is within the statement.
+pub(crate) fn line_distance(src_ref: &SourceRef, lineno: usize) -> usize {
+    if lineno < src_ref.line_no {
+        src_ref.line_no - lineno
+    } else {
+        lineno.saturating_sub(src_ref.end_line_no)
+    }
+}
+
+fn path_matches_file_hint(path: &str, hint: &str) -> bool {
+    let hint_path = Path::new(hint);
+    if hint_path.components().count() > 1 {
+        Path::new(path).ends_with(hint_path)
+    } else {
+        path.contains(hint)
+    }
 }
 
 /// Collection of individual source files under a root path
@@ -99,18 +329,367 @@ pub struct SourceTree {
     pub files_with_statements: HashMap<SourceFileID, StatementsInFile>,
 }
 
+/// Options that control which calls in the source code are treated as log statements.
+#[derive(Clone, Debug)]
+pub struct ExtractOptions {
+    /// The names of C++ functions and macros that are logging calls.  A call matches if the
+    /// function name, ignoring any namespace or object qualifiers, is in this list.
+    pub cpp_log_functions: Vec<String>,
+    /// Also treat function-like macros that are `#define`d in a C++ file as logging calls
+    /// when they expand to a call to one of the `cpp_log_functions`.  Only the calls in the
+    /// same file as the `#define` are found, so macros that are defined in a header, even
+    /// one under the same root, need to be added to `cpp_log_functions` instead.
+    pub detect_cpp_log_macros: bool,
+    /// Extra identifiers, in lowercase, that are related to logging in each language, like
+    /// the names of the methods of a house logging wrapper.  These are added to the
+    /// built-in lists and are never treated as the variables of a log statement.
+    pub identifiers: HashMap<SourceLanguage, Vec<String>>,
+    /// Tree-sitter queries that replace the built-in query for a language.  The queries
+    /// are checked when the options are passed to [`LogMatcher::set_extract_options`].
+    pub queries: HashMap<SourceLanguage, String>,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            cpp_log_functions: CPP_LOG_FUNCTIONS.iter().map(|s| s.to_string()).collect(),
+            detect_cpp_log_macros: true,
+            identifiers: HashMap::new(),
+            queries: HashMap::new(),
+        }
+    }
+}
+
+impl ExtractOptions {
+    /// Check if the given text is one of the logging related identifiers for a language.
+    fn is_identifier(&self, language: SourceLanguage, text: &str) -> bool {
+        let text = text.trim().to_lowercase();
+        language.get_identifiers().contains(&text.as_str())
+            || self
+                .identifiers
+                .get(&language)
+                .is_some_and(|identifiers| identifiers.contains(&text))
+    }
+
+    /// Get the options to use for the given source file, which includes any logging macros
+    /// that are defined in the file.  The files are extracted independently, so the
+    /// `#define`s in the headers that the file includes are not followed.
+    fn for_source(&self, code: &CodeSource) -> Cow<'_, ExtractOptions> {
+        if !self.detect_cpp_log_macros || code.info.language != SourceLanguage::Cpp {
+            return Cow::Borrowed(self);
+        }
+        let defines = CPP_DEFINE_REGEX
+            .captures_iter(&code.buffer)
+            .map(|cap| (cap.get(1).unwrap().as_str(), cap.get(2).unwrap().as_str()))
+            .collect::<Vec<_>>();
+        if defines.is_empty() {
+            return Cow::Borrowed(self);
+        }
+        let mut options = self.clone();
+        // Repeat until no new macros are found so that macros that wrap other logging
+        // macros are also found.
+        loop {
+            let names = options
+                .cpp_log_functions
+                .iter()
+                .map(|name| regex::escape(name))
+                .join("|");
+            let call_regex = Regex::new(&format!(r#"(?:^|\W)(?:{names})\s*\("#)).unwrap();
+            let found = defines
+                .iter()
+                .filter(|(name, body)| {
+                    !options.cpp_log_functions.iter().any(|known| known == name)
+                        && call_regex.is_match(body)
+                })
+                .map(|(name, _body)| name.to_string())
+                .collect::<Vec<_>>();
+            if found.is_empty() {
+                break;
+            }
+            options.cpp_log_functions.extend(found);
+        }
+        Cow::Owned(options)
+    }
+}
+
+/// Regex for a function-like macro definition in C/C++.  The first group is the name of the
+/// macro and the second is the rest of the definition, including continuation lines.
+static CPP_DEFINE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?m)^[ \t]*#[ \t]*define[ \t]+(\w+)\(((?:[^\n]*\\\r?\n)*[^\n]*)"#).unwrap()
+});
+
+/// Map the name of a level, or of a logging function like `LOG.warn`, to a canonical level
+/// so that the levels from log messages and source code can be compared.
+fn normalize_level(name: &str) -> Option<&'static str> {
+    let word = name
+        .rsplit(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .next()?;
+    let word = word.to_ascii_lowercase();
+    // .NET's ILogger has methods like `LogInformation`.
+    match word.strip_prefix("log").unwrap_or(&word) {
+        "trace" | "finer" | "finest" | "verbose" => Some("trace"),
+        "debug" | "fine" => Some("debug"),
+        "info" | "information" | "notice" => Some("info"),
+        "warn" | "warning" => Some("warn"),
+        "error" | "err" | "severe" | "exception" => Some("error"),
+        "critical" | "crit" | "fatal" => Some("critical"),
+        // Android's `Log` and Timber use single letters, like `Log.d`.
+        "v" => Some("trace"),
+        "d" => Some("debug"),
+        "i" => Some("info"),
+        "w" => Some("warn"),
+        "e" => Some("error"),
+        "wtf" => Some("critical"),
+        _ => None,
+    }
+}
+
+/// Level words that are commonly found at the start of a log message.
+const DEFAULT_LEVELS: &[&str] = &[
+    "TRACE", "DEBUG", "FINE", "INFO", "NOTICE", "WARN", "WARNING", "ERROR", "SEVERE", "CRITICAL",
+    "FATAL",
+];
+
+/// The marker that is commonly appended to a message that was truncated.
+const DEFAULT_TRUNCATION_MARKER: &str = "...";
+
+/// The number of statements with the closest literal text that are compared in full with
+/// a message by [`LogMatcher::suggest`].
+const SUGGESTION_CANDIDATES: usize = 4;
+
+/// The strategy used to find the statements that match a log message.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum MatchBackend {
+    /// Check the message against the combined RegexSet for each file.
+    #[default]
+    RegexSet,
+    /// Narrow down the candidates across all files using an index of the literal text in
+    /// each statement and then verify them using the statement's regex.  This is faster
+    /// when there are a large number of statements.
+    LiteralIndex,
+}
+
+/// Options that control how log messages are matched against log statements.
+#[derive(Clone, Debug, Default)]
+pub struct MatchOptions {
+    /// The strategy used to find matching statements.
+    pub backend: MatchBackend,
+    /// Level words to remove from the start of a message body before matching, along with
+    /// any separator that follows them.  This is useful when the log format does not
+    /// capture the level separately.  The comparison is case-insensitive.
+    pub strip_levels: Vec<String>,
+    /// Compare numeric values by their parsed value, limited to the precision declared by
+    /// the placeholder, instead of by their exact text.  For example, `1.23` and `1.2340`
+    /// are both considered equal to `1.234` for a `{:.3}` placeholder.
+    pub precision_tolerant: bool,
+    /// Compare the level of a log message, if the format captures it, with the level of the
+    /// matched statement and flag the mapping when they disagree.  Statements with a
+    /// matching level are preferred, but a mismatched one can still be matched since the
+    /// level is expected to drift over time.
+    pub check_levels: bool,
+    /// Match the text of messages regardless of case, for log processors that change the
+    /// case of messages.  Since the patterns are compiled when the statements are
+    /// extracted, this has to be set before calling
+    /// [`extract_log_statements`](LogMatcher::extract_log_statements).
+    pub case_insensitive: bool,
+    /// Match any run of whitespace in the literal text of a statement with any amount of
+    /// whitespace in a message, for log pipelines that collapse spaces.  The values are
+    /// still matched exactly.  Like `case_insensitive`, this has to be set before calling
+    /// [`extract_log_statements`](LogMatcher::extract_log_statements).
+    pub flexible_whitespace: bool,
+    /// Allow the values of integer and floating-point placeholders, like `%d` or `{:x}`, to
+    /// have their digits grouped by a separator, like `1,000` or `1 000`.  Placeholders
+    /// without a numeric type, like `{}`, or a `{:.2}` for an argument that is not known to
+    /// be a number, already match any text.  Like
+    /// `case_insensitive`, this has to be set before calling
+    /// [`extract_log_statements`](LogMatcher::extract_log_statements).
+    pub grouped_numbers: bool,
+    /// Process the roots in sorted order, without any parallelism, and break ties between
+    /// equally good matches by the path and then the line number of the statement, so
+    /// that the results are reproducible across runs.
+    pub deterministic: bool,
+    /// Remove ANSI escape sequences, like the colors added by a console logger, from each
+    /// line read by [`match_reader`](LogMatcher::match_reader) before it is parsed.  The
+    /// original line is kept in the mapping for display.  Pass it on to
+    /// [`filter_log_with`] for a log that is already in memory.
+    pub strip_ansi: bool,
+    /// When a message was not parsed by a log format, also try to match the statements
+    /// against the suffixes of the line that start after some whitespace, so that a line
+    /// with a leading timestamp or level can still be matched.  The longest suffix that
+    /// matches is used.
+    pub unanchored: bool,
+    /// The text that a log collector appends to a message that it cut short, like `...`.
+    /// When a message ends in the marker and no statement matches it in full, the
+    /// statements are matched against the rest of the message as a prefix, and the last
+    /// variable is flagged as truncated if the cut was in its value.
+    pub truncation_marker: Option<String>,
+    /// The lowest [`confidence`] that a match from
+    /// [`match_reader`](LogMatcher::match_reader) can have.  Lines whose best match is
+    /// below this are passed through without a statement, like lines that did not match.
+    pub min_confidence: f32,
+}
+
+impl MatchOptions {
+    /// Enable stripping of the common level words from the start of message bodies.
+    pub fn with_default_levels(mut self) -> Self {
+        self.strip_levels = DEFAULT_LEVELS.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    /// Enable matching of messages that were cut short and end in `...`.
+    pub fn with_default_truncation_marker(mut self) -> Self {
+        self.truncation_marker = Some(DEFAULT_TRUNCATION_MARKER.to_string());
+        self
+    }
+
+    /// Apply the options to a log message before it is matched.
+    fn prepare<'a>(&self, log_ref: &LogRef<'a>) -> LogRef<'a> {
+        if self.strip_levels.is_empty() {
+            return *log_ref;
+        }
+        let mut details = log_ref.details.unwrap_or_default();
+        details.body = Some(self.strip_level(log_ref.body()));
+        LogRef {
+            line: log_ref.line,
+            details: Some(details),
+        }
+    }
+
+    /// Remove a leading level word, optionally in square brackets, and the separator that
+    /// follows it from the given body.
+    fn strip_level<'a>(&self, body: &'a str) -> &'a str {
+        let trimmed = body.trim_start();
+        let (bracketed, rest) = match trimmed.strip_prefix('[') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed),
+        };
+        for level in &self.strip_levels {
+            let Some(word) = rest.get(..level.len()) else {
+                continue;
+            };
+            if !word.eq_ignore_ascii_case(level) {
+                continue;
+            }
+            let mut after = &rest[level.len()..];
+            if after.starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+                continue;
+            }
+            if bracketed {
+                match after.strip_prefix(']') {
+                    Some(unbracketed) => after = unbracketed,
+                    None => continue,
+                }
+            }
+            return after
+                .trim_start_matches(|c: char| c.is_whitespace() || matches!(c, ':' | '-' | '|'));
+        }
+        body
+    }
+}
+
+/// The path of the root that holds the files from [`LogMatcher::index_virtual`].
+pub const VIRTUAL_ROOT: &str = "<virtual>";
+
+/// A function that transforms the body of a log message before it is matched.
+pub type Preprocessor = Box<dyn Fn(&str) -> Cow<str> + Send + Sync>;
+
 /// Collection of root paths to their tree of source files
 /// that contain log statements.
 pub struct LogMatcher {
     roots: HashMap<PathBuf, SourceTree>,
+    extract_options: ExtractOptions,
+    options: MatchOptions,
+    literal_index: Option<LiteralIndex>,
+    preprocessor: Option<Preprocessor>,
+    ignore: IgnoreList,
+    stats: MatchCounters,
+}
+
+/// The counters behind [`MatchStats`], which are atomic so that messages can be matched
+/// from several threads.
+#[derive(Debug, Default)]
+struct MatchCounters {
+    matched: AtomicUsize,
+    unmatched: AtomicUsize,
+    ambiguous: AtomicUsize,
 }
 
 impl LogMatcher {
     /// Create an empty LogMatcher
     pub fn new() -> Self {
+        Self::with_options(MatchOptions::default())
+    }
+
+    /// Create an empty LogMatcher that uses the given options when matching.
+    pub fn with_options(options: MatchOptions) -> Self {
         Self {
             roots: HashMap::new(),
+            extract_options: ExtractOptions::default(),
+            options,
+            literal_index: None,
+            preprocessor: None,
+            ignore: IgnoreList::default(),
+            stats: MatchCounters::default(),
+        }
+    }
+
+    /// Set a function that is applied to the body of every log message before it is
+    /// matched.  This is an escape hatch for formats that need custom handling, like
+    /// un-escaping or reordering fields.  The mappings that are returned still refer to
+    /// the original message.
+    pub fn set_preprocessor(&mut self, preprocessor: Preprocessor) {
+        self.preprocessor = Some(preprocessor);
+    }
+
+    /// Set the options used when extracting log statements from source files.  The new
+    /// options only apply to files that are scanned after this call.  If one of the
+    /// queries is not valid, a [`LogError::InvalidQuery`] is returned and the current
+    /// options are kept.
+    pub fn set_extract_options(&mut self, options: ExtractOptions) -> Result<(), LogError> {
+        for (language, query) in &options.queries {
+            check_query(*language, query)?;
         }
+        self.extract_options = options;
+        Ok(())
+    }
+
+    /// Add identifiers that are related to logging in the given language, like the names
+    /// of the methods of a house logging wrapper, so that they are not mistaken for the
+    /// variables of a log statement.  Like
+    /// [`set_extract_options`](LogMatcher::set_extract_options), this only applies to files
+    /// that are scanned after this call.
+    pub fn add_identifiers(&mut self, language: SourceLanguage, identifiers: &[&str]) {
+        self.extract_options
+            .identifiers
+            .entry(language)
+            .or_default()
+            .extend(
+                identifiers
+                    .iter()
+                    .map(|identifier| identifier.to_lowercase()),
+            );
+    }
+
+    /// Replace the names of the C++ functions and macros that are treated as logging calls,
+    /// like `LOG` or `spdlog::info`, so that calls to other functions with a string
+    /// argument, like `throw std::runtime_error("...")`, are not mistaken for log
+    /// statements.  A name with a namespace only matches calls that use that namespace.
+    /// Like [`set_extract_options`](LogMatcher::set_extract_options), this only applies to
+    /// files that are scanned after this call.
+    pub fn set_cpp_log_functions(&mut self, names: &[&str]) {
+        self.extract_options.cpp_log_functions =
+            names.iter().map(|name| name.to_string()).collect();
+    }
+
+    /// Replace the built-in tree-sitter query used to find the log statements in the given
+    /// language, like to target a bespoke logging macro.  The query should capture the
+    /// format string of a logging call, the same as the built-in ones.  Like
+    /// [`set_extract_options`](LogMatcher::set_extract_options), this only applies to files
+    /// that are scanned after this call.
+    pub fn set_query(&mut self, language: SourceLanguage, query: String) -> Result<(), LogError> {
+        check_query(language, &query)?;
+        self.extract_options.queries.insert(language, query);
+        Ok(())
     }
 
     /// True if no log statements are recognized by this matcher.
@@ -120,21 +699,97 @@ impl LogMatcher {
             .all(|(_path, coll)| coll.files_with_statements.is_empty())
     }
 
-    /// Add a source root path
+    /// Add a source root path.  Roots cannot be nested, so each file is under at most one
+    /// root, and a path that overlaps an existing root, the root itself, a path under it,
+    /// or a parent of it, is rejected with [`LogError::PathExists`] no matter the order
+    /// the roots are added in.
     pub fn add_root(&mut self, path: &Path) -> Result<(), LogError> {
-        if let Some(_existing_path) = self.match_path(path) {
-        } else {
-            self.roots
-                .entry(path.to_owned())
-                .or_insert_with(|| SourceTree {
-                    tree: SourceHierTree::from(&path),
-                    files_with_statements: HashMap::new(),
-                });
+        if let Some(existing_path) = self.overlapping_root(path) {
+            return Err(LogError::PathExists {
+                path: path.to_owned(),
+                root: existing_path.clone(),
+            });
+        }
+        self.roots.insert(
+            path.to_owned(),
+            SourceTree {
+                tree: SourceHierTree::from(&path),
+                files_with_statements: HashMap::new(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Remove a root that was added with [`add_root`](LogMatcher::add_root), along with
+    /// the log statements that were found under it.  Returns `false` if the path is not a
+    /// root.  If the literal index cannot be rebuilt for the remaining statements, messages
+    /// are matched with the RegexSet backend until the next scan.
+    pub fn remove_root(&mut self, path: &Path) -> bool {
+        if self.roots.remove(path).is_none() {
+            return false;
+        }
+        if self.literal_index.is_some() {
+            let _ = self.update_literal_index();
+        }
+        true
+    }
+
+    /// Rebuild the literal index, if that is the backend, after the statements changed.  If
+    /// the index cannot be built, it is dropped so that the RegexSet backend is used.
+    fn update_literal_index(&mut self) -> Result<(), LogError> {
+        if self.options.backend != MatchBackend::LiteralIndex {
+            return Ok(());
         }
+        self.literal_index = None;
+        self.literal_index = Some(LiteralIndex::new(self)?);
         Ok(())
     }
 
-    /// Check if the given path is covered by any of the roots in this matcher.
+    /// Add the paths that match the given glob pattern, like `services/*/src`, as roots.
+    /// Paths that overlap a root, as described in [`add_root`](LogMatcher::add_root), are
+    /// skipped.  Returns the number of roots that were added.
+    pub fn add_roots_glob(&mut self, pattern: &str) -> Result<usize, LogError> {
+        let paths = glob::glob(pattern).map_err(|source| LogError::InvalidGlob {
+            pattern: pattern.to_string(),
+            source: Arc::new(source),
+        })?;
+        let mut added = 0;
+        for entry in paths {
+            let path = entry.map_err(|err| LogError::CannotAccessPath {
+                path: err.path().to_owned(),
+                source: Arc::new(err.into()),
+            })?;
+            if self.overlapping_root(&path).is_none() {
+                self.add_root(&path)?;
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    /// Skip the paths that match the given pattern when discovering sources, like
+    /// `target`, `node_modules`, or `/src/generated`.  As in a `.gitignore`, a pattern
+    /// without a slash matches the name of any file or directory and other patterns match
+    /// the path relative to a root.  Files that were already found are dropped by the next
+    /// call to [`discover_sources`](LogMatcher::discover_sources).
+    pub fn add_ignore(&mut self, pattern: &str) -> Result<(), LogError> {
+        self.ignore
+            .add(pattern)
+            .map_err(|source| LogError::InvalidGlob {
+                pattern: pattern.to_string(),
+                source: Arc::new(source),
+            })
+    }
+
+    /// Find the root that the given path is under or that is under the given path.
+    fn overlapping_root(&self, path: &Path) -> Option<&PathBuf> {
+        self.roots.keys().find(|existing_path| {
+            path.starts_with(existing_path) || existing_path.starts_with(path)
+        })
+    }
+
+    /// Check if the given path is covered by any of the roots in this matcher.  Roots
+    /// cannot be nested, so there is at most one.
     pub fn match_path(&self, path: &Path) -> Option<(&PathBuf, &SourceTree)> {
         self.roots
             .iter()
@@ -142,8 +797,30 @@ impl LogMatcher {
             .next()
     }
 
-    pub fn find_source_file_statements(&self, path: &Path) -> Vec<&StatementsInFile> {
-        self.roots
+    /// Get the log statements that were found in the given source file.  The path can be
+    /// absolute or relative to one of the roots, or just the name of the file.  If more
+    /// than one file matches the path, the statements for the first one are returned.
+    pub fn statements_for_file(&self, path: &Path) -> Option<&[SourceRef]> {
+        self.find_source_file_statements(path)
+            .first()
+            .map(|stmts| stmts.log_statements.as_slice())
+    }
+
+    /// Find the statement at the given location without looking at the text of a message,
+    /// for logs whose format has an accurate file and line number.  The path is resolved
+    /// like in [`statements_for_file`](LogMatcher::statements_for_file) and the line can be
+    /// any of the lines that a statement spans.
+    pub fn match_by_location(&self, file: &str, line: u32) -> Option<&SourceRef> {
+        let line = line as usize;
+        self.find_source_file_statements(Path::new(file))
+            .into_iter()
+            .flat_map(|stmts| stmts.log_statements.iter())
+            .find(|src_ref| (src_ref.line_no..=src_ref.end_line_no).contains(&line))
+    }
+
+    pub(crate) fn find_source_file_statements(&self, path: &Path) -> Vec<&StatementsInFile> {
+        let retval = self
+            .roots
             .values()
             .flat_map(|root| {
                 root.tree
@@ -151,7 +828,15 @@ impl LogMatcher {
                     .into_iter()
                     .filter_map(|(_actual_path, info)| root.files_with_statements.get(&info.id))
             })
-            .collect()
+            .collect::<Vec<_>>();
+        match path.file_name() {
+            // Fall back to the file name in case the directories in the path do not match
+            // the layout of the roots.
+            Some(file_name) if retval.is_empty() && path.components().count() > 1 => {
+                self.find_source_file_statements(Path::new(file_name))
+            }
+            _ => retval,
+        }
     }
 
     /// Traverse the roots looking for supported source files.
@@ -159,52 +844,89 @@ impl LogMatcher {
     pub fn discover_sources(&mut self, tracker: &ProgressTracker) -> Vec<LogError> {
         tracker.begin_step("Finding source code".to_string());
         let pguard = tracker.doing_work(self.roots.len() as u64, "paths".to_string());
-        self.roots.par_iter_mut().for_each(|(_path, coll)| {
-            coll.tree.sync();
+        let sync = |(path, coll): (&PathBuf, &mut SourceTree)| {
+            // The files in the virtual root do not exist on disk.
+            if path.as_os_str() != VIRTUAL_ROOT {
+                coll.tree.ignore.clone_from(&self.ignore);
+                coll.tree.sync();
+            }
             pguard.inc(1);
-        });
+        };
+        if self.options.deterministic {
+            self.roots
+                .iter_mut()
+                .sorted_by(|lhs, rhs| lhs.0.cmp(rhs.0))
+                .for_each(sync);
+        } else {
+            self.roots.par_iter_mut().for_each(sync);
+        }
         let mut retval: Vec<LogError> = Vec::new();
         let mut file_count: usize = 0;
-        self.roots.values().for_each(|coll| {
+        self.roots.iter().for_each(|(path, coll)| {
+            let mut root_file_count: usize = 0;
+            let error_count = retval.len();
             coll.tree.visit(|node| match &node.content {
-                SourceHierContent::File { .. } => file_count += 1,
+                SourceHierContent::File { .. } => root_file_count += 1,
                 SourceHierContent::UnsupportedFile { .. } => {}
                 SourceHierContent::Directory { .. } => {}
                 SourceHierContent::Error { ref source } => retval.push(source.clone()),
                 SourceHierContent::Unknown { .. } => {}
             });
+            // A root that could not be read has already been reported.
+            if root_file_count == 0 && retval.len() == error_count {
+                retval.push(LogError::RootHasNoSupportedFiles { root: path.clone() });
+            }
+            file_count += root_file_count;
         });
         tracker.end_step(format!("{} files found", file_count));
 
         retval
     }
 
-    /// Scan the source files looking for potential log statements.
-    pub fn extract_log_statements(&mut self, tracker: &ProgressTracker) {
+    /// Scan the source files looking for potential log statements.  Only the files that
+    /// were added or changed since the last call are parsed.  Files that could not be read
+    /// are skipped and the errors are returned.
+    #[must_use]
+    pub fn extract_log_statements(&mut self, tracker: &ProgressTracker) -> Vec<LogError> {
         tracker.begin_step("Extracting log statements".to_string());
+        let options = &self.extract_options;
+        let match_options = &self.options;
+        let mut retval: Vec<LogError> = Vec::new();
         self.roots.iter_mut().for_each(|(_path, coll)| {
-            let guard = tracker.doing_work(coll.tree.stats().files as u64, "files".to_string());
-            for event_chunk in &coll.tree.scan().chunks(10) {
+            let events = coll.tree.scan().collect::<Vec<_>>();
+            let new_files = events
+                .iter()
+                .filter(|event| matches!(event, ScanEvent::NewFile(..)))
+                .count();
+            let guard = tracker.doing_work(new_files as u64, "files".to_string());
+            for event_chunk in &events.into_iter().chunks(10) {
                 let sources = event_chunk
                     .flat_map(|event| match event {
-                        ScanEvent::NewFile(path, info) => match File::open(&path) {
-                            Ok(file) => match CodeSource::new(&path, info, file) {
+                        ScanEvent::NewFile(path, info) => {
+                            match File::open(&path)
+                                .map_err(|err| LogError::CannotReadSourceFile {
+                                    path: path.clone(),
+                                    source: err.into(),
+                                })
+                                .and_then(|file| CodeSource::new(&path, info, file))
+                            {
                                 Ok(cs) => Some(cs),
-                                Err(_) => todo!(),
-                            },
-                            Err(_) => {
-                                todo!()
+                                Err(err) => {
+                                    retval.push(err);
+                                    None
+                                }
                             }
-                        },
+                        }
                         ScanEvent::DeletedFile(_path, id) => {
                             coll.files_with_statements.remove(&id);
                             None
                         }
                     })
                     .collect::<Vec<CodeSource>>();
-                extract_logging_guarded(&sources, &guard)
+                extract_logging_guarded(&sources, options, &guard)
                     .into_iter()
-                    .for_each(|sif| {
+                    .for_each(|mut sif| {
+                        sif.apply_options(match_options);
                         coll.files_with_statements.insert(sif.id, sif);
                     });
             }
@@ -217,119 +939,963 @@ impl LogMatcher {
                 .map(|stmts| stmts.log_statements.len())
                 .sum::<usize>()
         ));
+        if let Err(err) = self.update_literal_index() {
+            retval.push(err);
+        }
+
+        retval
     }
 
-    /// Attempt to match the given log message.
-    pub fn match_log_statement<'a>(&self, log_ref: &LogRef<'a>) -> Option<LogMapping<'a>> {
-        for (_path, coll) in &self.roots {
-            let matches = if let Some(LogDetails {
-                file: Some(filename),
-                body: Some(body),
-                ..
-            }) = log_ref.details
-            {
-                // XXX this block and the else are basically the same, try to refactor
-                coll.files_with_statements
-                    .values()
-                    .filter(|stmts| stmts.path.contains(filename))
-                    .flat_map(|stmts| {
-                        let file_matches = stmts.matcher.matches(body);
-                        match file_matches.iter().next() {
-                            None => None,
-                            Some(index) => stmts.log_statements.get(index),
-                        }
-                    })
-                    .collect::<Vec<&SourceRef>>()
-            } else {
-                coll.files_with_statements
-                    .par_iter()
-                    .flat_map(|src_ref_coll| {
-                        let file_matches = src_ref_coll.1.matcher.matches(log_ref.body());
-                        match file_matches.iter().next() {
-                            None => None,
-                            Some(index) => src_ref_coll.1.log_statements.get(index),
-                        }
-                    })
-                    .collect::<Vec<&SourceRef>>()
+    /// Extract the log statements from files that only exist in memory, like editor buffers
+    /// that have not been saved, given their paths and contents.  The files are kept under
+    /// a separate root, named [`VIRTUAL_ROOT`], that is never scanned or cached, and a file
+    /// that is indexed again replaces the previous version.  Files in a language that is
+    /// not supported are skipped.  The paths have to be relative and cannot contain `..`
+    /// components, otherwise nothing is indexed and an error is returned.  An error is
+    /// also returned if the literal index cannot be rebuilt, in which case the files are
+    /// still matched with the RegexSet backend.
+    pub fn index_virtual(&mut self, files: &[(PathBuf, String)]) -> Result<(), LogError> {
+        if let Some((path, _content)) = files.iter().find(|(path, _)| !is_virtual_path(path)) {
+            return Err(LogError::InvalidVirtualPath { path: path.clone() });
+        }
+        let root_path = PathBuf::from(VIRTUAL_ROOT);
+        let coll = self
+            .roots
+            .entry(root_path.clone())
+            .or_insert_with(|| SourceTree {
+                tree: SourceHierTree::from(&root_path),
+                files_with_statements: HashMap::new(),
+            });
+        let mut sources = Vec::new();
+        for (path, content) in files {
+            let Some(language) = SourceLanguage::from_path(path) else {
+                continue;
             };
-            if let Some(src_ref) = matches
-                .iter()
-                .sorted_by(|lhs, rhs| rhs.quality.cmp(&lhs.quality))
-                .next()
-            {
-                let exception_trace = match log_ref {
-                    LogRef {
-                        details:
-                            Some(LogDetails {
-                                trace: Some(trace), ..
-                            }),
-                        ..
-                    } => trace.to_exception_trace(self),
-                    _ => Vec::new(),
-                };
-                let variables = extract_variables(log_ref, src_ref);
-                return Some(LogMapping {
-                    log_ref: log_ref.clone(),
-                    src_ref: Some((*src_ref).clone()),
-                    variables,
-                    exception_trace,
-                });
+            let (info, replaced) = coll
+                .tree
+                .insert_virtual(path, language, content.len() as u64);
+            if let Some(id) = replaced {
+                coll.files_with_statements.remove(&id);
             }
+            sources.push(CodeSource {
+                filename: path.to_string_lossy().to_string(),
+                info,
+                buffer: content.clone(),
+            });
         }
-        None
+        let guard = ProgressTracker::new().doing_work(sources.len() as u64, "files".to_string());
+        for mut sif in extract_logging_guarded(&sources, &self.extract_options, &guard) {
+            sif.apply_options(&self.options);
+            coll.files_with_statements.insert(sif.id, sif);
+        }
+        self.update_literal_index()
     }
-}
-
-#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize)]
-pub enum SourceLanguage {
-    Rust,
-    Java,
-    #[serde(rename = "C++")]
-    Cpp,
-    Python,
-}
 
-impl From<SourceLanguage> for Language {
-    fn from(value: SourceLanguage) -> Self {
-        match value {
-            SourceLanguage::Rust => tree_sitter_rust_orchard::LANGUAGE.into(),
-            SourceLanguage::Java => tree_sitter_java::LANGUAGE.into(),
-            SourceLanguage::Cpp => tree_sitter_cpp::LANGUAGE.into(),
-            SourceLanguage::Python => tree_sitter_python::LANGUAGE.into(),
-        }
+    /// Bring the log statements up-to-date with the source files on disk.  Only the files
+    /// whose modified time or size changed since the last scan are parsed again, and the
+    /// statements for files that were deleted are removed.  This is meant for long-running
+    /// processes that watch a source tree.
+    #[must_use]
+    pub fn rescan_changed(&mut self, tracker: &ProgressTracker) -> Vec<LogError> {
+        let mut retval = self.discover_sources(tracker);
+        retval.extend(self.extract_log_statements(tracker));
+        retval
     }
-}
 
-const IDENTS_RS: &[&str] = &["debug", "info", "warn"];
-const IDENTS_JAVA: &[&str] = &["logger", "log", "fine", "debug", "info", "warn", "trace"];
-const IDENTS_CPP: &[&str] = &["debug", "info", "warn", "trace"];
+    /// Attempt to match the given log message.  If more than one statement matches, the
+    /// one with the highest confidence is returned.
+    pub fn match_log_statement<'a>(&self, log_ref: &LogRef<'a>) -> Option<LogMapping<'a>> {
+        let (mapping, ambiguous) = self.best_match(log_ref);
+        self.count_match(mapping.is_some(), ambiguous);
+        mapping
+    }
 
-const IDENTS_PYTHON: &[&str] = &["debug", "info", "warn", "trace"];
+    /// Find the statement with the highest confidence that matches the given log message,
+    /// without counting it in the stats, and whether more than one statement matched.
+    fn best_match<'a>(&self, log_ref: &LogRef<'a>) -> (Option<LogMapping<'a>>, bool) {
+        let mappings = self.match_log_statement_all(log_ref);
+        let ambiguous = mappings.len() > 1;
+        (mappings.into_iter().next(), ambiguous)
+    }
 
-static RUST_PLACEHOLDER_REGEX: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r#"\{(?:([a-zA-Z_][a-zA-Z0-9_.]*)|(\d+))?\s*(?::[^}]*)?}"#).unwrap()
-});
+    /// Count a message in the stats by the final outcome of matching it.
+    fn count_match(&self, matched: bool, ambiguous: bool) {
+        let counter = if matched {
+            &self.stats.matched
+        } else {
+            &self.stats.unmatched
+        };
+        counter.fetch_add(1, AtomicOrdering::Relaxed);
+        if matched && ambiguous {
+            self.stats.ambiguous.fetch_add(1, AtomicOrdering::Relaxed);
+        }
+    }
 
-static JAVA_PLACEHOLDER_REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r#"\{[^}]*}|\\\{([^}]*)}"#).unwrap());
+    /// Get the tallies of the messages that were passed to
+    /// [`match_log_statement`](LogMatcher::match_log_statement), including the ones from
+    /// [`match_reader`](LogMatcher::match_reader) and
+    /// [`fuzzy_match`](LogMatcher::fuzzy_match), since this matcher was created or the
+    /// stats were last reset.  Each message is counted once, by whether it was matched in
+    /// the end, so a line that [`match_reader`](LogMatcher::match_reader) drops for being
+    /// below the `min_confidence` is counted as unmatched.
+    pub fn stats(&self) -> MatchStats {
+        MatchStats {
+            matched: self.stats.matched.load(AtomicOrdering::Relaxed),
+            unmatched: self.stats.unmatched.load(AtomicOrdering::Relaxed),
+            ambiguous: self.stats.ambiguous.load(AtomicOrdering::Relaxed),
+        }
+    }
 
-static CPP_PLACEHOLDER_REGEX: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r#"%[-+ #0]*\d*(?:\.\d+)?[hlLzjt]*[diuoxXfFeEgGaAcspn%]|\{(?:([a-zA-Z_][a-zA-Z0-9_.]*)|(\d+))?\s*(?::[^}]*)?}"#).unwrap()
-});
+    /// Set the tallies returned by [`stats`](LogMatcher::stats) back to zero.
+    pub fn reset_stats(&self) {
+        self.stats.matched.store(0, AtomicOrdering::Relaxed);
+        self.stats.unmatched.store(0, AtomicOrdering::Relaxed);
+        self.stats.ambiguous.store(0, AtomicOrdering::Relaxed);
+    }
 
-static PYTHON_PLACEHOLDER_REGEX: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r#"%[-+ #0]*\d*(?:\.\d+)?[hlLzjt]*[diuoxXfFeEgGaAcspn%]"#).unwrap()
-});
+    /// Find all of the statements that match the given log message, sorted by confidence
+    /// from highest to lowest.  Different modules, or different functions in the same
+    /// file, can log identical messages, so more than one statement can match.  If the
+    /// message has a line number, the statements closest to it come first, and ties are
+    /// broken by how specific the statements are.
+    pub fn match_log_statement_all<'a>(&self, log_ref: &LogRef<'a>) -> Vec<LogMapping<'a>> {
+        let unescaped = log_ref.unescaped_body();
+        if self.preprocessor.is_none() && matches!(unescaped, Cow::Borrowed(_)) {
+            return self.match_prepared(log_ref);
+        }
+        let preprocessed_body = self
+            .preprocessor
+            .as_ref()
+            .map(|preprocessor| preprocessor(&unescaped));
+        let mut details = log_ref.details.unwrap_or_default();
+        details.body = Some(preprocessed_body.as_deref().unwrap_or(&unescaped));
+        details.escaped_body = false;
+        let preprocessed = LogRef {
+            line: log_ref.line,
+            details: Some(details),
+        };
+        self.match_prepared(&preprocessed)
+            .into_iter()
+            .map(|mapping| LogMapping {
+                log_ref: *log_ref,
+                src_ref: mapping.src_ref,
+                variables: mapping.variables,
+                exception_trace: mapping.exception_trace,
+                level_mismatch: mapping.level_mismatch,
+                confidence: mapping.confidence,
+                root: mapping.root,
+                file_id: mapping.file_id,
+            })
+            .collect()
+    }
 
-static BACKTRACE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(
-        r#"(?smx)
-    (?<python>
-        # Match the initial 'Traceback' line
-        ^Traceback\s+\(most\s+recent\s+call\s+last\):\s*$\n?
+    /// Find the statements that match the longest suffix of a line that starts after some
+    /// whitespace, for when the line was not parsed by a log format and the message is
+    /// preceded by other text, like a timestamp.
+    fn suffix_candidates<'a>(&self, line: &'a str) -> Option<(LogRef<'a>, Vec<&SourceRef>)> {
+        line.match_indices(char::is_whitespace)
+            .map(|(index, ws)| index + ws.len())
+            .filter(|start| line[*start..].starts_with(|c: char| !c.is_whitespace()))
+            .find_map(|start| {
+                let log_ref = LogRefBuilder::new()
+                    .with_body(Some(&line[start..]))
+                    .build(line);
+                let matches = self.candidates(&log_ref);
+                (!matches.is_empty()).then_some((log_ref, matches))
+            })
+    }
 
-        # Match all stack frames
-        (?:
+    /// Find the statements that start with the body of a message that ends in the
+    /// truncation marker.  The returned message has the marker removed from its body.
+    fn truncated_candidates<'a>(
+        &self,
+        log_ref: &LogRef<'a>,
+    ) -> Option<(LogRef<'a>, Vec<&SourceRef>)> {
+        let marker = self.options.truncation_marker.as_deref()?;
+        let body = log_ref.body().strip_suffix(marker)?;
+        let file = log_ref.details.and_then(|details| details.file);
+        let mut roots = self.roots.iter().collect::<Vec<_>>();
+        roots.sort_by(|lhs, rhs| lhs.0.cmp(rhs.0));
+        let matches = roots
+            .into_iter()
+            .flat_map(|(_path, coll)| coll.files_with_statements.values())
+            .filter(|stmts| file.is_none_or(|file| stmts.matches_file_hint(file)))
+            .flat_map(|stmts| &stmts.log_statements)
+            .filter(|src_ref| src_ref.prefix_captures(body).is_some())
+            .unique_by(|src_ref| (&src_ref.source_path, src_ref.line_no, src_ref.column))
+            .collect::<Vec<_>>();
+        if matches.is_empty() {
+            return None;
+        }
+        let details = LogDetails {
+            body: Some(body),
+            ..log_ref.details.unwrap_or_default()
+        };
+        Some((
+            LogRef {
+                line: log_ref.line,
+                details: Some(details),
+            },
+            matches,
+        ))
+    }
+
+    /// Match a log message after it has been passed through the preprocessor.
+    fn match_prepared<'a>(&self, log_ref: &LogRef<'a>) -> Vec<LogMapping<'a>> {
+        let mut prepared = self.options.prepare(log_ref);
+        let mut matches = self.candidates(&prepared);
+        if let Some(
+            details @ LogDetails {
+                file: Some(filename),
+                ..
+            },
+        ) = prepared.details
+        {
+            // Fall back to the file name in case the directories in the hint do not match
+            // the layout of the roots.
+            let file_name = Path::new(filename)
+                .file_name()
+                .and_then(|name| name.to_str());
+            if matches.is_empty() && file_name.is_some_and(|name| name != filename) {
+                matches = self.candidates(&LogRef {
+                    line: prepared.line,
+                    details: Some(LogDetails {
+                        file: file_name,
+                        ..details
+                    }),
+                });
+            }
+        }
+        if matches.is_empty() && self.options.unanchored && prepared.details.is_none() {
+            if let Some((suffix_ref, suffix_matches)) = self.suffix_candidates(prepared.line) {
+                prepared = suffix_ref;
+                matches = suffix_matches;
+            }
+        }
+        let mut truncated = false;
+        if matches.is_empty() {
+            if let Some((truncated_ref, truncated_matches)) = self.truncated_candidates(&prepared) {
+                prepared = truncated_ref;
+                matches = truncated_matches;
+                truncated = true;
+            }
+        }
+        if matches.is_empty() {
+            return Vec::new();
+        }
+        let log_ref = &prepared;
+        let exception_trace = match log_ref {
+            LogRef {
+                details:
+                    Some(LogDetails {
+                        trace: Some(trace), ..
+                    }),
+                ..
+            } => trace.to_exception_trace(self),
+            _ => Vec::new(),
+        };
+        let log_level = log_ref
+            .details
+            .and_then(|details| details.level)
+            .and_then(normalize_level);
+        let lineno = log_ref.details.and_then(|details| details.lineno);
+        matches
+            .into_iter()
+            .map(|src_ref| {
+                let level_mismatch = self.options.check_levels
+                    && match (log_level, src_ref.level.as_deref()) {
+                        (Some(log_level), Some(src_level)) => log_level != src_level,
+                        _ => false,
+                    };
+                let (root, file_id) = self.locate(src_ref).unzip();
+                LogMapping {
+                    log_ref: *log_ref,
+                    src_ref: Some(src_ref.clone()),
+                    variables: if truncated {
+                        extract_truncated_variables(log_ref, src_ref)
+                    } else {
+                        extract_variables(log_ref, src_ref)
+                    },
+                    exception_trace: exception_trace.clone(),
+                    level_mismatch,
+                    confidence: confidence(log_ref, src_ref),
+                    root,
+                    file_id,
+                }
+            })
+            .sorted_by(|lhs, rhs| {
+                let quality = |mapping: &LogMapping| {
+                    mapping
+                        .src_ref
+                        .as_ref()
+                        .map_or(0, |src_ref| src_ref.quality)
+                };
+                fn location<'m>(mapping: &'m LogMapping) -> Option<(&'m str, usize)> {
+                    mapping
+                        .src_ref
+                        .as_ref()
+                        .map(|src_ref| (src_ref.source_path.as_str(), src_ref.line_no))
+                }
+                let distance = |mapping: &LogMapping| {
+                    mapping
+                        .src_ref
+                        .as_ref()
+                        .zip(lineno)
+                        .map_or(0, |(src_ref, lineno)| line_distance(src_ref, lineno))
+                };
+                lhs.level_mismatch
+                    .cmp(&rhs.level_mismatch)
+                    .then_with(|| distance(lhs).cmp(&distance(rhs)))
+                    .then_with(|| rhs.confidence.total_cmp(&lhs.confidence))
+                    .then_with(|| quality(rhs).cmp(&quality(lhs)))
+                    .then_with(|| {
+                        if self.options.deterministic {
+                            location(lhs).cmp(&location(rhs))
+                        } else {
+                            Ordering::Equal
+                        }
+                    })
+            })
+            .collect()
+    }
+
+    /// Find the statements that match the given log message using the configured backend.
+    /// A file can be found more than once, like a header that is under more than one root,
+    /// so only the first statement at each location is kept.
+    fn candidates(&self, log_ref: &LogRef) -> Vec<&SourceRef> {
+        let candidates = match &self.literal_index {
+            Some(literal_index) => literal_index.candidates(self, log_ref),
+            None => self.regex_set_candidates(log_ref),
+        };
+        candidates
+            .into_iter()
+            .unique_by(|src_ref| (&src_ref.source_path, src_ref.line_no, src_ref.column))
+            .collect()
+    }
+
+    /// Find the statements that match the given log message using the RegexSet for each
+    /// file in every root.
+    fn regex_set_candidates(&self, log_ref: &LogRef) -> Vec<&SourceRef> {
+        let lineno = log_ref.details.and_then(|details| details.lineno);
+        let (filename, body) = match log_ref.details {
+            Some(LogDetails {
+                file: Some(filename),
+                body: Some(body),
+                ..
+            }) => (Some(filename), body),
+            _ => (None, log_ref.body()),
+        };
+        let mut roots = self.roots.iter().collect::<Vec<_>>();
+        if self.options.deterministic {
+            roots.sort_by(|lhs, rhs| lhs.0.cmp(rhs.0));
+        }
+        let files = roots
+            .into_iter()
+            .flat_map(|(_path, coll)| coll.files_with_statements.values())
+            .filter(|stmts| filename.is_none_or(|filename| stmts.matches_file_hint(filename)));
+        if self.options.deterministic {
+            files
+                .flat_map(|stmts| stmts.matches(body, lineno))
+                .collect()
+        } else {
+            files
+                .collect::<Vec<_>>()
+                .into_par_iter()
+                .flat_map(|stmts| stmts.matches(body, lineno))
+                .collect()
+        }
+    }
+
+    /// Find the root and the ID of the file that hold the given statement, which has to be
+    /// one of the statements in this matcher and not a copy of one.  A file can be under
+    /// more than one root, so the statement itself is looked for instead of its path.
+    fn locate(&self, src_ref: &SourceRef) -> Option<(PathBuf, SourceFileID)> {
+        let path = Path::new(&src_ref.source_path);
+        self.roots.iter().find_map(|(root, coll)| {
+            let sub_path = match path.strip_prefix(root) {
+                Ok(sub_path) => sub_path,
+                Err(_) if root.as_os_str() == VIRTUAL_ROOT => path,
+                Err(_) => return None,
+            };
+            coll.tree
+                .find_file(sub_path)
+                .into_iter()
+                .find_map(|(_path, info)| {
+                    coll.files_with_statements
+                        .get(&info.id)
+                        .filter(|stmts| {
+                            stmts
+                                .log_statements
+                                .as_ptr_range()
+                                .contains(&std::ptr::from_ref(src_ref))
+                        })
+                        .map(|_stmts| (root.clone(), info.id))
+                })
+        })
+    }
+
+    /// Summarize the source files that were found and the log statements that were
+    /// extracted from them, broken down by language.
+    pub fn summary(&self) -> ScanSummary {
+        let mut retval = ScanSummary::default();
+        for coll in self.roots.values() {
+            coll.tree.visit(|node| {
+                if let SourceHierContent::File { info, .. } = &node.content {
+                    retval
+                        .languages
+                        .entry(info.language.as_str())
+                        .or_default()
+                        .files += 1;
+                }
+            });
+            for stmts in coll.files_with_statements.values() {
+                let Some(first) = stmts.log_statements.first() else {
+                    continue;
+                };
+                let summary = retval.languages.entry(first.language.as_str()).or_default();
+                summary.files_with_statements += 1;
+                summary.statements += stmts.log_statements.len();
+                retval.statements += stmts.log_statements.len();
+            }
+        }
+        retval
+    }
+
+    /// Iterate over the roots, in sorted order, along with the number of files under each
+    /// one that have log statements.
+    pub fn roots(&self) -> impl Iterator<Item = (&Path, usize)> {
+        self.roots
+            .iter()
+            .map(|(path, coll)| (path.as_path(), coll.files_with_statements.len()))
+            .sorted_by_key(|(path, _count)| *path)
+    }
+
+    /// Estimate the number of bytes of memory used by the index of log statements.  This
+    /// includes the statements, their patterns, and the compiled regexes, although the
+    /// size of a compiled regex is only approximated from the length of its pattern.
+    pub fn memory_estimate(&self) -> usize {
+        let statements = self
+            .roots
+            .values()
+            .flat_map(|coll| coll.files_with_statements.values())
+            .map(|stmts| {
+                size_of::<StatementsInFile>()
+                    + stmts.path.capacity()
+                    + stmts
+                        .log_statements
+                        .iter()
+                        .map(|src_ref| {
+                            // The RegexSet for the file is compiled from the same patterns.
+                            src_ref.memory_estimate() + src_ref.pattern.len() * REGEX_SIZE_FACTOR
+                        })
+                        .sum::<usize>()
+            })
+            .sum::<usize>();
+        let literal_index = self
+            .literal_index
+            .as_ref()
+            .map_or(0, |literal_index| literal_index.memory_usage());
+        size_of::<Self>() + statements + literal_index
+    }
+
+    /// Find the indexed statements that were not matched by any of the given log messages.
+    /// These are candidates for log statements that are never executed.  Every statement
+    /// that matches a message counts as hit, not only the best one, and the messages are
+    /// not counted in the [`stats`](LogMatcher::stats).
+    pub fn unmatched_statements(&self, log_refs: &[LogRef]) -> Vec<&SourceRef> {
+        let matched: HashSet<(String, usize, usize)> = log_refs
+            .iter()
+            .flat_map(|log_ref| self.match_log_statement_all(log_ref))
+            .filter_map(|mapping| mapping.src_ref)
+            .map(|src_ref| (src_ref.source_path, src_ref.line_no, src_ref.column))
+            .collect();
+        self.all_statements()
+            .filter(|src_ref| {
+                !matched.contains(&(src_ref.source_path.clone(), src_ref.line_no, src_ref.column))
+            })
+            .collect()
+    }
+
+    /// Get the indexed statements in files under the given path prefix.
+    pub fn statements_under(&self, prefix: &Path) -> Vec<&SourceRef> {
+        self.all_statements()
+            .filter(|src_ref| Path::new(&src_ref.source_path).starts_with(prefix))
+            .collect()
+    }
+
+    /// Find the statement that is the most similar to a log message that does not match any
+    /// statement, for example, because the statement was edited after the log was written.
+    /// A statement is only suggested if at least half of the text is the same.  The
+    /// statements are first ranked by the edit distance of their literal text, like for
+    /// [`fuzzy_match`](LogMatcher::fuzzy_match), and only the closest few are compared in
+    /// full.
+    pub fn suggest(&self, log_ref: &LogRef) -> Option<MatchSuggestion> {
+        let body = self.options.prepare(log_ref).body();
+        let body_len = body.chars().count();
+        let mut candidates = self
+            .all_statements()
+            .filter_map(|src_ref| {
+                let segments = src_ref.literal_segments();
+                let literal_len = segments.iter().map(|s| s.chars().count()).sum::<usize>();
+                // Less than half of the text can be the same when the literal text is more
+                // than twice as long as the message.
+                if literal_len > body_len * 2 {
+                    return None;
+                }
+                let distance = suggest::fuzzy_distance(&segments, body);
+                (distance * 2 <= literal_len.max(body_len)).then_some((distance, src_ref))
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|(distance, _src_ref)| *distance);
+        candidates
+            .into_iter()
+            .take(SUGGESTION_CANDIDATES)
+            .map(|(_distance, src_ref)| MatchSuggestion::new(src_ref, body))
+            .filter(|suggestion| suggestion.similarity >= 0.5)
+            .max_by(|lhs, rhs| lhs.similarity.total_cmp(&rhs.similarity))
+    }
+
+    /// Match a log message, falling back to the statement whose literal text is closest to
+    /// the message if no statement matches exactly.  The distance is the number of single
+    /// character edits needed to turn the literal text of a statement into the message,
+    /// with the placeholders matching any text, and it has to be no more than
+    /// `max_distance`.  The values of the variables are not extracted for a fuzzy match
+    /// and the confidence is reduced by the fraction of the message that had to be edited.
+    pub fn fuzzy_match<'a>(
+        &self,
+        log_ref: &LogRef<'a>,
+        max_distance: usize,
+    ) -> Option<LogMapping<'a>> {
+        let (mapping, ambiguous) = self.best_match(log_ref);
+        if mapping.is_some() {
+            self.count_match(true, ambiguous);
+            return mapping;
+        }
+        let mapping = self.closest_match(log_ref, max_distance);
+        self.count_match(mapping.is_some(), false);
+        mapping
+    }
+
+    /// Find the statement whose literal text is closest to the given log message for
+    /// [`fuzzy_match`](LogMatcher::fuzzy_match).
+    fn closest_match<'a>(
+        &self,
+        log_ref: &LogRef<'a>,
+        max_distance: usize,
+    ) -> Option<LogMapping<'a>> {
+        let body = self.options.prepare(log_ref).body();
+        let body_len = body.chars().count();
+        let (distance, src_ref) = self
+            .all_statements()
+            .filter_map(|src_ref| {
+                let segments = src_ref.literal_segments();
+                let literal_len = segments.iter().map(|s| s.chars().count()).sum::<usize>();
+                // The literal text has to be deleted if it is longer than the message.
+                if literal_len > body_len + max_distance {
+                    return None;
+                }
+                let distance = suggest::fuzzy_distance(&segments, body);
+                (distance <= max_distance).then_some((distance, src_ref))
+            })
+            .min_by_key(|(distance, _src_ref)| *distance)?;
+        let confidence = if body_len == 0 {
+            0.0
+        } else {
+            1.0 - (distance.min(body_len) as f32 / body_len as f32)
+        };
+        let (root, file_id) = self.locate(src_ref).unzip();
+        Some(LogMapping {
+            log_ref: *log_ref,
+            src_ref: Some(src_ref.clone()),
+            variables: Vec::new(),
+            exception_trace: Vec::new(),
+            level_mismatch: false,
+            confidence: (confidence * 100.0).round() / 100.0,
+            root,
+            file_id,
+        })
+    }
+
+    /// Find the groups of statements that cannot be told apart by the messages they log,
+    /// because their patterns are the same or one of them matches the messages of another,
+    /// like `"user {} logged in"` and `"user admin logged in"`.  Statements that are not
+    /// ambiguous are left out.  Every pair of patterns is checked, so this is meant for
+    /// auditing the messages and not for every scan.
+    pub fn ambiguity_report(&self) -> Vec<AmbiguityGroup> {
+        let mut patterns: Vec<&SourceRef> = Vec::new();
+        let mut pattern_index: HashMap<&str, usize> = HashMap::new();
+        let statements = self.all_statements().collect::<Vec<_>>();
+        for src_ref in &statements {
+            pattern_index
+                .entry(src_ref.pattern.as_str())
+                .or_insert_with(|| {
+                    patterns.push(src_ref);
+                    patterns.len() - 1
+                });
+        }
+
+        fn find_group(groups: &mut [usize], mut index: usize) -> usize {
+            while groups[index] != index {
+                groups[index] = groups[groups[index]];
+                index = groups[index];
+            }
+            index
+        }
+
+        let examples = patterns
+            .iter()
+            .map(|src_ref| src_ref.template("0"))
+            .collect::<Vec<_>>();
+        let mut groups = (0..patterns.len()).collect::<Vec<_>>();
+        for lhs in 0..patterns.len() {
+            for rhs in lhs + 1..patterns.len() {
+                if patterns[lhs].is_match(&examples[rhs]) || patterns[rhs].is_match(&examples[lhs])
+                {
+                    let lhs_group = find_group(&mut groups, lhs);
+                    let rhs_group = find_group(&mut groups, rhs);
+                    let (first, second) = (lhs_group.min(rhs_group), lhs_group.max(rhs_group));
+                    groups[second] = first;
+                }
+            }
+        }
+
+        let mut retval: BTreeMap<usize, AmbiguityGroup> = BTreeMap::new();
+        for src_ref in statements {
+            let group = find_group(&mut groups, pattern_index[src_ref.pattern.as_str()]);
+            retval
+                .entry(group)
+                .or_default()
+                .statements
+                .push(src_ref.clone());
+        }
+        retval
+            .into_values()
+            .filter(|group| group.statements.len() > 1)
+            .collect()
+    }
+
+    /// Iterate over all the indexed statements, ordered by their location in the source.
+    fn all_statements(&self) -> impl Iterator<Item = &SourceRef> {
+        self.roots
+            .values()
+            .flat_map(|coll| coll.files_with_statements.values())
+            .flat_map(|stmts| stmts.log_statements.iter())
+            .sorted_by(|lhs, rhs| {
+                (&lhs.source_path, lhs.line_no, lhs.column).cmp(&(
+                    &rhs.source_path,
+                    rhs.line_no,
+                    rhs.column,
+                ))
+            })
+    }
+
+    /// Attempt to match each of the given log messages.  Messages that do not match any
+    /// statement are returned with an empty `src_ref`.
+    pub fn match_log_statements<'a>(&self, log_refs: &[LogRef<'a>]) -> Vec<LogMapping<'a>> {
+        log_refs
+            .iter()
+            .map(|log_ref| {
+                self.match_log_statement(log_ref)
+                    .unwrap_or_else(|| LogMapping {
+                        log_ref: *log_ref,
+                        src_ref: None,
+                        variables: vec![],
+                        exception_trace: vec![],
+                        level_mismatch: false,
+                        confidence: 0.0,
+                        root: None,
+                        file_id: None,
+                    })
+            })
+            .collect()
+    }
+
+    /// Match each line read from the given reader, parsing it with the first of the given
+    /// formats that matches.  Only one line is held in memory at a time, so this can be used
+    /// on huge or endless logs.  The mappings own the text of the lines since the lines are
+    /// not kept around.  Bytes that are not valid UTF-8 are replaced with `U+FFFD`.  An I/O
+    /// error is returned as the last item of the iterator.
+    pub fn match_reader<'m, R: BufRead + 'm>(
+        &'m self,
+        mut reader: R,
+        log_formats: &'m [LogFormat],
+    ) -> impl Iterator<Item = io::Result<OwnedLogMapping>> + 'm {
+        let mut buffer = Vec::new();
+        let mut failed = false;
+        std::iter::from_fn(move || {
+            if failed {
+                return None;
+            }
+            buffer.clear();
+            match reader.read_until(b'\n', &mut buffer) {
+                Ok(0) => None,
+                Ok(_) => {
+                    if buffer.last() == Some(&b'\n') {
+                        buffer.pop();
+                        if buffer.last() == Some(&b'\r') {
+                            buffer.pop();
+                        }
+                    }
+                    let line = String::from_utf8_lossy(&buffer).into_owned();
+                    Some(Ok(self.match_line(line, log_formats)))
+                }
+                Err(err) => {
+                    failed = true;
+                    Some(Err(err))
+                }
+            }
+        })
+    }
+
+    /// Match a single line read by [`match_reader`](LogMatcher::match_reader).
+    fn match_line(&self, line: String, log_formats: &[LogFormat]) -> OwnedLogMapping {
+        let stripped = if self.options.strip_ansi {
+            strip_ansi(&line)
+        } else {
+            Cow::Borrowed(line.as_str())
+        };
+        let log_ref = LogRef::with_formats(&stripped, log_formats);
+        let (mapping, ambiguous) = self.best_match(&log_ref);
+        let mapping = mapping.filter(|mapping| mapping.confidence >= self.options.min_confidence);
+        self.count_match(mapping.is_some(), ambiguous);
+        let mut mapping: OwnedLogMapping = mapping
+            .unwrap_or_else(|| LogMapping {
+                log_ref,
+                src_ref: None,
+                variables: vec![],
+                exception_trace: vec![],
+                level_mismatch: false,
+                confidence: 0.0,
+                root: None,
+                file_id: None,
+            })
+            .into();
+        if let Cow::Owned(_) = stripped {
+            mapping.line = line;
+        }
+        mapping
+    }
+
+    /// Like [`match_reader`](LogMatcher::match_reader), but for a gzip-compressed log, like
+    /// an archived `app.log.gz`.  Logs made of several gzip members, like ones that were
+    /// appended to, are read in full.  A corrupt or truncated archive ends the iterator with
+    /// the error from the decoder.
+    #[cfg(feature = "gzip")]
+    pub fn match_gz_reader<'m, R: io::Read + 'm>(
+        &'m self,
+        reader: R,
+        log_formats: &'m [LogFormat],
+    ) -> impl Iterator<Item = io::Result<OwnedLogMapping>> + 'm {
+        self.match_reader(
+            io::BufReader::new(flate2::read::MultiGzDecoder::new(reader)),
+            log_formats,
+        )
+    }
+
+    /// Check if the value extracted for the variable at the given index of a mapping is
+    /// equal to an expected value, such as one emitted by a previous run.
+    pub fn value_matches(&self, mapping: &LogMapping, index: usize, expected: &str) -> bool {
+        let Some(pair) = mapping.variables.get(index) else {
+            return false;
+        };
+        if pair.value == expected {
+            return true;
+        }
+        if !self.options.precision_tolerant {
+            return false;
+        }
+        let precision = mapping
+            .src_ref
+            .as_ref()
+            .and_then(|src_ref| src_ref.precisions.get(index).copied().flatten());
+        precision.is_some_and(|precision| numbers_match(&pair.value, expected, precision))
+    }
+
+    /// Freeze this matcher into a handle that can be cheaply cloned and shared with other
+    /// threads for matching.  The statements can no longer be changed.
+    pub fn into_shared(self) -> SharedMatcher {
+        SharedMatcher(Arc::new(self))
+    }
+}
+
+/// A read-only [`LogMatcher`] that can be cloned to match log messages from several
+/// threads at once, as returned by [`LogMatcher::into_shared`].
+#[derive(Clone)]
+pub struct SharedMatcher(Arc<LogMatcher>);
+
+impl Deref for SharedMatcher {
+    type Target = LogMatcher;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Compare two numbers at the precision of the least precise one, limited to the given
+/// number of decimal places.
+fn numbers_match(lhs: &str, rhs: &str, precision: usize) -> bool {
+    fn decimals(number: &str) -> usize {
+        number.split_once('.').map_or(0, |(_, frac)| frac.len())
+    }
+
+    let (Ok(lhs_value), Ok(rhs_value)) = (lhs.trim().parse::<f64>(), rhs.trim().parse::<f64>())
+    else {
+        return false;
+    };
+    let places = decimals(lhs.trim())
+        .min(decimals(rhs.trim()))
+        .min(precision);
+    let tolerance = 0.5 * 10f64.powi(-(places as i32));
+    (lhs_value - rhs_value).abs() <= tolerance + f64::EPSILON * lhs_value.abs().max(1.0)
+}
+
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone, Serialize, Deserialize)]
+pub enum SourceLanguage {
+    Rust,
+    Java,
+    #[serde(rename = "C++")]
+    Cpp,
+    Python,
+    Go,
+    #[serde(rename = "C#")]
+    CSharp,
+    Swift,
+    JavaScript,
+    TypeScript,
+    Ruby,
+    Kotlin,
+    Scala,
+}
+
+impl From<SourceLanguage> for Language {
+    fn from(value: SourceLanguage) -> Self {
+        match value {
+            SourceLanguage::Rust => tree_sitter_rust_orchard::LANGUAGE.into(),
+            SourceLanguage::Java => tree_sitter_java::LANGUAGE.into(),
+            SourceLanguage::Cpp => tree_sitter_cpp::LANGUAGE.into(),
+            SourceLanguage::Python => tree_sitter_python::LANGUAGE.into(),
+            SourceLanguage::Go => tree_sitter_go::LANGUAGE.into(),
+            SourceLanguage::CSharp => tree_sitter_c_sharp::LANGUAGE.into(),
+            SourceLanguage::Swift => tree_sitter_swift::LANGUAGE.into(),
+            SourceLanguage::JavaScript => tree_sitter_javascript::LANGUAGE.into(),
+            SourceLanguage::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            SourceLanguage::Ruby => tree_sitter_ruby::LANGUAGE.into(),
+            SourceLanguage::Kotlin => tree_sitter_kotlin_ng::LANGUAGE.into(),
+            SourceLanguage::Scala => tree_sitter_scala::LANGUAGE.into(),
+        }
+    }
+}
+
+const IDENTS_RS: &[&str] = &["debug", "info", "warn"];
+const IDENTS_JAVA: &[&str] = &["logger", "log", "fine", "debug", "info", "warn", "trace"];
+const IDENTS_CPP: &[&str] = &["debug", "info", "warn", "trace"];
+
+const CPP_LOG_FUNCTIONS: &[&str] = &[
+    "printf", "fprintf", "syslog", "log", "trace", "debug", "info", "warn", "warning", "error",
+    "critical", "fatal", "LOG",
+];
+
+const IDENTS_PYTHON: &[&str] = &["debug", "info", "warning", "error", "critical", "exception"];
+
+const IDENTS_GO: &[&str] = &[
+    "log", "slog", "logrus", "debug", "info", "warn", "error", "printf", "fatalf",
+];
+
+const IDENTS_CSHARP: &[&str] = &[
+    "logger",
+    "_logger",
+    "log",
+    "logtrace",
+    "logdebug",
+    "loginformation",
+    "logwarning",
+    "logerror",
+    "logcritical",
+    "verbose",
+    "debug",
+    "information",
+    "warning",
+    "error",
+    "fatal",
+];
+
+const IDENTS_SWIFT: &[&str] = &[
+    "logger", "log", "trace", "debug", "info", "notice", "warning", "error", "critical", "fault",
+];
+
+const IDENTS_JS: &[&str] = &[
+    "console", "logger", "log", "trace", "debug", "info", "warn", "error", "fatal",
+];
+
+const IDENTS_RUBY: &[&str] = &[
+    "rails", "logger", "log", "debug", "info", "warn", "error", "fatal",
+];
+
+const IDENTS_KOTLIN: &[&str] = &["log", "timber", "tag", "v", "d", "i", "w", "e", "wtf"];
+
+const IDENTS_SCALA: &[&str] = &["logger", "log", "trace", "debug", "info", "warn", "error"];
+
+static RUST_PLACEHOLDER_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"\{\{|}}|\{(?:([a-zA-Z_][a-zA-Z0-9_.]*)|(\d+))?\s*(?::[^}]*)?}"#).unwrap()
+});
+
+static JAVA_PLACEHOLDER_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\\\{([^}]*)}|\{(\d+)?[^}]*}"#).unwrap());
+
+static CPP_PLACEHOLDER_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"%[-+ #0]*\d*(?:\.\d+)?[hlLzjt]*[diuoxXfFeEgGaAcspn%]|\{\{|}}|\{(?:([a-zA-Z_][a-zA-Z0-9_.]*)|(\d+))?\s*(?::[^}]*)?}"#).unwrap()
+});
+
+static PYTHON_PLACEHOLDER_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"%[-+ #0]*\d*(?:\.\d+)?[hlLzjt]*[diuoxXfFeEgGaAcspn%]"#).unwrap()
+});
+
+static GO_PLACEHOLDER_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"%%|%[-+ #0]*\d*(?:\.\d+)?[vTtbcdoOqxXUeEfFgGsp]"#).unwrap());
+
+/// Regex for the holes in a .NET message template, like `{UserId}`, `{@User}`, or `{0,8:N2}`.
+static CSHARP_PLACEHOLDER_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"\{\{|}}|\{[@$]?(?:([a-zA-Z_][a-zA-Z0-9_]*)|(\d+))(?:,-?\d+)?(?::[^}]*)?}"#)
+        .unwrap()
+});
+
+/// Swift string interpolations, like `\(count)`, are replaced by `\()` when the format
+/// string is extracted.
+static SWIFT_PLACEHOLDER_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\\\(\)"#).unwrap());
+
+/// The substitutions of `console.log` and pino, like `%s`, along with the expressions of a
+/// template literal, like `${id}`, which are replaced by `${}` when the format string is
+/// extracted.
+static JS_PLACEHOLDER_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\$\{}|%[sdifjoOc%]"#).unwrap());
+
+/// The interpolations in a Ruby string, like `#{id}`, which are replaced by `#{}` when the
+/// format string is extracted.
+static RUBY_PLACEHOLDER_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"#\{}"#).unwrap());
+
+/// The `String.format` conversions used by Android's `Log` and Timber, like `%s`, along
+/// with the string templates, like `$id` or `${user.id}`, which are replaced by `${}` when
+/// the format string is extracted.
+static KOTLIN_PLACEHOLDER_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"\$\{}|%%|%(?:\d+\$)?[-#+ 0,(]*\d*(?:\.\d+)?[bBhHsScCdoxXeEfgGaA]"#).unwrap()
+});
+
+/// The SLF4J placeholders used by scala-logging, like `{}`, along with the interpolations
+/// in an `s"..."` string, like `$id` or `${user.id}`, which are replaced by `${}` when the
+/// format string is extracted.
+static SCALA_PLACEHOLDER_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\$\{}|\{}"#).unwrap());
+
+static BACKTRACE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"(?smx)
+    (?<python>
+        # Match the initial 'Traceback' line
+        ^Traceback\s+\(most\s+recent\s+call\s+last\):\s*$\n?
+
+        # Match all stack frames
+        (?:
             # File line: '  File "path", line N, in function'
             ^\s{2}File\s+"[^"]*",\s+line\s+\d+,\s+in\s+\S+\s*$\n?
 
@@ -387,6 +1953,14 @@ impl SourceLanguage {
             SourceLanguage::Java => "Java",
             SourceLanguage::Cpp => "C++",
             SourceLanguage::Python => "Python",
+            SourceLanguage::Go => "Go",
+            SourceLanguage::CSharp => "C#",
+            SourceLanguage::Swift => "Swift",
+            SourceLanguage::JavaScript => "JavaScript",
+            SourceLanguage::TypeScript => "TypeScript",
+            SourceLanguage::Ruby => "Ruby",
+            SourceLanguage::Kotlin => "Kotlin",
+            SourceLanguage::Scala => "Scala",
         }
     }
 
@@ -395,7 +1969,15 @@ impl SourceLanguage {
             Some("rs") => Some(Self::Rust),
             Some("java") => Some(Self::Java),
             Some("h" | "hh" | "hpp" | "hxx" | "tpp" | "cc" | "cpp" | "cxx") => Some(Self::Cpp),
-            Some("py") => Some(Self::Python),
+            Some("py" | "pyi") => Some(Self::Python),
+            Some("go") => Some(Self::Go),
+            Some("cs") => Some(Self::CSharp),
+            Some("swift") => Some(Self::Swift),
+            Some("js" | "jsx" | "mjs" | "cjs") => Some(Self::JavaScript),
+            Some("ts" | "mts" | "cts") => Some(Self::TypeScript),
+            Some("rb") => Some(Self::Ruby),
+            Some("kt" | "kts") => Some(Self::Kotlin),
+            Some("scala") => Some(Self::Scala),
             None | Some(_) => None,
         }
     }
@@ -408,21 +1990,30 @@ impl SourceLanguage {
         }
     }
 
-    fn get_query(&self) -> &str {
+    fn get_query(&self, options: &ExtractOptions) -> Cow<'static, str> {
+        if let Some(query) = options.queries.get(self) {
+            return query.clone().into();
+        }
         match self {
             SourceLanguage::Rust => {
                 // XXX: assumes it's a debug macro
                 r#"
                     (macro_invocation macro: (_) @macro-name
                         (token_tree .
-                            (string_literal) @log
+                            [(string_literal) (raw_string_literal)] @log
                         )
                         (#not-any-of? @macro-name "format" "vec")
                     )
+                    (macro_invocation macro: (_) @macro-name
+                        (token_tree
+                            [(string_literal) (raw_string_literal)] @log
+                        )
+                        (#match? @macro-name "^(tracing::)?(trace|debug|info|warn|error|event)$")
+                    )
                 "#
+                .into()
             }
-            SourceLanguage::Java => {
-                r#"
+            SourceLanguage::Java => r#"
                     (method_invocation
                         object: (identifier) @object-name
                         name: (identifier) @method-name
@@ -435,36 +2026,160 @@ impl SourceLanguage {
                         (#match? @method-name "fine|debug|info|warn|trace|error")
                     )
                 "#
-            }
+            .into(),
             SourceLanguage::Cpp => {
-                r#"
+                let names = options
+                    .cpp_log_functions
+                    .iter()
+                    .map(|name| regex::escape(name).replace('\\', "\\\\"))
+                    .join("|");
+                format!(
+                    r#"
                     (
-                        (compound_statement
-                            (expression_statement
-                                (call_expression
-                                    function: (_) @fname
-                                    arguments: (argument_list (string_literal) @arguments)
-                                )
-                            )
+                        (call_expression
+                            function: (_) @fname
+                            arguments: (argument_list
+                                [(string_literal) (concatenated_string)] @arguments)
                         )
                         (#not-match? @fname "snprintf|sprintf")
+                        (#match? @fname "(^|::|\\.|->)({names})$")
                     )
                 "#
+                )
+                .into()
             }
-            SourceLanguage::Python => {
-                r#"
+            SourceLanguage::Python => r#"
                 (
                     (expression_statement
                       (call
-                        function: (_) @func
+                        function: [(identifier) (attribute)] @func
                         arguments: (argument_list .
                           (string) @args
                         )
                       )
                     )
+                    (#match? @func "(^|\\.)(debug|info|warning|warn|error|critical|exception|fatal)$")
                 )
                 "#
-            }
+            .into(),
+            // The standard `log` and `slog` packages, logrus, and logger values, including
+            // the ones returned by a chain of `With...` calls, like
+            // `logrus.WithFields(...).Info("...")`.  Other calls with the same names, like
+            // `fmt.Errorf` or `t.Fatalf` in a test, are not log statements.
+            SourceLanguage::Go => r#"
+                (call_expression
+                    function: (selector_expression
+                        operand: (_) @logger
+                        field: (field_identifier) @method)
+                    arguments: (argument_list .
+                        [(interpreted_string_literal) (raw_string_literal)] @msg)
+                    (#match? @logger "^(logrus|([a-zA-Z_][a-zA-Z0-9_]*\\.)*[a-zA-Z_]*[lL]og(ger)?)(\\.With[a-zA-Z]*\\((?s:.*)\\))*$")
+                    (#match? @method "^(Debug|Info|Warn|Warning|Error|Fatal|Panic|Print|Trace)(f|ln)?$")
+                )
+                "#
+            .into(),
+            // The message can come after an exception, like `LogError(ex, "...")`.
+            SourceLanguage::CSharp => r#"
+                (invocation_expression
+                    function: (member_access_expression
+                        expression: (_) @logger
+                        name: (identifier) @method)
+                    arguments: [
+                        (argument_list . (argument (string_literal) @msg))
+                        (argument_list . (argument (identifier)) . (argument (string_literal) @msg))
+                    ]
+                    (#match? @method "^(Log(Trace|Debug|Information|Warning|Error|Critical)|Verbose|Debug|Information|Warning|Error|Fatal)$")
+                )
+                "#
+            .into(),
+            // The methods of an `os.Logger`, like `logger.info("\(count) items")`.
+            SourceLanguage::Swift => r#"
+                (call_expression
+                    (navigation_expression
+                        target: (_) @logger
+                        suffix: (navigation_suffix suffix: (simple_identifier) @method))
+                    (call_suffix
+                        (value_arguments . (value_argument value: (line_string_literal) @msg)))
+                    (#match? @method "^(trace|debug|info|notice|warning|error|critical|fault)$")
+                )
+                "#
+            .into(),
+            // The console and loggers like pino, which take an optional object of fields
+            // before the message, like `logger.info({ user }, "logged in")`.
+            SourceLanguage::JavaScript | SourceLanguage::TypeScript => r#"
+                (call_expression
+                    function: (member_expression
+                        object: (_) @logger
+                        property: (property_identifier) @method)
+                    arguments: [
+                        (arguments . [(string) (template_string)] @msg)
+                        (arguments . (object) . [(string) (template_string)] @msg)
+                    ]
+                    (#match? @logger "^(console|(this\\.)?(logger|log))$")
+                    (#match? @method "^(log|trace|debug|info|warn|error|fatal)$")
+                )
+                "#
+            .into(),
+            // The stdlib `Logger` and the Rails logger, with or without parentheses around
+            // the arguments, like `logger.info "user #{id} logged in"`.
+            SourceLanguage::Ruby => r#"
+                (call
+                    receiver: (_) @logger
+                    method: (identifier) @method
+                    arguments: (argument_list . (string) @msg)
+                    (#match? @logger "^((Rails\\.)?logger|@logger|log)$")
+                    (#match? @method "^(debug|info|warn|error|fatal)$")
+                )
+                "#
+            .into(),
+            // Android's `Log`, where the tag before the message is skipped, like
+            // `Log.d(TAG, "connected to %s", host)`, and Timber, which has no tag.
+            SourceLanguage::Kotlin => r#"
+                (call_expression
+                    (navigation_expression
+                        (identifier) @logger
+                        (identifier) @method)
+                    (value_arguments . (value_argument) .
+                        (value_argument [(string_literal) (multiline_string_literal)] @msg))
+                    (#eq? @logger "Log")
+                    (#match? @method "^[vdiwe]$")
+                )
+                (call_expression
+                    (navigation_expression
+                        (identifier) @logger
+                        (identifier) @method)
+                    (value_arguments .
+                        (value_argument [(string_literal) (multiline_string_literal)] @msg))
+                    (#eq? @logger "Timber")
+                    (#match? @method "^([vdiwe]|wtf)$")
+                )
+                "#
+            .into(),
+            // SLF4J style loggers, like scala-logging, with a plain or an `s"..."`
+            // interpolated message, like `logger.info(s"processed $count rows")`.
+            SourceLanguage::Scala => r#"
+                (call_expression
+                    function: (field_expression
+                        value: (_) @logger
+                        field: (identifier) @method)
+                    arguments: (arguments . (string) @msg)
+                    (#match? @logger "^(this\\.)?(logger|log|LOGGER|LOG)$")
+                    (#match? @method "^(trace|debug|info|warn|error)$")
+                )
+                (call_expression
+                    function: (field_expression
+                        value: (_) @logger
+                        field: (identifier) @method)
+                    arguments: (arguments .
+                        (interpolated_string_expression
+                            interpolator: (identifier) @interpolator
+                            (interpolated_string) @msg))
+                    (#match? @logger "^(this\\.)?(logger|log|LOGGER|LOG)$")
+                    (#match? @method "^(trace|debug|info|warn|error)$")
+                    (#eq? @interpolator "s")
+                )
+                "#
+            .into(),
         }
     }
 
@@ -474,6 +2189,13 @@ impl SourceLanguage {
             SourceLanguage::Java => IDENTS_JAVA,
             SourceLanguage::Cpp => IDENTS_CPP,
             SourceLanguage::Python => IDENTS_PYTHON,
+            SourceLanguage::Go => IDENTS_GO,
+            SourceLanguage::CSharp => IDENTS_CSHARP,
+            SourceLanguage::Swift => IDENTS_SWIFT,
+            SourceLanguage::JavaScript | SourceLanguage::TypeScript => IDENTS_JS,
+            SourceLanguage::Ruby => IDENTS_RUBY,
+            SourceLanguage::Kotlin => IDENTS_KOTLIN,
+            SourceLanguage::Scala => IDENTS_SCALA,
         }
     }
 
@@ -483,6 +2205,13 @@ impl SourceLanguage {
             SourceLanguage::Java => JAVA_PLACEHOLDER_REGEX.deref(),
             SourceLanguage::Cpp => CPP_PLACEHOLDER_REGEX.deref(),
             SourceLanguage::Python => PYTHON_PLACEHOLDER_REGEX.deref(),
+            SourceLanguage::Go => GO_PLACEHOLDER_REGEX.deref(),
+            SourceLanguage::CSharp => CSHARP_PLACEHOLDER_REGEX.deref(),
+            SourceLanguage::Swift => SWIFT_PLACEHOLDER_REGEX.deref(),
+            SourceLanguage::JavaScript | SourceLanguage::TypeScript => JS_PLACEHOLDER_REGEX.deref(),
+            SourceLanguage::Ruby => RUBY_PLACEHOLDER_REGEX.deref(),
+            SourceLanguage::Kotlin => KOTLIN_PLACEHOLDER_REGEX.deref(),
+            SourceLanguage::Scala => SCALA_PLACEHOLDER_REGEX.deref(),
         }
     }
 
@@ -490,12 +2219,20 @@ impl SourceLanguage {
         for (index, cap) in caps.iter().skip(1).enumerate() {
             if let Some(cap) = cap {
                 return match (self, index) {
-                    (SourceLanguage::Rust | SourceLanguage::Java | SourceLanguage::Cpp, 0) => {
-                        FormatArgument::Named(cap.as_str().to_string())
-                    }
-                    (SourceLanguage::Rust | SourceLanguage::Cpp, 1) => {
-                        FormatArgument::Positional(cap.as_str().parse().unwrap())
-                    }
+                    (
+                        SourceLanguage::Rust
+                        | SourceLanguage::Java
+                        | SourceLanguage::Cpp
+                        | SourceLanguage::CSharp,
+                        0,
+                    ) => FormatArgument::Named(cap.as_str().to_string()),
+                    (
+                        SourceLanguage::Rust
+                        | SourceLanguage::Java
+                        | SourceLanguage::Cpp
+                        | SourceLanguage::CSharp,
+                        1,
+                    ) => FormatArgument::Positional(cap.as_str().parse().unwrap()),
                     _ => unreachable!(),
                 };
             }
@@ -504,10 +2241,71 @@ impl SourceLanguage {
     }
 }
 
+/// The number of source files and log statements found for a language.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct LanguageSummary {
+    pub files: usize,
+    #[serde(rename(serialize = "filesWithStatements"))]
+    pub files_with_statements: usize,
+    pub statements: usize,
+}
+
+/// A summary of the source files that were scanned by a LogMatcher.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ScanSummary {
+    /// The summary for each language that was found, keyed by the name of the language.
+    pub languages: BTreeMap<&'static str, LanguageSummary>,
+    /// The total number of log statements.
+    pub statements: usize,
+}
+
+/// Tallies of the log messages that were matched by a [`LogMatcher`].
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct MatchStats {
+    /// The number of messages that matched at least one statement.
+    pub matched: usize,
+    /// The number of messages that did not match any statement.
+    pub unmatched: usize,
+    /// The number of the matched messages that matched more than one statement.
+    pub ambiguous: usize,
+}
+
+/// Log statements that could have logged the same message, as found by
+/// [`LogMatcher::ambiguity_report`].
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct AmbiguityGroup {
+    /// The statements, ordered by their location in the source.
+    pub statements: Vec<SourceRef>,
+}
+
+/// The variables from [`extract_variables_checked`].
+#[derive(PartialEq, Clone, Debug, Default, Serialize)]
+pub struct ExtractedVariables {
+    pub variables: Vec<VariablePair>,
+    /// The expressions for the placeholders that did not have a value in the message.
+    pub missing: Vec<String>,
+}
+
 #[derive(PartialEq, Clone, Debug, Serialize)]
 pub struct VariablePair {
     pub expr: String,
     pub value: String,
+    /// True if the log message was cut off while this value was being written.
+    #[serde(skip_serializing_if = "is_false")]
+    pub truncated: bool,
+    /// The start and end byte offsets of the placeholder for this value within the
+    /// `text` of the matched statement, if known.
+    #[serde(rename(serialize = "placeholderSpan"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub placeholder_span: Option<(usize, usize)>,
+    /// The type of the value, as hinted by the format specifier of the placeholder.
+    #[serde(rename(serialize = "inferredType"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inferred_type: Option<VarType>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
 }
 
 #[derive(Serialize)]
@@ -520,6 +2318,103 @@ pub struct LogMapping<'a> {
     #[serde(rename(serialize = "exceptionTrace"))]
     pub exception_trace: Vec<CallSite>,
     pub variables: Vec<VariablePair>,
+    /// True if the level of the log message disagrees with the level of the statement.
+    /// This is only checked when `MatchOptions::check_levels` is enabled.
+    #[serde(rename(serialize = "levelMismatch"))]
+    #[serde(skip_serializing_if = "is_false")]
+    pub level_mismatch: bool,
+    /// How confident we are that the message was produced by the statement, from 0.0 to
+    /// 1.0.  See [`confidence`] for how it is computed.
+    pub confidence: f32,
+    /// The root that the statement was found under, as it was passed to
+    /// [`LogMatcher::add_root`], or [`VIRTUAL_ROOT`] for a file from
+    /// [`LogMatcher::index_virtual`].  This is `None` if there is no statement or it was
+    /// not matched by a [`LogMatcher`].
+    #[serde(skip)]
+    pub root: Option<PathBuf>,
+    /// The ID of the file with the statement in its root.
+    #[serde(skip)]
+    pub file_id: Option<SourceFileID>,
+}
+
+/// The version of the serialized form of a [`LogMapping`].  This should be bumped whenever
+/// the shape of the mapping changes so that consumers can detect the change.
+///
+/// Version 2 added `confidence` and `levelMismatch` to the mapping, `level` to the log
+/// reference, `startByte` and `endByte` to the statement, and `placeholderSpan` and
+/// `inferredType` to the variables.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// A [`LogMapping`] tagged with the [`SCHEMA_VERSION`] for serializing as a JSON line.
+#[derive(Serialize)]
+pub struct VersionedLogMapping<'m, 'a> {
+    #[serde(rename(serialize = "schemaVersion"))]
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub mapping: &'m LogMapping<'a>,
+}
+
+impl<'a> LogMapping<'a> {
+    /// Wrap this mapping with the current schema version.
+    pub fn versioned(&self) -> VersionedLogMapping<'_, 'a> {
+        VersionedLogMapping {
+            schema_version: SCHEMA_VERSION,
+            mapping: self,
+        }
+    }
+}
+
+/// A version of [`LogMapping`] that owns the parts of the log message, for when the
+/// message does not outlive the match, like with [`LogMatcher::match_reader`].
+#[derive(Clone, Debug, Serialize)]
+pub struct OwnedLogMapping {
+    #[serde(skip_serializing)]
+    pub line: String,
+    #[serde(skip_serializing)]
+    pub body: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lineno: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<String>,
+    #[serde(rename(serialize = "srcRef"))]
+    pub src_ref: Option<SourceRef>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(rename(serialize = "exceptionTrace"))]
+    pub exception_trace: Vec<CallSite>,
+    pub variables: Vec<VariablePair>,
+    #[serde(rename(serialize = "levelMismatch"))]
+    #[serde(skip_serializing_if = "is_false")]
+    pub level_mismatch: bool,
+    pub confidence: f32,
+    #[serde(skip_serializing)]
+    pub root: Option<PathBuf>,
+    #[serde(skip_serializing)]
+    pub file_id: Option<SourceFileID>,
+}
+
+impl From<LogMapping<'_>> for OwnedLogMapping {
+    fn from(mapping: LogMapping<'_>) -> Self {
+        let details = mapping.log_ref.details.unwrap_or_default();
+        OwnedLogMapping {
+            line: mapping.log_ref.line.to_string(),
+            body: mapping.log_ref.body().to_string(),
+            thread: details.thread.map(str::to_string),
+            file: details.file.map(str::to_string),
+            lineno: details.lineno,
+            level: details.level.map(str::to_string),
+            src_ref: mapping.src_ref,
+            exception_trace: mapping.exception_trace,
+            variables: mapping.variables,
+            level_mismatch: mapping.level_mismatch,
+            confidence: mapping.confidence,
+            root: mapping.root,
+            file_id: mapping.file_id,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Serialize)]
@@ -535,6 +2430,7 @@ fn is_only_body(details: &Option<LogDetails>) -> bool {
         details.thread.is_none()
             && details.file.is_none()
             && details.lineno.is_none()
+            && details.level.is_none()
             && details.trace.is_none()
     } else {
         true
@@ -607,7 +2503,15 @@ impl<'a> StackTrace<'a> {
                     }
                 }
             }
-            SourceLanguage::Cpp => {}
+            SourceLanguage::Cpp
+            | SourceLanguage::Go
+            | SourceLanguage::CSharp
+            | SourceLanguage::Swift
+            | SourceLanguage::JavaScript
+            | SourceLanguage::TypeScript
+            | SourceLanguage::Ruby
+            | SourceLanguage::Kotlin
+            | SourceLanguage::Scala => {}
             SourceLanguage::Python => {
                 for cap in PYTHON_CALLER_REGEX.captures_iter(self.content) {
                     retval.push(CallSite {
@@ -625,23 +2529,35 @@ impl<'a> StackTrace<'a> {
 
 #[derive(Copy, Clone, Debug, PartialEq, Serialize, Default)]
 pub struct LogDetails<'a> {
+    /// The timestamp of the message as it appears in the log.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<&'a str>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thread: Option<&'a str>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub file: Option<&'a str>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub lineno: Option<usize>,
+    /// The level of the message as it appears in the log, like `INFO`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<&'a str>,
     #[serde(skip_serializing)]
     pub body: Option<&'a str>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub trace: Option<StackTrace<'a>>,
+    /// True if the body is the contents of a quoted string, like the message in a JSON
+    /// log, whose escape sequences, like `\"` or `\n`, are decoded before it is matched.
+    #[serde(skip_serializing)]
+    pub escaped_body: bool,
 }
 
 impl<'a> LogDetails<'a> {
     fn is_empty(&self) -> bool {
-        self.thread.is_none()
+        self.timestamp.is_none()
+            && self.thread.is_none()
             && self.file.is_none()
             && self.lineno.is_none()
+            && self.level.is_none()
             && self.body.is_none()
             && self.trace.is_none()
     }
@@ -665,7 +2581,9 @@ impl<'a> LogRefBuilder<'a> {
                     .name("line")
                     .map(|m| m.as_str().parse::<usize>().unwrap_or_default()),
             )
+            .with_timestamp(captures.name("timestamp").map(|m| m.as_str()))
             .with_thread(captures.name("thread").map(|m| m.as_str()))
+            .with_level(captures.name("level").map(|m| m.as_str()))
             .with_body(captures.name("body").map(|m| m.as_str()))
             .build(content)
     }
@@ -683,6 +2601,23 @@ impl<'a> LogRefBuilder<'a> {
         self
     }
 
+    pub fn with_timestamp(mut self, timestamp: Option<&'a str>) -> Self {
+        self.details.timestamp = timestamp;
+        self
+    }
+
+    pub fn with_level(mut self, level: Option<&'a str>) -> Self {
+        self.details.level = level;
+        self
+    }
+
+    /// Mark the body as the contents of a quoted string whose escape sequences still need
+    /// to be decoded, see [`LogRef::unescaped_body`].
+    pub fn with_escaped_body(mut self, escaped: bool) -> Self {
+        self.details.escaped_body = escaped;
+        self
+    }
+
     pub fn with_body(mut self, body: Option<&'a str>) -> Self {
         let (body, trace) = if let Some(body) = body {
             if let Some(trace) = BACKTRACE_REGEX.captures(body) {
@@ -723,6 +2658,16 @@ impl<'a> LogRefBuilder<'a> {
 }
 
 impl<'a> LogRef<'a> {
+    /// Parse a log message using the first of the given formats that matches it.  Logs
+    /// from different sources can be interleaved, so each format is tried in order.  If
+    /// none of them match, the whole message is treated as the body.
+    pub fn with_formats(line: &'a str, formats: &[LogFormat]) -> LogRef<'a> {
+        formats
+            .iter()
+            .find_map(|format| format.parse(line))
+            .unwrap_or_else(|| LogRefBuilder::new().with_body(Some(line)).build(line))
+    }
+
     pub fn body(self) -> &'a str {
         if let Some(LogDetails { body: Some(s), .. }) = self.details {
             s
@@ -730,6 +2675,236 @@ impl<'a> LogRef<'a> {
             self.line
         }
     }
+
+    /// Get the body with its escape sequences decoded if it was taken from a quoted string,
+    /// like the message in a JSON log.  This is the text that is matched against the
+    /// statements.
+    pub fn unescaped_body(self) -> Cow<'a, str> {
+        match self.details {
+            Some(LogDetails {
+                escaped_body: true, ..
+            }) => log_format::unescape(self.body()),
+            _ => Cow::Borrowed(self.body()),
+        }
+    }
+
+    /// Get the `key=value` pairs at the end of the body, like the context that is appended
+    /// by structured loggers in `request done user=7 latency=34ms`.  A value can be quoted
+    /// to include spaces, in which case the quotes are removed but any escapes are left as
+    /// they are.  Pairs that are followed by other text are not included.
+    pub fn kv_pairs(&self) -> Vec<(&'a str, &'a str)> {
+        let body = self.body();
+        let mut retval = Vec::new();
+        let mut rest = body.trim_start();
+        while !rest.is_empty() {
+            let key_len = rest
+                .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '.' | '-')))
+                .unwrap_or(rest.len());
+            let pair = match rest[key_len..].strip_prefix('=') {
+                Some(value) if key_len > 0 => match value.strip_prefix('"') {
+                    Some(quoted) => quoted_len(quoted).map(|len| {
+                        let after = &quoted[len + 1..];
+                        (&quoted[..len], after)
+                    }),
+                    None => {
+                        let len = value.find(char::is_whitespace).unwrap_or(value.len());
+                        Some((&value[..len], &value[len..]))
+                    }
+                },
+                _ => None,
+            };
+            match pair {
+                Some((value, after))
+                    if after.is_empty() || after.starts_with(char::is_whitespace) =>
+                {
+                    retval.push((&rest[..key_len], value));
+                    rest = after;
+                }
+                _ => {
+                    retval.clear();
+                    let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                    rest = &rest[len..];
+                }
+            }
+            rest = rest.trim_start();
+        }
+        retval
+    }
+
+    /// Parse the timestamp captured by the log format using the given `strftime`-style
+    /// format, like `%Y-%m-%d %H:%M:%S`.  Returns `None` if there is no timestamp or it is
+    /// not in the given format.
+    #[cfg(feature = "chrono")]
+    pub fn parsed_timestamp(&self, fmt: &str) -> Option<chrono::NaiveDateTime> {
+        let timestamp = self.details?.timestamp?;
+        chrono::NaiveDateTime::parse_from_str(timestamp, fmt).ok()
+    }
+}
+
+/// Find the length of the text before the closing quote of a quoted value, skipping over
+/// escaped quotes.  The opening quote should already be removed.
+fn quoted_len(text: &str) -> Option<usize> {
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '"' => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+static ANSI_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\x1b\[[0-?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)|\x1b[@-Z\\-_]").unwrap()
+});
+
+/// Remove the ANSI escape sequences, like colors and hyperlinks, from a line of a log.
+/// The line is only copied if it contains any.
+pub fn strip_ansi(line: &str) -> Cow<'_, str> {
+    ANSI_REGEX.replace_all(line, "")
+}
+
+/// Counts of the lines that were looked at by [`filter_log_stats`].  A `format_matched`
+/// count of zero usually means that the log format does not fit the log.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct FilterStats {
+    /// The number of lines in the log.
+    #[serde(rename(serialize = "totalLines"))]
+    pub total_lines: usize,
+    /// The number of lines within the requested range.
+    #[serde(rename(serialize = "inRange"))]
+    pub in_range: usize,
+    /// The number of lines in the range that were parsed by one of the formats.
+    #[serde(rename(serialize = "formatMatched"))]
+    pub format_matched: usize,
+}
+
+/// Split a log into messages, one per line, for the lines in the given range (0 based).
+/// When formats are given, only the lines that match one of them are kept and they are
+/// parsed into their parts.  Otherwise, every line is used as the body of a message.
+/// See [`filter_log_with`] for a log with ANSI escape sequences.
+pub fn filter_log<'a>(
+    buffer: &'a str,
+    range: Range<usize>,
+    log_formats: &[LogFormat],
+) -> Vec<LogRef<'a>> {
+    filter_log_stats(buffer, range, log_formats).0
+}
+
+/// Like [`filter_log`], but also count the lines that were in range and that matched
+/// one of the formats.
+pub fn filter_log_stats<'a>(
+    buffer: &'a str,
+    range: Range<usize>,
+    log_formats: &[LogFormat],
+) -> (Vec<LogRef<'a>>, FilterStats) {
+    filter_lines(buffer, range, log_formats, |line| line)
+}
+
+/// Like [`filter_log_stats`], but when `strip_ansi` is set, the ANSI escape sequences are
+/// removed from each line before it is parsed, as with [`MatchOptions::strip_ansi`].  The
+/// copies of the lines that had any are kept in `stripped`, which the parts of the
+/// messages borrow from, while the `line` of each message is still the original text.
+pub fn filter_log_with<'a>(
+    buffer: &'a str,
+    range: Range<usize>,
+    log_formats: &[LogFormat],
+    strip_ansi: bool,
+    stripped: &'a mut Vec<String>,
+) -> (Vec<LogRef<'a>>, FilterStats) {
+    stripped.clear();
+    if strip_ansi {
+        for (index, line) in buffer.lines().enumerate() {
+            if !range.contains(&index) {
+                continue;
+            }
+            if let Cow::Owned(line) = crate::strip_ansi(line.trim_end_matches('\r')) {
+                stripped.push(line);
+            }
+        }
+    }
+    let stripped: &'a [String] = stripped;
+    let mut stripped = stripped.iter();
+    filter_lines(buffer, range, log_formats, |line| {
+        if strip_ansi && ANSI_REGEX.is_match(line) {
+            stripped.next().map_or(line, String::as_str)
+        } else {
+            line
+        }
+    })
+}
+
+/// Split a log into messages for [`filter_log_stats`] and [`filter_log_with`], parsing the
+/// text that `parsed_text` returns for each line in the range.
+fn filter_lines<'a>(
+    buffer: &'a str,
+    range: Range<usize>,
+    log_formats: &[LogFormat],
+    mut parsed_text: impl FnMut(&'a str) -> &'a str,
+) -> (Vec<LogRef<'a>>, FilterStats) {
+    let mut stats = FilterStats::default();
+    let mut log_refs = Vec::new();
+    for (index, line) in buffer.lines().enumerate() {
+        // A `\r\n` is already removed, but not a stray `\r`, like at the end of a file
+        // with Windows line endings that was cut off or in a file with mixed endings.
+        let line = line.trim_end_matches('\r');
+        stats.total_lines += 1;
+        if !range.contains(&index) {
+            continue;
+        }
+        stats.in_range += 1;
+        let text = parsed_text(line);
+        if log_formats.is_empty() {
+            stats.format_matched += 1;
+            log_refs.push(LogRefBuilder::new().with_body(Some(text)).build(line));
+        } else if let Some(mut log_ref) = log_formats.iter().find_map(|format| format.parse(text)) {
+            stats.format_matched += 1;
+            log_ref.line = line;
+            log_refs.push(log_ref);
+        }
+    }
+    (log_refs, stats)
+}
+
+/// Like [`filter_log`], but lines that do not match any of the formats, like the rest of a
+/// stack trace, are grouped with the message before them.  The range is checked against
+/// the first line of each message and lines before the first message are skipped.
+pub fn filter_log_multiline<'a>(
+    buffer: &'a str,
+    range: Range<usize>,
+    log_formats: &[LogFormat],
+) -> Vec<LogRef<'a>> {
+    if log_formats.is_empty() {
+        return filter_log(buffer, range, log_formats);
+    }
+
+    let mut starts = Vec::new();
+    let mut offset = 0;
+    for (index, line) in buffer.split_inclusive('\n').enumerate() {
+        let trimmed = line.trim_end_matches(['\n', '\r']);
+        if log_formats.iter().any(|format| format.is_match(trimmed)) {
+            starts.push((index, offset));
+        }
+        offset += line.len();
+    }
+
+    let mut log_refs = Vec::new();
+    for (pos, (index, start)) in starts.iter().enumerate() {
+        if !range.contains(index) {
+            continue;
+        }
+        let end = starts
+            .get(pos + 1)
+            .map_or(buffer.len(), |(_index, end)| *end);
+        let message = buffer[*start..end].trim_end_matches(['\n', '\r']);
+        if let Some(log_ref) = log_formats.iter().find_map(|format| format.parse(message)) {
+            log_refs.push(log_ref);
+        }
+    }
+    log_refs
 }
 
 pub fn link_to_source<'a>(log_ref: &LogRef, src_refs: &'a [SourceRef]) -> Option<&'a SourceRef> {
@@ -739,6 +2914,24 @@ pub fn link_to_source<'a>(log_ref: &LogRef, src_refs: &'a [SourceRef]) -> Option
         .find(|&source_ref| source_ref.captures(log_ref.body()).is_some())
 }
 
+/// Match a log message against the given statements, for callers that keep their own
+/// statements instead of using a [`LogMatcher`].  The statement is found as in
+/// [`link_to_source`] and its variables are extracted.  An exception trace in the message
+/// is not resolved, since that needs the statements from all the files.
+pub fn match_line<'a>(log_ref: &LogRef<'a>, src_refs: &'a [SourceRef]) -> Option<LogMapping<'a>> {
+    let src_ref = link_to_source(log_ref, src_refs)?;
+    Some(LogMapping {
+        log_ref: *log_ref,
+        src_ref: Some(src_ref.clone()),
+        variables: extract_variables(log_ref, src_ref),
+        exception_trace: Vec::new(),
+        level_mismatch: false,
+        confidence: confidence(log_ref, src_ref),
+        root: None,
+        file_id: None,
+    })
+}
+
 pub fn lookup_source<'a>(
     log_ref: &LogRef,
     log_format: &LogFormat,
@@ -760,55 +2953,206 @@ pub fn lookup_source<'a>(
     }
 }
 
-pub fn extract_variables<'a>(log_ref: &LogRef<'a>, src_ref: &'a SourceRef) -> Vec<VariablePair> {
-    let mut variables = Vec::new();
-    let line = match log_ref.details {
-        Some(details) => details.body.unwrap_or(log_ref.line),
-        None => log_ref.line,
+/// Score how confident we are that a log message was produced by a statement, from 0.0 to
+/// 1.0.  The score is mostly the fraction of the message body that was matched by the
+/// literal text of the statement, as opposed to its placeholders, since a pattern that is
+/// mostly placeholders can match almost anything.  When the message has file or line
+/// number hints, a fifth of the score is the fraction of the hints that agree with the
+/// location of the statement.  The score is rounded to two decimal places.
+pub fn confidence(log_ref: &LogRef, src_ref: &SourceRef) -> f32 {
+    let body = log_ref.body();
+    let literal = match src_ref.captures(body) {
+        Some(captures) if !body.is_empty() => {
+            let captured = captures
+                .iter()
+                .skip(1)
+                .flatten()
+                .map(|cap| cap.as_str().chars().count())
+                .sum::<usize>();
+            let total = body.chars().count();
+            total.saturating_sub(captured) as f32 / total as f32
+        }
+        Some(_) => 1.0,
+        None => 0.0,
+    };
+    let mut hints = Vec::new();
+    if let Some(details) = log_ref.details {
+        if let Some(file) = details.file {
+            hints.push(path_matches_file_hint(&src_ref.source_path, file));
+        }
+        if let Some(lineno) = details.lineno {
+            hints.push((src_ref.line_no..=src_ref.end_line_no).contains(&lineno));
+        }
+    }
+    let score = if hints.is_empty() {
+        literal
+    } else {
+        let agreed = hints.iter().filter(|agreed| **agreed).count() as f32;
+        0.8 * literal + 0.2 * agreed / hints.len() as f32
     };
-    if let Some(captures) = src_ref.captures(line) {
-        let mut placeholder_index = 0;
-        for (cap, placeholder) in std::iter::zip(captures.iter().skip(1), src_ref.args.iter()) {
-            let expr = match placeholder {
-                FormatArgument::Named(name) => name.clone(),
-                FormatArgument::Positional(pos) => src_ref
+    (score * 100.0).round() / 100.0
+}
+
+pub fn extract_variables<'a>(log_ref: &LogRef<'a>, src_ref: &'a SourceRef) -> Vec<VariablePair> {
+    match src_ref.captures(log_ref.body()) {
+        Some(captures) => variables_from_captures(&captures, src_ref).variables,
+        None => Vec::new(),
+    }
+}
+
+/// Like [`extract_variables`], but a log message that is missing the values at the end,
+/// like a malformed or cut off line, is matched as well.  The variables that were captured
+/// are returned along with the names of the ones that were not.
+pub fn extract_variables_checked<'a>(
+    log_ref: &LogRef<'a>,
+    src_ref: &'a SourceRef,
+) -> ExtractedVariables {
+    let body = log_ref.body();
+    if let Some(captures) = src_ref.captures(body) {
+        return variables_from_captures(&captures, src_ref);
+    }
+    match src_ref.prefix_captures(body) {
+        Some((captures, _cut_in_value)) => variables_from_captures(&captures, src_ref),
+        None => ExtractedVariables::default(),
+    }
+}
+
+/// Extract the variables from a log message that was truncated before the end of the
+/// statement.  The last variable is flagged as truncated if the message was cut off in the
+/// middle of its value.
+pub fn extract_truncated_variables<'a>(
+    log_ref: &LogRef<'a>,
+    src_ref: &'a SourceRef,
+) -> Vec<VariablePair> {
+    match src_ref.prefix_captures(log_ref.body()) {
+        Some((captures, cut_in_value)) => {
+            let mut variables = variables_from_captures(&captures, src_ref).variables;
+            if let Some(last) = variables.last_mut() {
+                last.truncated = cut_in_value;
+            }
+            variables
+        }
+        None => Vec::new(),
+    }
+}
+
+fn variables_from_captures(captures: &Captures, src_ref: &SourceRef) -> ExtractedVariables {
+    let mut retval = ExtractedVariables::default();
+    let mut placeholder_index = 0;
+    for (index, placeholder) in src_ref.args.iter().enumerate() {
+        // An argument that is referenced more than once is only reported the first time.
+        if src_ref.first_reference(index).is_some() {
+            continue;
+        }
+        let expr = match placeholder {
+            FormatArgument::Named(name) => name.clone(),
+            FormatArgument::Positional(pos) => src_ref
+                .vars
+                .get(*pos)
+                .map(|s| s.as_str())
+                .unwrap_or("<unknown>")
+                .to_string(),
+            FormatArgument::Placeholder => {
+                // There can be fewer variables than placeholders when the arguments are
+                // not all found, like in a C++ call with a bespoke argument list.
+                let res = src_ref
                     .vars
-                    .get(*pos)
+                    .get(placeholder_index)
                     .map(|s| s.as_str())
                     .unwrap_or("<unknown>")
-                    .to_string(),
-                FormatArgument::Placeholder => {
-                    let res = src_ref.vars[placeholder_index].to_string();
+                    .to_string();
 
-                    placeholder_index += 1;
-                    res
-                }
-            };
-            variables.push(VariablePair {
-                expr,
-                value: cap.unwrap().as_str().to_string(),
-            });
+                placeholder_index += 1;
+                res
+            }
+        };
+        let Some(cap) = captures.get(index + 1) else {
+            retval.missing.push(expr);
+            continue;
+        };
+        retval.variables.push(VariablePair {
+            expr,
+            value: cap.as_str().to_string(),
+            truncated: false,
+            placeholder_span: src_ref.placeholder_spans.get(index).copied(),
+            inferred_type: src_ref.value_types.get(index).copied().flatten(),
+        });
+    }
+
+    retval
+}
+
+/// Group mappings by the name of the function that contains the matched log statement.
+/// Mappings that were not matched to a statement are dropped.
+pub fn group_by_function<'a>(
+    mappings: Vec<LogMapping<'a>>,
+) -> HashMap<String, Vec<LogMapping<'a>>> {
+    let mut retval: HashMap<String, Vec<LogMapping<'a>>> = HashMap::new();
+    for mapping in mappings {
+        if let Some(src_ref) = &mapping.src_ref {
+            retval
+                .entry(src_ref.name.clone())
+                .or_default()
+                .push(mapping);
         }
     }
+    retval
+}
+
+/// Sort mappings by the location of the matched log statement, for viewing a log from the
+/// perspective of the source.  Mappings that were not matched to a statement are moved to
+/// the end and the order of mappings for the same statement is preserved.
+pub fn sort_by_source(mappings: &mut [LogMapping]) {
+    mappings.sort_by(|lhs, rhs| {
+        let lhs = lhs
+            .src_ref
+            .as_ref()
+            .map(|src_ref| (&src_ref.source_path, src_ref.line_no, src_ref.column));
+        let rhs = rhs
+            .src_ref
+            .as_ref()
+            .map(|src_ref| (&src_ref.source_path, src_ref.line_no, src_ref.column));
+        match (lhs, rhs) {
+            (Some(lhs), Some(rhs)) => lhs.cmp(&rhs),
+            (lhs, rhs) => rhs.is_some().cmp(&lhs.is_some()),
+        }
+    });
+}
 
-    variables
+/// Check that a tree-sitter query compiles for the given language.
+fn check_query(language: SourceLanguage, query: &str) -> Result<(), LogError> {
+    Query::new(&language.into(), query).map_err(|source| LogError::InvalidQuery {
+        language: language.as_str(),
+        source: Arc::new(source),
+    })?;
+    Ok(())
 }
 
-pub fn extract_logging_guarded(sources: &[CodeSource], guard: &WorkGuard) -> Vec<StatementsInFile> {
+pub fn extract_logging_guarded(
+    sources: &[CodeSource],
+    options: &ExtractOptions,
+    guard: &WorkGuard,
+) -> Vec<StatementsInFile> {
     sources
         .par_iter()
         .flat_map(|code| {
             let mut matched = vec![];
-            let mut patterns = vec![];
             let src_query = SourceQuery::new(code);
-            let query = code.info.language.get_query();
-            let results = src_query.query(query, None);
+            let query = code.info.language.get_query(&options.for_source(code));
+            let results = src_query.query(&query, None);
             for result in results {
                 // println!("node.kind()={:?} range={:?}", result.kind, result.range);
                 match result.kind.as_str() {
-                    "string_literal" | "string" => {
+                    "string_literal"
+                    | "raw_string_literal"
+                    | "interpreted_string_literal"
+                    | "concatenated_string"
+                    | "line_string_literal"
+                    | "multiline_string_literal"
+                    | "interpolated_string"
+                    | "template_string"
+                    | "string" => {
                         if let Some(src_ref) = SourceRef::new(code, result) {
-                            patterns.push(src_ref.pattern.clone());
                             matched.push(src_ref);
                         }
                     }
@@ -819,13 +3163,7 @@ pub fn extract_logging_guarded(sources: &[CodeSource], guard: &WorkGuard) -> Vec
                             let text = source[range.start_byte..range.end_byte].to_string();
                             // eprintln!("text={} matched.len()={}", text, matched.len());
                             // check the text doesn't match any of the logging related identifiers
-                            if code
-                                .info
-                                .language
-                                .get_identifiers()
-                                .iter()
-                                .all(|&s| s != text.to_lowercase())
-                            {
+                            if !options.is_identifier(code.info.language, &text) {
                                 let length = matched.len() - 1;
                                 let prior_result: &mut SourceRef = matched.get_mut(length).unwrap();
                                 prior_result.end_line_no = result.range.end_point.row + 1;
@@ -837,6 +3175,23 @@ pub fn extract_logging_guarded(sources: &[CodeSource], guard: &WorkGuard) -> Vec
                 }
                 // println!("*****");
             }
+            match code.info.language {
+                SourceLanguage::Rust => matched.iter_mut().for_each(|src_ref| {
+                    src_ref.numeric_precisions();
+                    src_ref.append_context_placeholders();
+                }),
+                SourceLanguage::Go => matched.iter_mut().for_each(|src_ref| {
+                    src_ref.split_context_fields();
+                    src_ref.append_context_placeholders();
+                }),
+                SourceLanguage::Python => matched
+                    .iter_mut()
+                    .for_each(|src_ref| src_ref.split_keyword_arguments()),
+                SourceLanguage::Java => matched
+                    .iter_mut()
+                    .for_each(|src_ref| src_ref.split_throwable()),
+                _ => {}
+            }
             guard.inc(1);
             if matched.is_empty() {
                 None
@@ -844,8 +3199,8 @@ pub fn extract_logging_guarded(sources: &[CodeSource], guard: &WorkGuard) -> Vec
                 Some(StatementsInFile {
                     path: matched.first().unwrap().source_path.clone(),
                     id: code.info.id,
+                    matcher: Matcher::new(&matched),
                     log_statements: matched,
-                    matcher: RegexSet::new(patterns).expect("To combine patterns"),
                 })
             }
         })
@@ -853,21 +3208,71 @@ pub fn extract_logging_guarded(sources: &[CodeSource], guard: &WorkGuard) -> Vec
 }
 
 pub fn extract_logging(sources: &[CodeSource], tracker: &ProgressTracker) -> Vec<StatementsInFile> {
+    extract_logging_with_options(sources, &ExtractOptions::default(), tracker)
+}
+
+pub fn extract_logging_with_options(
+    sources: &[CodeSource],
+    options: &ExtractOptions,
+    tracker: &ProgressTracker,
+) -> Vec<StatementsInFile> {
     let guard = tracker.doing_work(sources.len() as u64, "files".to_string());
-    extract_logging_guarded(sources, &guard)
+    extract_logging_guarded(sources, options, &guard)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use insta::{assert_snapshot, assert_yaml_snapshot};
+    use std::fmt::Write;
+    use std::fs;
     use std::ptr;
+    use std::time::Duration;
+    use tempfile::TempDir;
 
     fn from_log_format_and_line<'a>(buffer: &'a str, log_format: LogFormat) -> LogRef<'a> {
         let captures = log_format.captures(&buffer).unwrap();
         LogRefBuilder::new().build_from_captures(captures, &buffer)
     }
 
+    fn matcher_for_sources(sources: &[(&str, &str)]) -> (TempDir, LogMatcher) {
+        matcher_for_sources_with_options(sources, MatchOptions::default())
+    }
+
+    fn matcher_for_sources_with_options(
+        sources: &[(&str, &str)],
+        options: MatchOptions,
+    ) -> (TempDir, LogMatcher) {
+        matcher_for_roots(sources, &[""], options)
+    }
+
+    /// Write the sources to a temporary directory and extract the statements from the
+    /// given subdirectories of it, where an empty path is the directory itself.
+    pub(crate) fn matcher_for_roots(
+        sources: &[(&str, &str)],
+        roots: &[&str],
+        options: MatchOptions,
+    ) -> (TempDir, LogMatcher) {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in sources {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let tracker = ProgressTracker::new();
+        let mut log_matcher = LogMatcher::with_options(options);
+        for root in roots {
+            if root.is_empty() {
+                log_matcher.add_root(dir.path()).unwrap();
+            } else {
+                log_matcher.add_root(&dir.path().join(root)).unwrap();
+            }
+        }
+        assert!(log_matcher.discover_sources(&tracker).is_empty());
+        assert!(log_matcher.extract_log_statements(&tracker).is_empty());
+        (dir, log_matcher)
+    }
+
     #[test]
     fn test_log_ref_builder() {
         let buffer = String::from(
@@ -878,11 +3283,14 @@ mod tests {
         let captures = log_format.captures(&buffer).unwrap();
         let result = LogRefBuilder::new().build_from_captures(captures, &buffer);
         let details = Some(LogDetails {
+            timestamp: Some("2025-04-10 22:12:52"),
             thread: None,
             file: Some("JvmPauseMonitor"),
             lineno: Some(146),
+            level: Some("INFO"),
             body: Some("JvmPauseMonitor-n0: Started"),
             trace: None,
+            escaped_body: false,
         });
         assert_eq!(
             result,
@@ -893,6 +3301,31 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_parsed_timestamp() {
+        let regex = r"^(?<timestamp>\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}) (?<level>\w+)\s+ (?<file>[\w$.]+):(?<line>\d+) - (?<body>.*)$";
+        let log_format: LogFormat = regex.try_into().unwrap();
+        let log_ref = log_format
+            .parse("2025-04-10 22:12:52 INFO  JvmPauseMonitor:146 - JvmPauseMonitor-n0: Started")
+            .unwrap();
+        let expected = chrono::NaiveDate::from_ymd_opt(2025, 4, 10)
+            .unwrap()
+            .and_hms_opt(22, 12, 52)
+            .unwrap();
+        assert_eq!(
+            log_ref.parsed_timestamp("%Y-%m-%d %H:%M:%S"),
+            Some(expected)
+        );
+        assert_eq!(log_ref.parsed_timestamp("%d/%m/%Y %H:%M"), None);
+        assert_eq!(
+            LogRefBuilder::new()
+                .build("no timestamp")
+                .parsed_timestamp("%Y-%m-%d %H:%M:%S"),
+            None
+        );
+    }
+
     const TEST_SOURCE: &str = r#"
 #[macro_use]
 extern crate log;
@@ -972,99 +3405,2191 @@ fn namedarg2(salutation: &str, name: &str) {
         assert_yaml_snapshot!(result);
     }
 
-    const MULTILINE_SOURCE: &str = r#"
-#[macro_use]
-extern crate log;
+    #[test]
+    fn test_group_by_function() {
+        let (_dir, log_matcher) = matcher_for_sources(&[("main.rs", TEST_SOURCE)]);
+        let log_refs = [
+            "you're only as funky as your last cut",
+            "this won't match i=1; j=2",
+            "nothing to see here",
+            "this won't match i=3; j=4",
+        ]
+        .map(|line| LogRefBuilder::new().build(line));
+        let mappings = log_matcher.match_log_statements(&log_refs);
+        assert_eq!(mappings.len(), 4);
+        let groups = group_by_function(mappings);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups["main"].len(), 1);
+        assert_eq!(groups["main"][0].log_ref.line, log_refs[0].line);
+        let nope_lines = groups["nope"]
+            .iter()
+            .map(|mapping| mapping.log_ref.line)
+            .collect::<Vec<_>>();
+        assert_eq!(nope_lines, [log_refs[1].line, log_refs[3].line]);
+    }
+
+    #[test]
+    fn test_versioned_mapping() {
+        let (_dir, log_matcher) = matcher_for_sources(&[("main.rs", TEST_SOURCE)]);
+        let log_refs =
+            ["Hello, Tim!", "nothing to see here"].map(|line| LogRefBuilder::new().build(line));
+        for mapping in log_matcher.match_log_statements(&log_refs) {
+            let value = serde_json::to_value(mapping.versioned()).unwrap();
+            assert_eq!(value["schemaVersion"], SCHEMA_VERSION);
+            assert!(value.get("variables").is_some());
+        }
+    }
 
+    const COVERAGE_SOURCE: &str = r#"
 fn main() {
-    env_logger::init();
-    let adjective = "funky";
-    debug!("you're only as {}\n as your last cut", adjective);
+    debug!("starting up");
+    if should_fail() {
+        error!("failed with code {}", code);
+    }
+    debug!("shutting down");
 }
 "#;
-    #[test]
-    fn test_link_multiline() {
-        let lf = r#"^\[\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}Z \w+ \w+\]\s+(?<body>.*)"#
-            .try_into()
-            .unwrap();
-        let log_ref = from_log_format_and_line(
-            "[2024-05-09T19:58:53Z DEBUG main] you're only as funky\n as your last cut",
-            lf,
-        );
-        let code = CodeSource::from_string(&Path::new("in-mem.rs"), MULTILINE_SOURCE);
-        let src_refs = extract_logging(&[code], &ProgressTracker::new())
-            .pop()
-            .unwrap()
-            .log_statements;
-        assert_eq!(src_refs.len(), 1);
-        let result = link_to_source(&log_ref, &src_refs);
-        assert!(ptr::eq(result.unwrap(), &src_refs[0]));
-        let vars = extract_variables(&log_ref, &src_refs[0]);
-        assert_eq!(
-            vars,
-            [VariablePair {
-                expr: "adjective".to_string(),
-                value: "funky".to_string()
-            }]
-        );
-    }
 
     #[test]
-    fn test_link_to_source_no_matches() {
-        let log_ref = LogRefBuilder::new().build("nope!");
-        let code = CodeSource::from_string(&Path::new("in-mem.rs"), TEST_SOURCE);
-        let src_refs = extract_logging(&[code], &ProgressTracker::new())
-            .pop()
-            .unwrap()
-            .log_statements;
-        assert_eq!(src_refs.len(), 5);
-        let result = link_to_source(&log_ref, &src_refs);
-        assert!(result.is_none());
+    fn test_unmatched_statements() {
+        let (_dir, log_matcher) = matcher_for_sources(&[
+            ("main.rs", COVERAGE_SOURCE),
+            ("init.rs", "fn init() {\n    debug!(\"starting up\");\n}\n"),
+        ]);
+        let log_refs = ["starting up", "shutting down", "unrelated"]
+            .map(|line| LogRefBuilder::new().build(line));
+        let unmatched = log_matcher.unmatched_statements(&log_refs);
+        assert_eq!(unmatched.len(), 1);
+        assert_eq!(unmatched[0].line_no, 5);
+        assert_eq!(unmatched[0].text, "\"failed with code {}\"");
+        assert_eq!(log_matcher.stats(), MatchStats::default());
     }
 
     #[test]
-    fn test_extract_variables() {
-        let log_ref = LogRefBuilder::new().build("this won't match i=1; j=2");
-        let code = CodeSource::from_string(&Path::new("in-mem.rs"), TEST_SOURCE);
-        let src_refs = extract_logging(&[code], &ProgressTracker::new())
-            .pop()
-            .unwrap()
-            .log_statements;
-        assert_eq!(src_refs.len(), 5);
-        let vars = extract_variables(&log_ref, &src_refs[1]);
-        assert_eq!(
-            vars,
-            vec![
-                VariablePair {
-                    expr: "i".to_string(),
-                    value: "1".to_string()
-                },
-                VariablePair {
-                    expr: "j".to_string(),
-                    value: "2".to_string()
-                }
-            ]
+    fn test_strip_level() {
+        let log_ref = LogRefBuilder::new().build("INFO you're only as funky as your last cut");
+        let (_dir, log_matcher) = matcher_for_sources(&[("main.rs", TEST_SOURCE)]);
+        assert!(log_matcher.match_log_statement(&log_ref).is_none());
+
+        let (_dir, log_matcher) = matcher_for_sources_with_options(
+            &[("main.rs", TEST_SOURCE)],
+            MatchOptions::default().with_default_levels(),
         );
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert_eq!(mapping.src_ref.unwrap().name, "main");
+        let log_ref = LogRefBuilder::new().build("[warn]: Hello, Tim!");
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert_eq!(mapping.variables.last().unwrap().value, "Tim");
+        let log_ref =
+            LogRefBuilder::new().build("INFORMATIVE you're only as funky as your last cut");
+        assert!(log_matcher.match_log_statement(&log_ref).is_none());
     }
 
     #[test]
-    fn test_extract_named() {
-        let log_ref = LogRefBuilder::new().build("Hello, Tim!");
-        let code = CodeSource::from_string(&Path::new("in-mem.rs"), TEST_SOURCE);
-        let src_refs = extract_logging(&[code], &ProgressTracker::new())
-            .pop()
-            .unwrap()
-            .log_statements;
-        assert_eq!(src_refs.len(), 5);
-        let vars = extract_variables(&log_ref, &src_refs[3]);
-        assert_eq!(
-            vars,
-            vec![VariablePair {
-                expr: "name".to_string(),
-                value: "Tim".to_string()
-            },]
+    fn test_check_levels() {
+        let log_format: LogFormat = r"^(?<level>\w+) (?<body>.*)$".try_into().unwrap();
+        let source = "fn main() {\n    warn!(\"disk almost full\");\n}\n";
+        let (_dir, log_matcher) = matcher_for_sources_with_options(
+            &[("main.rs", source)],
+            MatchOptions {
+                check_levels: true,
+                ..Default::default()
+            },
         );
-    }
+        let log_ref = from_log_format_and_line("INFO disk almost full", log_format.clone());
+        assert_eq!(log_ref.details.unwrap().level, Some("INFO"));
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert!(mapping.level_mismatch);
+        let log_ref = from_log_format_and_line("WARNING disk almost full", log_format.clone());
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert!(!mapping.level_mismatch);
+
+        let (_dir, log_matcher) = matcher_for_sources(&[("main.rs", source)]);
+        let log_ref = from_log_format_and_line("INFO disk almost full", log_format.clone());
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert!(!mapping.level_mismatch);
+
+        // The statement with the same level should win over an identical one.
+        let (_dir, log_matcher) = matcher_for_sources_with_options(
+            &[
+                ("main.rs", source),
+                (
+                    "other.rs",
+                    "fn other() {\n    debug!(\"disk almost full\");\n}\n",
+                ),
+            ],
+            MatchOptions {
+                check_levels: true,
+                ..Default::default()
+            },
+        );
+        for (line, expected) in [
+            ("WARN disk almost full", "main.rs"),
+            ("DEBUG disk almost full", "other.rs"),
+        ] {
+            let log_ref = from_log_format_and_line(line, log_format.clone());
+            let mappings = log_matcher.match_log_statement_all(&log_ref);
+            assert_eq!(mappings.len(), 2);
+            assert!(!mappings[0].level_mismatch);
+            assert!(mappings[1].level_mismatch);
+            assert!(mappings[0]
+                .src_ref
+                .as_ref()
+                .unwrap()
+                .source_path
+                .ends_with(expected));
+        }
+    }
+
+    #[test]
+    fn test_preprocessor() {
+        let log_ref = LogRefBuilder::new().build("APP| Hello,\\tTim!");
+        let (_dir, mut log_matcher) = matcher_for_sources(&[("main.rs", TEST_SOURCE)]);
+        assert!(log_matcher.match_log_statement(&log_ref).is_none());
+
+        log_matcher.set_preprocessor(Box::new(|body| {
+            let body = body.strip_prefix("APP| ").unwrap_or(body);
+            if body.contains("\\t") {
+                Cow::Owned(body.replace("\\t", " "))
+            } else {
+                Cow::Borrowed(body)
+            }
+        }));
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert_eq!(mapping.log_ref, log_ref);
+        assert_eq!(mapping.variables.last().unwrap().value, "Tim");
+    }
+
+    #[test]
+    fn test_rescan_changed() {
+        let (dir, mut log_matcher) = matcher_for_sources(&[
+            ("a.rs", "fn a() {\n    debug!(\"alpha {}\", x);\n}\n"),
+            ("b.rs", "fn b() {\n    debug!(\"bravo {}\", x);\n}\n"),
+            ("c.rs", "fn c() {\n    debug!(\"charlie {}\", x);\n}\n"),
+        ]);
+        fs::write(
+            dir.path().join("b.rs"),
+            "fn b() {\n    debug!(\"bravo {}\", x);\n    debug!(\"delta {}\", y);\n}\n",
+        )
+        .unwrap();
+        fs::remove_file(dir.path().join("c.rs")).unwrap();
+
+        let mut tracker = ProgressTracker::new();
+        let listener = tracker.subscribe();
+        assert!(log_matcher.rescan_changed(&tracker).is_empty());
+        let parsed = std::iter::from_fn(|| listener.try_next_for(Duration::ZERO))
+            .filter_map(|update| match update {
+                ProgressUpdate::Work(info) if info.units == "files" => Some(info.total),
+                _ => None,
+            })
+            .sum::<u64>();
+        assert_eq!(parsed, 1);
+
+        let matched = |line: &str| {
+            log_matcher
+                .match_log_statement(&LogRefBuilder::new().build(line))
+                .is_some()
+        };
+        assert!(matched("alpha 1"));
+        assert!(matched("delta 2"));
+        assert!(!matched("charlie 3"));
+    }
+
+    #[test]
+    fn test_with_formats() {
+        let formats: [LogFormat; 2] = [
+            r"^\[(?<level>\w+)\] (?<file>[\w.]+):(?<line>\d+) (?<body>.*)$"
+                .try_into()
+                .unwrap(),
+            r#"^(?<thread>\S+) "(?<body>[^"]*)" \d+$"#.try_into().unwrap(),
+        ];
+        let log_ref = LogRef::with_formats("[INFO] main.rs:12 started", &formats);
+        let details = log_ref.details.unwrap();
+        assert_eq!(details.file, Some("main.rs"));
+        assert_eq!(details.lineno, Some(12));
+        assert_eq!(log_ref.body(), "started");
+
+        let log_ref = LogRef::with_formats(r#"10.0.0.1 "GET /index.html" 200"#, &formats);
+        let details = log_ref.details.unwrap();
+        assert_eq!(details.thread, Some("10.0.0.1"));
+        assert_eq!(details.file, None);
+        assert_eq!(log_ref.body(), "GET /index.html");
+
+        let log_ref = LogRef::with_formats("neither format", &formats);
+        assert_eq!(log_ref.body(), "neither format");
+    }
+
+    #[test]
+    fn test_kv_pairs() {
+        let log_ref = LogRefBuilder::new()
+            .build(r#"request done for a=b cache user=7 msg="not \"found\" here" latency=34ms"#);
+        assert_eq!(
+            log_ref.kv_pairs(),
+            [
+                ("user", "7"),
+                ("msg", r#"not \"found\" here"#),
+                ("latency", "34ms")
+            ]
+        );
+        let log_ref = LogRefBuilder::new().build("k=v then text");
+        assert!(log_ref.kv_pairs().is_empty());
+        let log_ref = LogRefBuilder::new().build(r#"k="unterminated value"#);
+        assert!(log_ref.kv_pairs().is_empty());
+        let log_ref = LogRefBuilder::new().build("=x empty= k=");
+        assert_eq!(log_ref.kv_pairs(), [("empty", ""), ("k", "")]);
+    }
+
+    #[test]
+    fn test_serde_statements_in_file() {
+        let code = CodeSource::from_string(
+            Path::new("in-mem.rs"),
+            "fn main() {\n    debug!(\"started\");\n    info!(\"took {}ms\", elapsed);\n}\n",
+        );
+        let stmts = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap();
+        let json = serde_json::to_string(&stmts).unwrap();
+        let loaded: StatementsInFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.path, stmts.path);
+        assert_eq!(loaded.id, stmts.id);
+        assert_eq!(loaded.log_statements.len(), 2);
+        assert_eq!(serde_json::to_string(&loaded).unwrap(), json);
+        assert_eq!(loaded.matcher.first_match("took 12ms"), Some(1));
+        let src_ref = &loaded.log_statements[1];
+        assert_eq!(src_ref.line_no, 3);
+        assert_eq!(src_ref.vars, ["elapsed"]);
+        assert_eq!(src_ref.captures("took 12ms").unwrap()[1].to_string(), "12");
+
+        let json = serde_json::to_string(&CachedStatement::from(src_ref)).unwrap();
+        let cached: CachedStatement = serde_json::from_str(&json).unwrap();
+        let loaded = SourceRef::try_from(cached).unwrap();
+        assert_eq!(&loaded, src_ref);
+        assert_eq!(
+            serde_json::to_value(&loaded).unwrap(),
+            serde_json::to_value(src_ref).unwrap()
+        );
+
+        // The patterns are compiled again, so an invalid one is an error.
+        let json = json.replace("^took ", "^took (");
+        assert!(serde_json::from_str::<StatementsInFile>(&json).is_err());
+    }
+
+    #[test]
+    fn test_match_reader() {
+        let (_dir, log_matcher) = matcher_for_sources(&[("main.rs", TEST_SOURCE)]);
+        let formats: [LogFormat; 1] = [r"^(?<level>\w+) (?<body>.*)$".try_into().unwrap()];
+        let input = "DEBUG you're only as funky as your last cut\nINFO not in the source\nbare\n";
+        let mappings = log_matcher
+            .match_reader(io::Cursor::new(input), &formats)
+            .collect::<io::Result<Vec<OwnedLogMapping>>>()
+            .unwrap();
+        assert_eq!(mappings.len(), 3);
+        assert_eq!(mappings[0].level.as_deref(), Some("DEBUG"));
+        assert_eq!(mappings[0].body, "you're only as funky as your last cut");
+        assert_eq!(mappings[0].src_ref.as_ref().unwrap().line_no, 7);
+        assert_eq!(mappings[1].body, "not in the source");
+        assert!(mappings[1].src_ref.is_none());
+        assert_eq!(mappings[2].line, "bare");
+        assert_eq!(mappings[2].level, None);
+    }
+
+    #[test]
+    fn test_match_reader_errors() {
+        let (_dir, log_matcher) = matcher_for_sources(&[("main.rs", TEST_SOURCE)]);
+        let input: &[u8] = b"bad \xff byte\r\nyou're only as funky as your last cut\n";
+        let mappings = log_matcher
+            .match_reader(
+                io::BufReader::new(io::Read::chain(input, FailingReader)),
+                &[],
+            )
+            .collect::<Vec<_>>();
+        assert_eq!(mappings.len(), 3);
+        assert_eq!(mappings[0].as_ref().unwrap().line, "bad \u{fffd} byte");
+        assert_eq!(
+            mappings[1]
+                .as_ref()
+                .unwrap()
+                .src_ref
+                .as_ref()
+                .unwrap()
+                .line_no,
+            7
+        );
+        assert_eq!(
+            mappings[2].as_ref().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    /// A reader that always fails, like a truncated or unreadable file.
+    struct FailingReader;
+
+    impl io::Read for FailingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::UnexpectedEof.into())
+        }
+    }
+
+    #[test]
+    fn test_match_reader_min_confidence() {
+        let source = r#"
+fn main() {
+    debug!("connected to server");
+    info!("got {}", msg);
+}
+"#;
+        let input = "connected to server
+got a payload that is mostly the value
+";
+        let sources = [("main.rs", source)];
+        let (_dir, log_matcher) = matcher_for_sources(&sources);
+        let mappings = log_matcher
+            .match_reader(io::Cursor::new(input), &[])
+            .collect::<io::Result<Vec<OwnedLogMapping>>>()
+            .unwrap();
+        assert!(mappings.iter().all(|mapping| mapping.src_ref.is_some()));
+
+        let (_dir, log_matcher) = matcher_for_sources_with_options(
+            &sources,
+            MatchOptions {
+                min_confidence: 0.8,
+                ..MatchOptions::default()
+            },
+        );
+        let mappings = log_matcher
+            .match_reader(io::Cursor::new(input), &[])
+            .collect::<io::Result<Vec<OwnedLogMapping>>>()
+            .unwrap();
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(mappings[0].src_ref.as_ref().unwrap().line_no, 3);
+        assert_eq!(mappings[0].confidence, 1.0);
+        assert_eq!(mappings[1].line, "got a payload that is mostly the value");
+        assert!(mappings[1].src_ref.is_none());
+        assert!(mappings[1].variables.is_empty());
+        assert_eq!(
+            log_matcher.stats(),
+            MatchStats {
+                matched: 1,
+                unmatched: 1,
+                ambiguous: 0,
+            }
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_match_gz_reader() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let (_dir, log_matcher) = matcher_for_sources(&[("main.rs", TEST_SOURCE)]);
+        let formats: [LogFormat; 1] = [r"^(?<level>\w+) (?<body>.*)$".try_into().unwrap()];
+        let input = "DEBUG you're only as funky as your last cut\nINFO not in the source\n";
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(input.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let summarize = |mappings: Vec<OwnedLogMapping>| {
+            mappings
+                .into_iter()
+                .map(|mapping| (mapping.line, mapping.src_ref.map(|src_ref| src_ref.line_no)))
+                .collect::<Vec<_>>()
+        };
+        let expected = summarize(
+            log_matcher
+                .match_reader(io::Cursor::new(input), &formats)
+                .collect::<io::Result<_>>()
+                .unwrap(),
+        );
+        assert_eq!(expected.len(), 2);
+        assert_eq!(expected[0].1, Some(7));
+        let actual = summarize(
+            log_matcher
+                .match_gz_reader(compressed.as_slice(), &formats)
+                .collect::<io::Result<_>>()
+                .unwrap(),
+        );
+        assert_eq!(actual, expected);
+
+        let truncated = &compressed[..compressed.len() - 4];
+        let results = log_matcher
+            .match_gz_reader(truncated, &formats)
+            .collect::<Vec<_>>();
+        assert_eq!(results.len(), 3);
+        assert!(results.last().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_unanchored() {
+        let line = "2025-04-10 22:12:52 DEBUG [main]  you're only as funky as your last cut";
+        let log_ref = LogRefBuilder::new().build(line);
+        let (_dir, log_matcher) = matcher_for_sources(&[("main.rs", TEST_SOURCE)]);
+        assert!(log_matcher.match_log_statement(&log_ref).is_none());
+
+        let (_dir, log_matcher) = matcher_for_sources_with_options(
+            &[("main.rs", TEST_SOURCE)],
+            MatchOptions {
+                unanchored: true,
+                ..MatchOptions::default()
+            },
+        );
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert_eq!(mapping.src_ref.unwrap().line_no, 7);
+        assert_eq!(mapping.log_ref.line, line);
+        assert_eq!(
+            mapping.log_ref.body(),
+            "you're only as funky as your last cut"
+        );
+        let log_ref = LogRefBuilder::new().build("2025-04-10 22:12:52 not in the source");
+        assert!(log_matcher.match_log_statement(&log_ref).is_none());
+    }
+
+    #[test]
+    fn test_truncation_marker() {
+        const SOURCE: &str = r#"
+fn handle(peer: &str, ms: u64) {
+    info!("request from {} took {}ms to process the batch", peer, ms);
+}
+"#;
+        let (_dir, log_matcher) = matcher_for_sources_with_options(
+            &[("main.rs", SOURCE)],
+            MatchOptions::default().with_default_truncation_marker(),
+        );
+        let matched = |line: &str| {
+            log_matcher
+                .match_log_statement(&LogRefBuilder::new().build(line))
+                .map(|mapping| {
+                    assert_eq!(mapping.src_ref.as_ref().unwrap().line_no, 3);
+                    mapping
+                        .variables
+                        .iter()
+                        .map(|var| (var.value.clone(), var.truncated))
+                        .collect::<Vec<_>>()
+                })
+        };
+        assert_eq!(
+            matched("request from 10.0.0.1 took 12ms to pro..."),
+            Some(vec![
+                ("10.0.0.1".to_string(), false),
+                ("12".to_string(), false)
+            ])
+        );
+        assert_eq!(
+            matched("request from 10.0..."),
+            Some(vec![("10.0".to_string(), true)])
+        );
+        assert_eq!(matched("request fr..."), Some(vec![]));
+        assert_eq!(matched("request from 10.0.0.1 took 12ms to pro"), None);
+        assert_eq!(matched("response from 10.0..."), None);
+        // Matching again uses the patterns that were compiled the first time.
+        assert_eq!(
+            matched("request from 10.0..."),
+            Some(vec![("10.0".to_string(), true)])
+        );
+
+        // A statement that starts with a value needs some of the text that follows it.
+        let (_dir, log_matcher) = matcher_for_sources_with_options(
+            &[(
+                "main.rs",
+                "fn main() {\n    info!(\"{} connected\", peer);\n}\n",
+            )],
+            MatchOptions::default().with_default_truncation_marker(),
+        );
+        let log_ref = LogRefBuilder::new().build("db1 conn...");
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert_eq!(mapping.variables[0].value, "db1");
+        let log_ref = LogRefBuilder::new().build("unrelated...");
+        assert!(log_matcher.match_log_statement(&log_ref).is_none());
+
+        // The value before the cut does not take the part of the text that was logged.
+        let (_dir, log_matcher) = matcher_for_sources_with_options(
+            &[(
+                "main.rs",
+                "fn main() {\n    info!(\"copied {} of {} bytes to disk\", done, total);\n}\n",
+            )],
+            MatchOptions::default().with_default_truncation_marker(),
+        );
+        let log_ref = LogRefBuilder::new().build("copied 10 of 20 bytes to ...");
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert_eq!(
+            mapping
+                .variables
+                .iter()
+                .map(|var| (var.value.as_str(), var.truncated))
+                .collect::<Vec<_>>(),
+            [("10", false), ("20", false)]
+        );
+    }
+
+    #[test]
+    fn test_match_reader_strip_ansi() {
+        let formats: [LogFormat; 1] = [r"^(?<level>\w+) (?<body>.*)$".try_into().unwrap()];
+        let input = "\x1b[32mDEBUG\x1b[0m you're only as \x1b[1mfunky\x1b[0m as your last cut\n";
+        let (_dir, log_matcher) = matcher_for_sources(&[("main.rs", TEST_SOURCE)]);
+        let mappings = log_matcher
+            .match_reader(io::Cursor::new(input), &formats)
+            .collect::<io::Result<Vec<OwnedLogMapping>>>()
+            .unwrap();
+        assert!(mappings[0].src_ref.is_none());
+
+        let (_dir, log_matcher) = matcher_for_sources_with_options(
+            &[("main.rs", TEST_SOURCE)],
+            MatchOptions {
+                strip_ansi: true,
+                ..MatchOptions::default()
+            },
+        );
+        let mappings = log_matcher
+            .match_reader(io::Cursor::new(input), &formats)
+            .collect::<io::Result<Vec<OwnedLogMapping>>>()
+            .unwrap();
+        assert_eq!(mappings[0].level.as_deref(), Some("DEBUG"));
+        assert_eq!(mappings[0].body, "you're only as funky as your last cut");
+        assert_eq!(mappings[0].src_ref.as_ref().unwrap().line_no, 7);
+        assert_eq!(mappings[0].line, input.trim_end());
+        assert_eq!(
+            strip_ansi("\x1b]8;;http://x\x1b\\link\x1b]8;;\x1b\\ \x1b[1;31mred\x1b[m"),
+            "link red"
+        );
+    }
+
+    #[test]
+    fn test_progress_steps() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("main.rs"), TEST_SOURCE).unwrap();
+        let mut tracker = ProgressTracker::new();
+        let receiver = tracker.subscribe().into_receiver();
+        let mut log_matcher = LogMatcher::new();
+        log_matcher.add_root(dir.path()).unwrap();
+        assert!(log_matcher.rescan_changed(&tracker).is_empty());
+        let steps = receiver
+            .try_iter()
+            .filter_map(|update| match update {
+                ProgressUpdate::BeginStep(msg) => Some(format!("begin {msg}")),
+                ProgressUpdate::EndStep(msg) => Some(format!("end {msg}")),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            steps,
+            [
+                "begin Finding source code",
+                "end 1 files found",
+                "begin Extracting log statements",
+                "end 5 found",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_roots() {
+        let (dir, log_matcher) = matcher_for_roots(
+            &[
+                ("b/main.rs", TEST_SOURCE),
+                ("b/util.rs", "fn util() {}\n"),
+                ("a/main.rs", TEST_SOURCE),
+                ("a/other.rs", COVERAGE_SOURCE),
+            ],
+            &["b", "a"],
+            MatchOptions::default(),
+        );
+        assert_eq!(
+            log_matcher.roots().collect::<Vec<_>>(),
+            [
+                (dir.path().join("a").as_path(), 2),
+                (dir.path().join("b").as_path(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_add_nested_root() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        let parent = dir.path().join("a");
+        let child = dir.path().join("a/b");
+        for (first, second) in [(&parent, &child), (&child, &parent)] {
+            let mut log_matcher = LogMatcher::new();
+            log_matcher.add_root(first).unwrap();
+            for path in [second, first] {
+                match log_matcher.add_root(path) {
+                    Err(LogError::PathExists {
+                        path: err_path,
+                        root,
+                    }) => {
+                        assert_eq!(&err_path, path);
+                        assert_eq!(&root, first);
+                    }
+                    res => panic!("unexpected result: {:?}", res),
+                }
+            }
+            assert_eq!(log_matcher.roots.keys().collect::<Vec<_>>(), [first]);
+            assert_eq!(
+                log_matcher
+                    .match_path(&child.join("main.rs"))
+                    .map(|(root, _coll)| root),
+                Some(first)
+            );
+        }
+    }
+
+    #[test]
+    fn test_match_stats() {
+        let source = r#"
+fn main() {
+    debug!("connected to {}", host);
+}
+"#;
+        let (_dir, log_matcher) =
+            matcher_for_sources(&[("a.rs", source), ("b.rs", source), ("main.rs", TEST_SOURCE)]);
+        let input = "\
+connected to db1
+you're only as funky as your last cut
+not in the source
+this won't match i=1; j=2
+";
+        let mappings = log_matcher
+            .match_reader(io::Cursor::new(input), &[])
+            .collect::<io::Result<Vec<OwnedLogMapping>>>()
+            .unwrap();
+        assert_eq!(mappings.len(), 4);
+        assert_eq!(
+            log_matcher.stats(),
+            MatchStats {
+                matched: 3,
+                unmatched: 1,
+                ambiguous: 1,
+            }
+        );
+        log_matcher.reset_stats();
+        assert_eq!(log_matcher.stats(), MatchStats::default());
+
+        // A fuzzy match is only counted once, as matched.
+        let log_ref = LogRefBuilder::new().build("you're only as funky as your lst cut");
+        assert!(log_matcher.fuzzy_match(&log_ref, 2).is_some());
+        let log_ref = LogRefBuilder::new().build("not in the source");
+        assert!(log_matcher.fuzzy_match(&log_ref, 0).is_none());
+        assert_eq!(
+            log_matcher.stats(),
+            MatchStats {
+                matched: 1,
+                unmatched: 1,
+                ambiguous: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_shared_matcher() {
+        let (_dir, log_matcher) = matcher_for_sources(&[("main.rs", TEST_SOURCE)]);
+        let shared = log_matcher.into_shared();
+        let handles = (0..4)
+            .map(|index| {
+                let shared = shared.clone();
+                std::thread::spawn(move || {
+                    let line = format!("this won't match i={}; j={}", index, index * 2);
+                    let log_ref = LogRefBuilder::new().build(&line);
+                    let mapping = shared.match_log_statement(&log_ref).unwrap();
+                    assert_eq!(mapping.src_ref.as_ref().unwrap().line_no, 18);
+                    mapping.variables[1].value.clone()
+                })
+            })
+            .collect::<Vec<_>>();
+        let values = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(values, ["0", "2", "4", "6"]);
+    }
+
+    #[test]
+    fn test_match_by_location() {
+        let (dir, log_matcher) = matcher_for_sources(&[("main.rs", TEST_SOURCE)]);
+        let src_ref = log_matcher.match_by_location("main.rs", 7).unwrap();
+        assert_eq!(src_ref.text, "\"you're only as funky as your last cut\"");
+        let path = dir.path().join("main.rs");
+        let src_ref = log_matcher
+            .match_by_location(path.to_str().unwrap(), 18)
+            .unwrap();
+        assert_eq!(src_ref.name, "nope");
+        assert!(log_matcher.match_by_location("main.rs", 8).is_none());
+        assert!(log_matcher.match_by_location("other.rs", 7).is_none());
+    }
+
+    #[test]
+    fn test_match_most_specific() {
+        let (_tmp, log_matcher) = matcher_for_sources(&[(
+            "main.rs",
+            r#"
+fn main() {
+    info!("{}!", msg);
+    info!("processing {}!", job);
+}
+"#,
+        )]);
+        let log_ref = LogRefBuilder::new().build("processing job-7!");
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        let src_ref = mapping.src_ref.unwrap();
+        assert_eq!(src_ref.line_no, 4);
+        assert_eq!(mapping.variables[0].value, "job-7");
+
+        let log_ref = LogRefBuilder::new().build("done!");
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert_eq!(mapping.src_ref.unwrap().line_no, 3);
+    }
+
+    #[test]
+    fn test_index_virtual() {
+        let mut log_matcher = LogMatcher::new();
+        log_matcher
+            .index_virtual(&[
+                (PathBuf::from("src/main.rs"), TEST_SOURCE.to_string()),
+                (
+                    PathBuf::from("src/net.rs"),
+                    "fn connect() {\n    info!(\"connected to {}\", host);\n}\n".to_string(),
+                ),
+                (PathBuf::from("README.md"), "# not source".to_string()),
+            ])
+            .unwrap();
+        assert_eq!(log_matcher.summary().statements, 6);
+        let log_ref = LogRefBuilder::new().build("connected to db1");
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        let src_ref = mapping.src_ref.unwrap();
+        assert_eq!(src_ref.source_path, "src/net.rs");
+        assert_eq!(src_ref.line_no, 2);
+        assert_eq!(mapping.variables[0].value, "db1");
+        assert_eq!(
+            log_matcher
+                .statements_for_file(Path::new("net.rs"))
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // The new version of a file replaces the old one and the files are not scanned.
+        log_matcher
+            .index_virtual(&[(
+                PathBuf::from("src/net.rs"),
+                "fn connect() {\n    info!(\"reconnected to {}\", host);\n}\n".to_string(),
+            )])
+            .unwrap();
+        let tracker = ProgressTracker::new();
+        assert!(log_matcher.rescan_changed(&tracker).is_empty());
+        assert!(log_matcher.match_log_statement(&log_ref).is_none());
+        let log_ref = LogRefBuilder::new().build("reconnected to db1");
+        assert!(log_matcher.match_log_statement(&log_ref).is_some());
+        assert_eq!(log_matcher.summary().statements, 6);
+
+        // Paths that would be folded into another one are rejected.
+        for path in ["/src/net.rs", "src/../net.rs", "."] {
+            let result = log_matcher.index_virtual(&[
+                (PathBuf::from("src/ok.rs"), TEST_SOURCE.to_string()),
+                (PathBuf::from(path), TEST_SOURCE.to_string()),
+            ]);
+            assert!(matches!(result, Err(LogError::InvalidVirtualPath { .. })));
+        }
+        assert_eq!(log_matcher.summary().statements, 6);
+        log_matcher
+            .index_virtual(&[(PathBuf::from("./src/net.rs"), TEST_SOURCE.to_string())])
+            .unwrap();
+        assert_eq!(log_matcher.summary().statements, 10);
+    }
+
+    #[test]
+    fn test_match_escaped_json_body() {
+        let source = r#"
+fn save(name: &str) {
+    info!("saved \"{}\"\nsize={}", name, size);
+}
+"#;
+        let (_tmp, log_matcher) = matcher_for_sources(&[("store.rs", source)]);
+        let format = LogFormat::json("msg", "file", "line");
+        let line = r#"{"msg":"saved \"notes.txt\"\nsize=12","file":"store.rs","line":3}"#;
+        let log_ref = LogRef::with_formats(line, std::slice::from_ref(&format));
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert_eq!(mapping.src_ref.unwrap().line_no, 3);
+        assert_eq!(mapping.log_ref, log_ref);
+        assert_eq!(mapping.variables[0].value, "notes.txt");
+        assert_eq!(mapping.variables[1].value, "12");
+    }
+
+    #[test]
+    fn test_match_all_roots() {
+        let source = "fn run() {\n    info!(\"shutting down\");\n}\n";
+        for backend in [MatchBackend::RegexSet, MatchBackend::LiteralIndex] {
+            let (dir, log_matcher) = matcher_for_roots(
+                &[("api/server.rs", source), ("worker/job.rs", source)],
+                &["api", "worker"],
+                MatchOptions {
+                    backend,
+                    ..Default::default()
+                },
+            );
+            let log_ref = LogRefBuilder::new().build("shutting down");
+            let paths = log_matcher
+                .match_log_statement_all(&log_ref)
+                .into_iter()
+                .map(|mapping| mapping.src_ref.unwrap().source_path)
+                .sorted()
+                .collect::<Vec<_>>();
+            assert_eq!(
+                paths,
+                [
+                    dir.path().join("api/server.rs").to_string_lossy(),
+                    dir.path().join("worker/job.rs").to_string_lossy()
+                ],
+                "{backend:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_match_root_and_file_id() {
+        let disk_source = "fn main() {\n    info!(\"disk {} is full\", disk);\n}\n";
+        let (dir, mut log_matcher) = matcher_for_roots(
+            &[
+                ("a/alpha.rs", TEST_SOURCE),
+                ("b/beta.rs", TEST_SOURCE),
+                ("b/disk.rs", disk_source),
+            ],
+            &["a", "b"],
+            MatchOptions::default(),
+        );
+
+        let log_ref = LogRefBuilder::new().build("disk sda1 is full");
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        let root = mapping.root.unwrap();
+        assert_eq!(root, dir.path().join("b"));
+        let stmts = &log_matcher.roots[&root].files_with_statements[&mapping.file_id.unwrap()];
+        assert_eq!(stmts.path, mapping.src_ref.unwrap().source_path);
+        assert!(stmts.path.ends_with("disk.rs"));
+
+        let fuzzy = log_matcher
+            .fuzzy_match(&LogRefBuilder::new().build("disk sda1 is fll"), 2)
+            .unwrap();
+        assert_eq!(fuzzy.root, Some(dir.path().join("b")));
+        assert_eq!(fuzzy.file_id, mapping.file_id);
+
+        log_matcher
+            .index_virtual(&[(
+                PathBuf::from("src/net.rs"),
+                disk_source.replace("disk", "link"),
+            )])
+            .unwrap();
+        let mapping = log_matcher
+            .match_log_statement(&LogRefBuilder::new().build("link eth0 is full"))
+            .unwrap();
+        assert_eq!(mapping.root, Some(PathBuf::from(VIRTUAL_ROOT)));
+        assert!(mapping.file_id.is_some());
+    }
+
+    #[test]
+    fn test_remove_root() {
+        let (dir, mut log_matcher) = matcher_for_roots(
+            &[("a/alpha.rs", TEST_SOURCE), ("b/beta.rs", TEST_SOURCE)],
+            &["a", "b"],
+            MatchOptions::default(),
+        );
+        assert_eq!(
+            log_matcher
+                .find_source_file_statements(&dir.path().join("b/beta.rs"))
+                .len(),
+            1
+        );
+
+        assert!(log_matcher.remove_root(&dir.path().join("b")));
+        assert!(!log_matcher.remove_root(&dir.path().join("b")));
+        assert!(!log_matcher.remove_root(&dir.path().join("a/alpha.rs")));
+        assert!(log_matcher
+            .find_source_file_statements(&dir.path().join("b/beta.rs"))
+            .is_empty());
+        assert_eq!(
+            log_matcher
+                .find_source_file_statements(&dir.path().join("a/alpha.rs"))
+                .len(),
+            1
+        );
+        assert_eq!(
+            log_matcher
+                .find_source_file_statements(Path::new("alpha.rs"))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_add_roots_glob() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["auth/src", "billing/src", "billing/src/util", "docs"] {
+            fs::create_dir_all(dir.path().join(name)).unwrap();
+        }
+        let mut log_matcher = LogMatcher::new();
+        log_matcher.add_root(&dir.path().join("auth/src")).unwrap();
+        let pattern = format!("{}/*/src", dir.path().display());
+        assert_eq!(log_matcher.add_roots_glob(&pattern).unwrap(), 1);
+        let pattern = format!("{}/*/src/*", dir.path().display());
+        assert_eq!(log_matcher.add_roots_glob(&pattern).unwrap(), 0);
+        // The directories that hold the roots are skipped as well.
+        let pattern = format!("{}/*", dir.path().display());
+        assert_eq!(log_matcher.add_roots_glob(&pattern).unwrap(), 1);
+        assert_eq!(
+            log_matcher.roots.keys().sorted().collect::<Vec<_>>(),
+            [
+                &dir.path().join("auth/src"),
+                &dir.path().join("billing/src"),
+                &dir.path().join("docs")
+            ]
+        );
+        assert!(matches!(
+            log_matcher.add_roots_glob("[unclosed"),
+            Err(LogError::InvalidGlob { .. })
+        ));
+    }
+
+    #[test]
+    fn test_root_has_no_supported_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let empty_dir = dir.path().join("empty");
+        let src_dir = dir.path().join("src");
+        fs::create_dir(&empty_dir).unwrap();
+        fs::create_dir(&src_dir).unwrap();
+        fs::write(empty_dir.join("README.md"), "# Nothing to see").unwrap();
+        fs::write(src_dir.join("main.rs"), TEST_SOURCE).unwrap();
+        let tracker = ProgressTracker::new();
+        let mut log_matcher = LogMatcher::new();
+        log_matcher.add_root(&empty_dir).unwrap();
+        log_matcher.add_root(&src_dir).unwrap();
+        match &log_matcher.discover_sources(&tracker)[..] {
+            [LogError::RootHasNoSupportedFiles { root }] => assert_eq!(root, &empty_dir),
+            errors => panic!("unexpected errors: {:?}", errors),
+        }
+    }
+
+    #[test]
+    fn test_add_ignore() {
+        let (dir, mut log_matcher) = matcher_for_sources(&[
+            ("src/lib.rs", TEST_SOURCE),
+            ("target/debug/build/lib.rs", TEST_SOURCE),
+            ("vendor/dep/src/lib.rs", TEST_SOURCE),
+        ]);
+        let tracker = ProgressTracker::new();
+        log_matcher.add_ignore("target/").unwrap();
+        log_matcher.add_ignore("/vendor/*/src").unwrap();
+        assert!(log_matcher.rescan_changed(&tracker).is_empty());
+        let sources = |log_matcher: &LogMatcher| {
+            log_matcher
+                .all_statements()
+                .map(|src_ref| {
+                    let path = Path::new(&src_ref.source_path);
+                    path.strip_prefix(dir.path()).unwrap().to_path_buf()
+                })
+                .unique()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sources(&log_matcher), [Path::new("src/lib.rs")]);
+        assert_eq!(log_matcher.summary().languages["Rust"].files, 1);
+
+        log_matcher.add_ignore("lib.rs").unwrap();
+        assert!(matches!(
+            log_matcher.rescan_changed(&tracker)[..],
+            [LogError::RootHasNoSupportedFiles { .. }]
+        ));
+        assert!(log_matcher.is_empty());
+        assert!(matches!(
+            log_matcher.add_ignore("[unclosed"),
+            Err(LogError::InvalidGlob { .. })
+        ));
+    }
+
+    #[test]
+    fn test_statements_for_file() {
+        let (dir, log_matcher) = matcher_for_sources(&[
+            ("src/main.rs", TEST_SOURCE),
+            ("src/other.rs", COVERAGE_SOURCE),
+        ]);
+        let code = CodeSource::from_string(&Path::new("main.rs"), TEST_SOURCE);
+        let expected = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        for path in [
+            dir.path().join("src/main.rs"),
+            PathBuf::from("src/main.rs"),
+            PathBuf::from("main.rs"),
+        ] {
+            let stmts = log_matcher.statements_for_file(&path).unwrap();
+            assert_eq!(stmts.len(), expected.len(), "path: {}", path.display());
+            assert_eq!(stmts[0].text, expected[0].text);
+        }
+        assert!(log_matcher
+            .statements_for_file(Path::new("missing.rs"))
+            .is_none());
+    }
+
+    #[test]
+    fn test_summary() {
+        let (_dir, log_matcher) = matcher_for_sources(&[
+            ("main.rs", TEST_SOURCE),
+            ("empty.rs", "fn main() {}\n"),
+            ("Main.java", TEST_PUNC_SRC),
+            ("README.md", "# nothing"),
+        ]);
+        let summary = log_matcher.summary();
+        assert_eq!(
+            summary.languages["Rust"],
+            LanguageSummary {
+                files: 2,
+                files_with_statements: 1,
+                statements: 5,
+            }
+        );
+        assert_eq!(
+            summary.languages["Java"],
+            LanguageSummary {
+                files: 1,
+                files_with_statements: 1,
+                statements: 2,
+            }
+        );
+        assert_eq!(summary.languages.len(), 2);
+        assert_eq!(summary.statements, 7);
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["languages"]["Java"]["filesWithStatements"], 1);
+    }
+
+    #[test]
+    fn test_match_all() {
+        let source =
+            "\nfn flush() {\n    warn!(\"queue is full, dropping {} events\", count);\n}\n";
+        let (_dir, log_matcher) = matcher_for_sources(&[
+            ("ingest.rs", source),
+            ("export.rs", &format!("\n{}", source)),
+        ]);
+        let log_ref = LogRefBuilder::new().build("queue is full, dropping 12 events");
+        let mappings = log_matcher.match_log_statement_all(&log_ref);
+        let locations = mappings
+            .iter()
+            .map(|mapping| {
+                let src_ref = mapping.src_ref.as_ref().unwrap();
+                let file_name = Path::new(&src_ref.source_path).file_name().unwrap();
+                (file_name.to_str().unwrap(), src_ref.line_no)
+            })
+            .sorted()
+            .collect::<Vec<_>>();
+        assert_eq!(locations, [("export.rs", 4), ("ingest.rs", 3)]);
+        assert!(mappings
+            .iter()
+            .all(|mapping| mapping.variables[0].value == "12"));
+
+        let hinted = LogRefBuilder::new()
+            .with_file(Some("src/export.rs"))
+            .with_lineno(Some(4))
+            .with_body(Some("queue is full, dropping 12 events"))
+            .build("queue is full, dropping 12 events");
+        let top = log_matcher.match_log_statement(&hinted).unwrap();
+        assert_eq!(top.src_ref.unwrap().line_no, 4);
+        assert!(log_matcher
+            .match_log_statement_all(&LogRefBuilder::new().build("nope"))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_match_all_same_file() {
+        let source = "\
+fn connect() {
+    debug!(\"retrying request\");
+}
+
+fn upload() {
+    debug!(\"retrying request\");
+}
+";
+        for backend in [MatchBackend::RegexSet, MatchBackend::LiteralIndex] {
+            let (_dir, log_matcher) = matcher_for_sources_with_options(
+                &[("client.rs", source)],
+                MatchOptions {
+                    backend,
+                    ..MatchOptions::default()
+                },
+            );
+            let log_ref = LogRefBuilder::new().build("retrying request");
+            let mappings = log_matcher.match_log_statement_all(&log_ref);
+            assert_eq!(
+                mappings
+                    .iter()
+                    .map(|mapping| mapping.src_ref.as_ref().unwrap().name.as_str())
+                    .collect::<Vec<_>>(),
+                ["connect", "upload"],
+                "{:?}",
+                backend
+            );
+
+            let hinted = LogRefBuilder::new()
+                .with_lineno(Some(6))
+                .with_body(Some("retrying request"))
+                .build("retrying request");
+            let mappings = log_matcher.match_log_statement_all(&hinted);
+            assert_eq!(mappings[0].src_ref.as_ref().unwrap().name, "upload");
+            let top = log_matcher.match_log_statement(&hinted).unwrap();
+            assert_eq!(top.src_ref.unwrap().line_no, 6);
+            assert_eq!(log_matcher.stats().ambiguous, 1);
+        }
+    }
+
+    const RETRY_SOURCE: &str = r#"fn sync() {
+    debug!("retrying in {}ms", delay);
+    upload();
+    debug!("retrying in {}ms", delay * 2);
+}
+"#;
+
+    #[test]
+    fn test_case_insensitive() {
+        let source = "fn main() {\n    info!(\"Started {} workers\", count);\n}\n";
+        for backend in [MatchBackend::RegexSet, MatchBackend::LiteralIndex] {
+            let (_dir, log_matcher) = matcher_for_sources_with_options(
+                &[("main.rs", source)],
+                MatchOptions {
+                    backend,
+                    ..MatchOptions::default()
+                },
+            );
+            let log_ref = LogRefBuilder::new().build("STARTED 4 WORKERS");
+            assert!(log_matcher.match_log_statement(&log_ref).is_none());
+
+            let (_dir, log_matcher) = matcher_for_sources_with_options(
+                &[("main.rs", source)],
+                MatchOptions {
+                    backend,
+                    case_insensitive: true,
+                    ..MatchOptions::default()
+                },
+            );
+            let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+            assert_eq!(mapping.src_ref.unwrap().line_no, 2, "{:?}", backend);
+            assert_eq!(mapping.variables[0].value, "4");
+            let log_ref = LogRefBuilder::new().build("started 4 workers");
+            assert!(log_matcher.match_log_statement(&log_ref).is_some());
+        }
+    }
+
+    #[test]
+    fn test_flexible_whitespace() {
+        let source = "fn main() {\n    info!(\"pool:  {} of {}\", used, total);\n}\n";
+        for backend in [MatchBackend::RegexSet, MatchBackend::LiteralIndex] {
+            let (_dir, log_matcher) = matcher_for_sources_with_options(
+                &[("main.rs", source)],
+                MatchOptions {
+                    backend,
+                    ..MatchOptions::default()
+                },
+            );
+            let log_ref = LogRefBuilder::new().build("pool: a  b of 8");
+            assert!(log_matcher.match_log_statement(&log_ref).is_none());
+
+            let (_dir, log_matcher) = matcher_for_sources_with_options(
+                &[("main.rs", source)],
+                MatchOptions {
+                    backend,
+                    flexible_whitespace: true,
+                    ..MatchOptions::default()
+                },
+            );
+            let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+            assert_eq!(mapping.src_ref.unwrap().line_no, 2, "{:?}", backend);
+            assert_eq!(mapping.variables[0].value, "a  b");
+            assert_eq!(mapping.variables[1].value, "8");
+        }
+    }
+
+    #[test]
+    fn test_grouped_numbers() {
+        let sources = [
+            (
+                "main.rs",
+                "fn main() {\n    info!(\"count={} avg={:.2}\", count, avg as f64);\n}\n",
+            ),
+            (
+                "main.cpp",
+                "void main() {\n    info(\"count=%d of %u\", count, total);\n}\n",
+            ),
+        ];
+        let matched = |log_matcher: &LogMatcher, line: &str| {
+            log_matcher
+                .match_log_statement(&LogRefBuilder::new().build(line))
+                .map(|mapping| {
+                    mapping
+                        .variables
+                        .iter()
+                        .map(|var| var.value.clone())
+                        .collect::<Vec<_>>()
+                })
+        };
+        let (_dir, log_matcher) = matcher_for_sources(&sources);
+        assert_eq!(
+            matched(&log_matcher, "count=1,000 avg=2.50"),
+            Some(vec!["1,000".to_string(), "2.50".to_string()])
+        );
+        assert_eq!(matched(&log_matcher, "count=1,000 avg=1,234.50"), None);
+        assert_eq!(matched(&log_matcher, "count=1,000 of 1 000 000"), None);
+
+        let (_dir, log_matcher) = matcher_for_sources_with_options(
+            &sources,
+            MatchOptions {
+                grouped_numbers: true,
+                ..MatchOptions::default()
+            },
+        );
+        assert_eq!(
+            matched(&log_matcher, "count=1,000 avg=1,234.50"),
+            Some(vec!["1,000".to_string(), "1,234.50".to_string()])
+        );
+        assert_eq!(
+            matched(&log_matcher, "count=-1,000 of 1 000 000"),
+            Some(vec!["-1,000".to_string(), "1 000 000".to_string()])
+        );
+        assert_eq!(matched(&log_matcher, "count=1,00 of 5"), None);
+    }
+
+    #[test]
+    fn test_fuzzy_match() {
+        let source = "fn main() {\n    info!(\"connection closed by peer {}\", addr);\n}\n";
+        let (_dir, log_matcher) = matcher_for_sources(&[("main.rs", source)]);
+        let log_ref = LogRefBuilder::new().build("connection closd by peer 10.0.0.1");
+        assert!(log_matcher.match_log_statement(&log_ref).is_none());
+        assert!(log_matcher.fuzzy_match(&log_ref, 0).is_none());
+        let mapping = log_matcher.fuzzy_match(&log_ref, 2).unwrap();
+        assert_eq!(mapping.src_ref.unwrap().line_no, 2);
+        assert!(mapping.variables.is_empty());
+        assert_eq!(mapping.confidence, 0.97);
+
+        let log_ref = LogRefBuilder::new().build("connection closed by peer 10.0.0.1");
+        let mapping = log_matcher.fuzzy_match(&log_ref, 0).unwrap();
+        assert_eq!(mapping.variables[0].value, "10.0.0.1");
+        let log_ref = LogRefBuilder::new().build("connection reset by peer 10.0.0.1");
+        assert!(log_matcher.fuzzy_match(&log_ref, 2).is_none());
+    }
+
+    #[test]
+    fn test_ambiguity_report() {
+        let source = r#"
+fn connect() {
+    debug!("retrying request");
+    info!("user {} logged in", name);
+}
+
+fn disconnect() {
+    debug!("retrying request");
+    info!("user admin logged in");
+    info!("goodbye");
+}
+"#;
+        let (_dir, log_matcher) = matcher_for_sources(&[("main.rs", source)]);
+        let groups = log_matcher.ambiguity_report();
+        assert_eq!(
+            groups
+                .iter()
+                .map(|group| group
+                    .statements
+                    .iter()
+                    .map(|src_ref| src_ref.line_no)
+                    .collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+            [vec![3, 8], vec![4, 9]]
+        );
+        assert_eq!(groups[0].statements[0].name, "connect");
+        assert_eq!(groups[0].statements[1].name, "disconnect");
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let source = "fn tick() {\n    debug!(\"tick {}\", n);\n}\n";
+        let matched = || {
+            let dir = tempfile::tempdir().unwrap();
+            for name in ["b/tick.rs", "a/z.rs", "a/tick.rs", "c/tick.rs"] {
+                let path = dir.path().join(name);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, source).unwrap();
+            }
+            let tracker = ProgressTracker::new();
+            let mut log_matcher = LogMatcher::with_options(MatchOptions {
+                deterministic: true,
+                ..MatchOptions::default()
+            });
+            for root in ["c", "b", "a"] {
+                log_matcher.add_root(&dir.path().join(root)).unwrap();
+            }
+            assert!(log_matcher.discover_sources(&tracker).is_empty());
+            assert!(log_matcher.extract_log_statements(&tracker).is_empty());
+            let log_ref = LogRefBuilder::new().build("tick 1");
+            let src_ref = log_matcher.match_log_statement(&log_ref).unwrap().src_ref;
+            let path = PathBuf::from(src_ref.unwrap().source_path);
+            path.strip_prefix(dir.path()).unwrap().to_path_buf()
+        };
+        for _ in 0..4 {
+            assert_eq!(matched(), Path::new("a/tick.rs"));
+        }
+    }
+
+    #[test]
+    fn test_lineno_hint() {
+        for backend in [MatchBackend::RegexSet, MatchBackend::LiteralIndex] {
+            let options = MatchOptions {
+                backend,
+                ..MatchOptions::default()
+            };
+            let (_dir, log_matcher) =
+                matcher_for_sources_with_options(&[("sync.rs", RETRY_SOURCE)], options);
+            let matched_line = |lineno| {
+                let log_ref = LogRefBuilder::new()
+                    .with_file(Some("sync.rs"))
+                    .with_lineno(lineno)
+                    .with_body(Some("retrying in 200ms"))
+                    .build("retrying in 200ms");
+                let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+                mapping.src_ref.unwrap().line_no
+            };
+            assert_eq!(matched_line(Some(4)), 4, "{:?}", backend);
+            assert_eq!(matched_line(Some(5)), 4, "{:?}", backend);
+            assert_eq!(matched_line(Some(2)), 2, "{:?}", backend);
+            assert_eq!(matched_line(None), 2, "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn test_sort_by_source() {
+        let (_dir, log_matcher) = matcher_for_sources(&[
+            ("b.rs", COVERAGE_SOURCE),
+            ("a.rs", "\nfn init() {\n    debug!(\"warming up\");\n}\n"),
+        ]);
+        let log_refs = [
+            "shutting down",
+            "unrelated",
+            "failed with code 1",
+            "warming up",
+            "starting up",
+            "failed with code 2",
+        ]
+        .map(|line| LogRefBuilder::new().build(line));
+        let mut mappings = log_matcher.match_log_statements(&log_refs);
+        sort_by_source(&mut mappings);
+        let order = mappings
+            .iter()
+            .map(|mapping| {
+                (
+                    mapping.log_ref.line,
+                    mapping.src_ref.as_ref().map(|src_ref| src_ref.line_no),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            [
+                ("warming up", Some(3)),
+                ("starting up", Some(3)),
+                ("failed with code 1", Some(5)),
+                ("failed with code 2", Some(5)),
+                ("shutting down", Some(7)),
+                ("unrelated", None),
+            ]
+        );
+        assert!(mappings[0]
+            .src_ref
+            .as_ref()
+            .unwrap()
+            .source_path
+            .ends_with("a.rs"));
+        assert!(mappings[1]
+            .src_ref
+            .as_ref()
+            .unwrap()
+            .source_path
+            .ends_with("b.rs"));
+    }
+
+    #[test]
+    fn test_same_file_name() {
+        let (_dir, log_matcher) = matcher_for_sources(&[
+            ("a/util.rs", COVERAGE_SOURCE),
+            ("b/util.rs", COVERAGE_SOURCE),
+        ]);
+        let found = log_matcher.find_source_file_statements(Path::new("util.rs"));
+        assert_eq!(found.len(), 2);
+        let found = log_matcher.find_source_file_statements(Path::new("b/util.rs"));
+        assert_eq!(found.len(), 1);
+        assert!(found[0].path.ends_with("b/util.rs"));
+        let found = log_matcher.find_source_file_statements(Path::new("src/b/util.rs"));
+        assert_eq!(found.len(), 2);
+
+        for (hint, expected) in [("a/util.rs", "a/util.rs"), ("b/util.rs", "b/util.rs")] {
+            let log_ref = LogRefBuilder::new()
+                .with_file(Some(hint))
+                .with_body(Some("starting up"))
+                .build("starting up");
+            let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+            assert!(mapping.src_ref.unwrap().source_path.ends_with(expected));
+        }
+        let log_ref = LogRefBuilder::new()
+            .with_file(Some("other/util.rs"))
+            .with_body(Some("starting up"))
+            .build("starting up");
+        assert!(log_matcher.match_log_statement(&log_ref).is_some());
+    }
+
+    #[test]
+    fn test_memory_estimate() {
+        let (_dir, log_matcher) = matcher_for_sources(&[("a.rs", COVERAGE_SOURCE)]);
+        let one_file = log_matcher.memory_estimate();
+        assert!(one_file > COVERAGE_SOURCE.len());
+        let (_dir, log_matcher) =
+            matcher_for_sources(&[("a.rs", COVERAGE_SOURCE), ("b.rs", TEST_SOURCE)]);
+        let two_files = log_matcher.memory_estimate();
+        assert!(two_files > one_file);
+        let (_dir, log_matcher) = matcher_for_sources(&[
+            ("a.rs", COVERAGE_SOURCE),
+            ("b.rs", TEST_SOURCE),
+            ("c.rs", TEST_SOURCE),
+        ]);
+        assert!(log_matcher.memory_estimate() > two_files);
+    }
+
+    #[test]
+    fn test_suggest_diff() {
+        let source = "fn main() {\n    warn!(\"connection to {} closed by peer\", host);\n}\n";
+        let (_dir, log_matcher) =
+            matcher_for_sources(&[("main.rs", source), ("other.rs", TEST_SOURCE)]);
+        let log_ref = LogRefBuilder::new().build("connection to db1 dropped by peer");
+        assert!(log_matcher.match_log_statement(&log_ref).is_none());
+        let suggestion = log_matcher.suggest(&log_ref).unwrap();
+        assert_eq!(suggestion.src_ref.line_no, 2);
+        assert_eq!(suggestion.template, "connection to {} closed by peer");
+        let diff = suggestion.diff();
+        assert_eq!(
+            diff,
+            [
+                DiffOp::Equal("connection to ".to_string()),
+                DiffOp::Delete("{}".to_string()),
+                DiffOp::Insert("db1".to_string()),
+                DiffOp::Equal(" ".to_string()),
+                DiffOp::Delete("clos".to_string()),
+                DiffOp::Insert("dropp".to_string()),
+                DiffOp::Equal("ed by peer".to_string()),
+            ]
+        );
+
+        let log_ref = LogRefBuilder::new().build("completely unrelated");
+        assert!(log_matcher.suggest(&log_ref).is_none());
+
+        let mut source = String::from("fn main() {\n");
+        for index in 0..SUGGESTION_CANDIDATES * 2 {
+            source.push_str(&format!(
+                "    warn!(\"connection {index} closed by peer\");\n"
+            ));
+        }
+        source.push_str("    warn!(\"connection to {} closed by peer\", host);\n}\n");
+        let (_dir, log_matcher) = matcher_for_sources(&[("main.rs", &source)]);
+        let log_ref = LogRefBuilder::new().build("connection to db1 dropped by peer");
+        let suggestion = log_matcher.suggest(&log_ref).unwrap();
+        assert_eq!(suggestion.template, "connection to {} closed by peer");
+    }
+
+    #[test]
+    fn test_too_many_statements() {
+        let mut source = String::from("fn main() {\n");
+        for index in 0..2000 {
+            writeln!(
+                source,
+                "    debug!(\"statement {index} a={{}} b={{}} c={{}} d={{}} e={{}}\", a, b, c, d, e);"
+            )
+            .unwrap();
+        }
+        source.push_str("}\n");
+        let (_dir, log_matcher) = matcher_for_sources(&[("main.rs", &source)]);
+        let stmts = log_matcher.find_source_file_statements(Path::new("main.rs"));
+        assert!(matches!(stmts[0].matcher, Matcher::Fallback(_)));
+        let log_ref = LogRefBuilder::new().build("statement 1234 a=1 b=2 c=3 d=4 e=5");
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert_eq!(mapping.src_ref.unwrap().line_no, 1236);
+        assert_eq!(mapping.variables[4].value, "5");
+    }
+
+    #[test]
+    fn test_unreadable_source_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("main.rs"), COVERAGE_SOURCE).unwrap();
+        fs::write(dir.path().join("binary.rs"), b"fn main() {\xff\xfe}").unwrap();
+        fs::write(dir.path().join("deleted.rs"), COVERAGE_SOURCE).unwrap();
+        let tracker = ProgressTracker::new();
+        let mut log_matcher = LogMatcher::new();
+        log_matcher.add_root(dir.path()).unwrap();
+        assert!(log_matcher.discover_sources(&tracker).is_empty());
+        fs::remove_file(dir.path().join("deleted.rs")).unwrap();
+        let mut errors = log_matcher
+            .extract_log_statements(&tracker)
+            .into_iter()
+            .map(|err| match err {
+                LogError::CannotReadSourceFile { path, .. } => path,
+                err => panic!("unexpected error: {:?}", err),
+            })
+            .collect::<Vec<_>>();
+        errors.sort();
+        assert_eq!(
+            errors,
+            [dir.path().join("binary.rs"), dir.path().join("deleted.rs")]
+        );
+        assert_eq!(log_matcher.all_statements().count(), 3);
+    }
+
+    #[test]
+    fn test_confidence() {
+        let source = r#"
+fn main() {
+    info!("user {} logged in from {}", user, addr);
+    debug!("x: {}", value);
+}
+"#;
+        let (_dir, log_matcher) = matcher_for_sources(&[("main.rs", source)]);
+        let precise = LogRefBuilder::new().build("user bob logged in from 10.0.0.1");
+        let precise = log_matcher.match_log_statement(&precise).unwrap();
+        let greedy =
+            LogRefBuilder::new().build("x: a much longer value that dominates the message");
+        let greedy = log_matcher.match_log_statement(&greedy).unwrap();
+        assert!(precise.confidence > 0.5);
+        assert!(greedy.confidence < 0.1);
+        assert!(precise.confidence > greedy.confidence);
+
+        let body = "user bob logged in from 10.0.0.1";
+        let hinted = LogRefBuilder::new()
+            .with_file(Some("main.rs"))
+            .with_lineno(Some(3))
+            .with_body(Some(body))
+            .build(body);
+        let hinted = log_matcher.match_log_statement(&hinted).unwrap();
+        let wrong_line = LogRefBuilder::new()
+            .with_file(Some("main.rs"))
+            .with_lineno(Some(30))
+            .with_body(Some(body))
+            .build(body);
+        let wrong_line = log_matcher.match_log_statement(&wrong_line).unwrap();
+        assert!(hinted.confidence > wrong_line.confidence);
+        assert!(hinted.confidence <= 1.0);
+
+        let mapping = &log_matcher.match_log_statements(&[LogRefBuilder::new().build("nope")])[0];
+        assert_eq!(mapping.confidence, 0.0);
+    }
+
+    const PRECISION_SOURCE: &str = r#"
+fn main() {
+    info!("request took {:.3} seconds", elapsed);
+}
+"#;
+
+    #[test]
+    fn test_precision_tolerant() {
+        let log_ref = LogRefBuilder::new().build("request took 1.23 seconds");
+        let (_dir, log_matcher) = matcher_for_sources(&[("main.rs", PRECISION_SOURCE)]);
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert!(log_matcher.value_matches(&mapping, 0, "1.23"));
+        assert!(!log_matcher.value_matches(&mapping, 0, "1.234"));
+
+        let (_dir, log_matcher) = matcher_for_sources_with_options(
+            &[("main.rs", PRECISION_SOURCE)],
+            MatchOptions {
+                precision_tolerant: true,
+                ..Default::default()
+            },
+        );
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert!(log_matcher.value_matches(&mapping, 0, "1.234"));
+        assert!(log_matcher.value_matches(&mapping, 0, "1.2340"));
+        assert!(!log_matcher.value_matches(&mapping, 0, "1.254"));
+        assert!(!log_matcher.value_matches(&mapping, 0, "abc"));
+        assert!(!log_matcher.value_matches(&mapping, 1, "1.23"));
+    }
+
+    #[test]
+    fn test_precision_without_type() {
+        let source = r#"
+fn main() {
+    info!("user {:.3} took {:.1}s", name, elapsed as f64);
+}
+"#;
+        let (_dir, log_matcher) = matcher_for_sources(&[("main.rs", source)]);
+        let src_ref = log_matcher.all_statements().next().unwrap();
+        assert_eq!(src_ref.value_types, [None, Some(VarType::Float)]);
+        let log_ref = LogRefBuilder::new().build("user bob took 0.5s");
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert_eq!(mapping.variables[0].value, "bob");
+        assert_eq!(mapping.variables[1].value, "0.5");
+        let log_ref = LogRefBuilder::new().build("user bob took a whiles");
+        assert!(log_matcher.match_log_statement(&log_ref).is_none());
+    }
+
+    #[test]
+    fn test_statements_under() {
+        let (dir, log_matcher) = matcher_for_sources(&[
+            ("net/client.rs", TEST_SOURCE),
+            ("net/server.rs", COVERAGE_SOURCE),
+            ("storage/disk.rs", COVERAGE_SOURCE),
+        ]);
+        let under_net = log_matcher.statements_under(&dir.path().join("net"));
+        assert_eq!(under_net.len(), 8);
+        assert!(under_net
+            .iter()
+            .all(|src_ref| src_ref.source_path.contains("net")));
+        let under_storage = log_matcher.statements_under(&dir.path().join("storage"));
+        assert_eq!(under_storage.len(), 3);
+        assert!(log_matcher
+            .statements_under(&dir.path().join("stor"))
+            .is_empty());
+    }
+
+    const MULTILINE_SOURCE: &str = r#"
+#[macro_use]
+extern crate log;
+
+fn main() {
+    env_logger::init();
+    let adjective = "funky";
+    debug!("you're only as {}\n as your last cut", adjective);
+}
+"#;
+    #[test]
+    fn test_link_multiline() {
+        let lf = r#"^\[\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}Z \w+ \w+\]\s+(?<body>.*)"#
+            .try_into()
+            .unwrap();
+        let log_ref = from_log_format_and_line(
+            "[2024-05-09T19:58:53Z DEBUG main] you're only as funky\n as your last cut",
+            lf,
+        );
+        let code = CodeSource::from_string(&Path::new("in-mem.rs"), MULTILINE_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 1);
+        let result = link_to_source(&log_ref, &src_refs);
+        assert!(ptr::eq(result.unwrap(), &src_refs[0]));
+        let vars = extract_variables(&log_ref, &src_refs[0]);
+        assert_eq!(
+            vars,
+            [VariablePair {
+                expr: "adjective".to_string(),
+                value: "funky".to_string(),
+                truncated: false,
+                placeholder_span: Some((16, 18)),
+                inferred_type: Some(VarType::String),
+            }]
+        );
+    }
+
+    const RAW_STRING_SOURCE: &str = r###"
+fn main() {
+    debug!(r#"opening "{}""#, path);
+    debug!(r##"query failed:
+  "#{}" -> {}"##, query, reason);
+}
+"###;
+
+    #[test]
+    fn test_link_raw_string() {
+        let code = CodeSource::from_string(&Path::new("in-mem.rs"), RAW_STRING_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 2);
+
+        let log_ref = LogRefBuilder::new().build(r#"opening "/tmp/a.txt""#);
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[0]));
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(vars[0].expr, "path");
+        assert_eq!(vars[0].value, "/tmp/a.txt");
+
+        let log_ref = LogRefBuilder::new().build("query failed:\n  \"#select\" -> timeout");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[1]));
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(
+            vars.iter()
+                .map(|pair| (pair.expr.as_str(), pair.value.as_str()))
+                .collect::<Vec<_>>(),
+            [("query", "select"), ("reason", "timeout")]
+        );
+    }
+
+    #[test]
+    fn test_match_line() {
+        let code = CodeSource::from_string(&Path::new("in-mem.rs"), TEST_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        let log_ref = LogRefBuilder::new().build("this won't match i=1; j=2");
+        let mapping = match_line(&log_ref, &src_refs).unwrap();
+        assert_eq!(mapping.src_ref.unwrap().line_no, 18);
+        assert_eq!(
+            mapping
+                .variables
+                .iter()
+                .map(|pair| (pair.expr.as_str(), pair.value.as_str()))
+                .collect::<Vec<_>>(),
+            [("i", "1"), ("j", "2")]
+        );
+        assert!(mapping.confidence > 0.5);
+        let log_ref = LogRefBuilder::new().build("not logged by anything");
+        assert!(match_line(&log_ref, &src_refs).is_none());
+    }
+
+    #[test]
+    fn test_link_byte_string() {
+        let source = r##"
+fn main() {
+    debug!(b"read {} bytes", n);
+    debug!(br#"raw "{}" bytes"#, n);
+    debug!(c"wrote {} bytes", n);
+}
+"##;
+        let code = CodeSource::from_string(&Path::new("in-mem.rs"), source);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(
+            src_refs
+                .iter()
+                .map(|src_ref| src_ref.pattern.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "(?s)^read (.+) bytes$",
+                r#"(?s)^raw "(.+)" bytes$"#,
+                "(?s)^wrote (.+) bytes$"
+            ]
+        );
+        let log_ref = LogRefBuilder::new().build("read 12 bytes");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[0]));
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(vars[0].value, "12");
+        let (start, end) = vars[0].placeholder_span.unwrap();
+        assert_eq!(&result.text[start..end], "{}");
+    }
+
+    #[test]
+    fn test_link_multiline_value() {
+        let source = "fn main() {\n    debug!(\"loaded config: {:#?}; done\", config);\n}\n";
+        let log_ref = LogRefBuilder::new().build("loaded config: Config {\n    port: 80,\n}; done");
+        let code = CodeSource::from_string(&Path::new("in-mem.rs"), source);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(vars[0].expr, "config");
+        assert_eq!(vars[0].value, "Config {\n    port: 80,\n}");
+    }
+
+    #[test]
+    fn test_match_multiline_json_value() {
+        let source = r#"
+fn send(payload: &str) {
+    info!("sending request {} to {}", payload, host);
+}
+"#;
+        for backend in [MatchBackend::RegexSet, MatchBackend::LiteralIndex] {
+            let (_tmp, log_matcher) = matcher_for_sources_with_options(
+                &[("client.rs", source)],
+                MatchOptions {
+                    backend,
+                    ..Default::default()
+                },
+            );
+            let log_ref = LogRefBuilder::new().build("sending request {\n  \"id\": 7\n} to db1");
+            let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+            assert_eq!(mapping.src_ref.unwrap().line_no, 3, "{backend:?}");
+            assert_eq!(mapping.variables[0].value, "{\n  \"id\": 7\n}");
+            assert_eq!(mapping.variables[1].value, "db1");
+        }
+    }
+
+    #[test]
+    fn test_link_to_source_no_matches() {
+        let log_ref = LogRefBuilder::new().build("nope!");
+        let code = CodeSource::from_string(&Path::new("in-mem.rs"), TEST_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 5);
+        let result = link_to_source(&log_ref, &src_refs);
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_filter_log_stats() {
+        let buffer = "\
+2025-04-10 22:12:52 INFO  Main:12 - Started
+  at continuation line
+2025-04-10 22:12:53 INFO  Main:14 - Stopped
+garbage";
+        let regex = r"^(?<timestamp>\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}) (?<level>\w+)\s+ (?<file>[\w$.]+):(?<line>\d+) - (?<body>.*)$";
+        let log_formats = [LogFormat::try_from(regex).unwrap()];
+        let (log_refs, stats) = filter_log_stats(buffer, 0..usize::MAX, &log_formats);
+        assert_eq!(
+            stats,
+            FilterStats {
+                total_lines: 4,
+                in_range: 4,
+                format_matched: 2,
+            }
+        );
+        assert_eq!(
+            log_refs
+                .iter()
+                .map(|log_ref| log_ref.body())
+                .collect::<Vec<_>>(),
+            ["Started", "Stopped"]
+        );
+
+        let (log_refs, stats) = filter_log_stats(buffer, 1..3, &log_formats);
+        assert_eq!((stats.in_range, stats.format_matched), (2, 1));
+        assert_eq!(log_refs[0].details.unwrap().lineno, Some(14));
+        let (log_refs, stats) = filter_log_stats(buffer, 1..3, &[]);
+        assert_eq!((stats.in_range, stats.format_matched), (2, 2));
+        assert_eq!(log_refs[0].body(), "  at continuation line");
+    }
+
+    #[test]
+    fn test_filter_log_with_strip_ansi() {
+        let buffer = "\
+\x1b[32mINFO\x1b[0m  Main:12 - \x1b[1mStarted\x1b[0m
+WARN  Main:14 - Stopped";
+        let regex = r"^(?<level>\w+)\s+ (?<file>\w+):(?<line>\d+) - (?<body>.*)$";
+        let log_formats = [LogFormat::try_from(regex).unwrap()];
+        let mut stripped = Vec::new();
+        let (log_refs, stats) =
+            filter_log_with(buffer, 0..usize::MAX, &log_formats, false, &mut stripped);
+        assert_eq!(stats.format_matched, 1);
+        assert_eq!(log_refs[0].body(), "Stopped");
+
+        let (log_refs, stats) =
+            filter_log_with(buffer, 0..usize::MAX, &log_formats, true, &mut stripped);
+        assert_eq!(stats.format_matched, 2);
+        assert_eq!(log_refs[0].details.unwrap().level, Some("INFO"));
+        assert_eq!(log_refs[0].body(), "Started");
+        assert_eq!(log_refs[0].line, buffer.lines().next().unwrap());
+        assert_eq!(log_refs[1].body(), "Stopped");
+
+        let (log_refs, _stats) = filter_log_with(buffer, 0..1, &[], true, &mut stripped);
+        assert_eq!(log_refs[0].body(), "INFO  Main:12 - Started");
+        assert_eq!(log_refs[0].line, buffer.lines().next().unwrap());
+    }
+
+    #[test]
+    fn test_filter_log_crlf() {
+        let bodies = |buffer: &str, log_formats: &[LogFormat]| {
+            filter_log(buffer, 0..usize::MAX, log_formats)
+                .iter()
+                .map(|log_ref| (log_ref.line.to_string(), log_ref.body().to_string()))
+                .collect::<Vec<_>>()
+        };
+        let pairs = |lines: &[&str]| {
+            lines
+                .iter()
+                .map(|line| (line.to_string(), line.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(bodies("a\r\nb\r\n", &[]), pairs(&["a", "b"]));
+        assert_eq!(bodies("a\nb\r\r\nc\r", &[]), pairs(&["a", "b", "c"]));
+
+        let log_formats = [LogFormat::try_from(r"^(?<level>\w+) (?<body>.*)$").unwrap()];
+        let log_refs = filter_log(
+            "INFO started\r\nWARN stopped\r",
+            0..usize::MAX,
+            &log_formats,
+        );
+        assert_eq!(
+            log_refs
+                .iter()
+                .map(|log_ref| log_ref.body())
+                .collect::<Vec<_>>(),
+            ["started", "stopped"]
+        );
+    }
+
+    #[test]
+    fn test_filter_log_multiline() {
+        let buffer = "\
+garbage
+[2024-05-09T19:58:53Z DEBUG main] you're only as funky
+ as your last cut
+[2024-05-09T19:58:54Z DEBUG main] done
+";
+        let lf: LogFormat = r#"^\[\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}Z \w+ \w+\]\s+(?<body>.*)"#
+            .try_into()
+            .unwrap();
+        let log_formats = [lf];
+        let log_refs = filter_log_multiline(buffer, 0..usize::MAX, &log_formats);
+        assert_eq!(
+            log_refs
+                .iter()
+                .map(|log_ref| log_ref.body())
+                .collect::<Vec<_>>(),
+            ["you're only as funky\n as your last cut", "done"]
+        );
+
+        let code = CodeSource::from_string(&Path::new("in-mem.rs"), MULTILINE_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        let result = link_to_source(&log_refs[0], &src_refs);
+        assert!(ptr::eq(result.unwrap(), &src_refs[0]));
+
+        let log_refs = filter_log_multiline(buffer, 2..usize::MAX, &log_formats);
+        assert_eq!(log_refs.len(), 1);
+        assert_eq!(log_refs[0].body(), "done");
+    }
+
+    #[test]
+    fn test_render_example() {
+        let code = CodeSource::from_string(&Path::new("in-mem.rs"), TEST_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        let values = HashMap::from([
+            ("i".to_string(), "1".to_string()),
+            ("j".to_string(), "2".to_string()),
+        ]);
+        let example = src_refs[1].render_example(&values);
+        assert_eq!(example, "this won't match i=1; j=2");
+        let log_ref = LogRefBuilder::new().build(&example);
+        let result = link_to_source(&log_ref, &src_refs);
+        assert!(ptr::eq(result.unwrap(), &src_refs[1]));
+        assert_eq!(
+            extract_variables(&log_ref, &src_refs[1])
+                .iter()
+                .map(|pair| pair.value.as_str())
+                .collect::<Vec<_>>(),
+            ["1", "2"]
+        );
+        assert_eq!(
+            src_refs[1].render_example(&HashMap::new()),
+            "this won't match i=<var>; j=<var>"
+        );
+    }
+
+    #[test]
+    fn test_extract_variables() {
+        let log_ref = LogRefBuilder::new().build("this won't match i=1; j=2");
+        let code = CodeSource::from_string(&Path::new("in-mem.rs"), TEST_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 5);
+        let vars = extract_variables(&log_ref, &src_refs[1]);
+        let (start, end) = vars[0].placeholder_span.unwrap();
+        assert_eq!(&src_refs[1].text[start - 2..end], "i={}");
+        assert_eq!(
+            vars,
+            vec![
+                VariablePair {
+                    expr: "i".to_string(),
+                    value: "1".to_string(),
+                    truncated: false,
+                    placeholder_span: Some((20, 22)),
+                    inferred_type: Some(VarType::String),
+                },
+                VariablePair {
+                    expr: "j".to_string(),
+                    value: "2".to_string(),
+                    truncated: false,
+                    placeholder_span: Some((26, 28)),
+                    inferred_type: Some(VarType::String),
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_named() {
+        let log_ref = LogRefBuilder::new().build("Hello, Tim!");
+        let code = CodeSource::from_string(&Path::new("in-mem.rs"), TEST_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 5);
+        let vars = extract_variables(&log_ref, &src_refs[3]);
+        assert_eq!(
+            vars,
+            vec![VariablePair {
+                expr: "name".to_string(),
+                value: "Tim".to_string(),
+                truncated: false,
+                placeholder_span: Some((8, 14)),
+                inferred_type: Some(VarType::String),
+            },]
+        );
+    }
+
+    const TRUNCATED_SOURCE: &str = r#"
+fn main() {
+    debug!("count={} of {}", count, total);
+}
+"#;
+
+    #[test]
+    fn test_extract_variables_checked() {
+        let source = r#"
+fn main() {
+    info!("copied {} of {} bytes to {}", done, total, dest);
+}
+"#;
+        let code = CodeSource::from_string(&Path::new("in-mem.rs"), source);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        let log_ref = LogRefBuilder::new().build("copied 10 of 20");
+        assert!(extract_variables(&log_ref, &src_refs[0]).is_empty());
+        let extracted = extract_variables_checked(&log_ref, &src_refs[0]);
+        assert_eq!(
+            extracted
+                .variables
+                .iter()
+                .map(|pair| (pair.expr.as_str(), pair.value.as_str()))
+                .collect::<Vec<_>>(),
+            [("done", "10"), ("total", "20")]
+        );
+        assert_eq!(extracted.missing, ["dest"]);
+        let log_ref = LogRefBuilder::new().build("copied 10 of 20 bytes to ");
+        let extracted = extract_variables_checked(&log_ref, &src_refs[0]);
+        assert_eq!(extracted.variables[1].value, "20");
+        assert_eq!(extracted.missing, ["dest"]);
+
+        let log_ref = LogRefBuilder::new().build("copied 10 of 20 bytes to /tmp");
+        let extracted = extract_variables_checked(&log_ref, &src_refs[0]);
+        assert_eq!(extracted.variables.len(), 3);
+        assert!(extracted.missing.is_empty());
+        let log_ref = LogRefBuilder::new().build("moved 10");
+        assert_eq!(
+            extract_variables_checked(&log_ref, &src_refs[0]),
+            ExtractedVariables::default()
+        );
+    }
+
+    #[test]
+    fn test_extract_truncated() {
+        let log_ref = LogRefBuilder::new().build("count=1");
+        let code = CodeSource::from_string(&Path::new("in-mem.rs"), TRUNCATED_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 1);
+        assert!(extract_variables(&log_ref, &src_refs[0]).is_empty());
+        let vars = extract_truncated_variables(&log_ref, &src_refs[0]);
+        assert_eq!(
+            vars,
+            vec![VariablePair {
+                expr: "count".to_string(),
+                value: "1".to_string(),
+                truncated: true,
+                placeholder_span: Some((7, 9)),
+                inferred_type: Some(VarType::String),
+            },]
+        );
+    }
+
+    #[test]
+    fn test_call_range() {
+        const PYTHON_SOURCE: &str =
+            "def main():\n    logger.info(\n        \"started %s\", name\n    )\n";
+        for (name, source, expected) in [
+            (
+                "in-mem.rs",
+                TRUNCATED_SOURCE,
+                r#"debug!("count={} of {}", count, total)"#,
+            ),
+            (
+                "in-mem.py",
+                PYTHON_SOURCE,
+                "logger.info(\n        \"started %s\", name\n    )",
+            ),
+        ] {
+            let code = CodeSource::from_string(&Path::new(name), source);
+            let src_refs = extract_logging(&[code], &ProgressTracker::new())
+                .pop()
+                .unwrap()
+                .log_statements;
+            assert_eq!(src_refs.len(), 1);
+            assert_eq!(
+                &source[src_refs[0].start_byte..src_refs[0].end_byte],
+                expected
+            );
+        }
+    }
+
+    const MIXED_POSITIONAL_SOURCE: &str = r#"
+fn main() {
+    debug!("x={0} y={} z={1}", a, b);
+}
+"#;
+
+    #[test]
+    fn test_extract_mixed_positional() {
+        let log_ref = LogRefBuilder::new().build("x=1 y=1 z=2");
+        let code = CodeSource::from_string(&Path::new("in-mem.rs"), MIXED_POSITIONAL_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        let vars = extract_variables(&log_ref, &src_refs[0])
+            .into_iter()
+            .map(|pair| (pair.expr, pair.value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vars,
+            [
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "2".to_string())
+            ]
+        );
+        let log_ref = LogRefBuilder::new().build("x=1 y=3 z=2");
+        assert!(extract_variables(&log_ref, &src_refs[0]).is_empty());
+    }
+
+    const REPEATED_POSITIONAL_SOURCE: &str = r#"
+public class Retry {
+  void run() {
+    logger.info("{0} failed, retrying {1} after {0}", task, action);
+  }
+}
+"#;
+
+    #[test]
+    fn test_repeated_positional() {
+        let (_dir, log_matcher) =
+            matcher_for_sources(&[("Retry.java", REPEATED_POSITIONAL_SOURCE)]);
+        let log_ref = LogRefBuilder::new().build("upload failed, retrying sync after upload");
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert_eq!(
+            mapping
+                .variables
+                .iter()
+                .map(|pair| (pair.expr.as_str(), pair.value.as_str()))
+                .collect::<Vec<_>>(),
+            [("task", "upload"), ("action", "sync")]
+        );
+        let log_ref = LogRefBuilder::new().build("upload failed, retrying sync after download");
+        assert!(log_matcher.match_log_statement(&log_ref).is_none());
+    }
 
     const TEST_PUNC_SRC: &str = r#"""
   private void run() {
@@ -1076,57 +5601,1073 @@ fn main() {
     } finally {
       LOG.info("{}: Stopped", this);
     }
-  }
-"""#;
+  }
+"""#;
+    #[test]
+    fn test_log_level_serialized() {
+        let lf =
+            r"^(?<timestamp>\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}) (?<level>\w+)\s+ (?<file>[\w$.]+):(?<line>\d+) - (?<body>.*)$".try_into().unwrap();
+        let log_ref = from_log_format_and_line(
+            "2025-04-10 22:12:52 WARN  JvmPauseMonitor:146 - JvmPauseMonitor-n0: Started",
+            lf,
+        );
+        assert_eq!(log_ref.details.unwrap().level, Some("WARN"));
+        let code = CodeSource::from_string(&PathBuf::from("in-mem.java"), TEST_PUNC_SRC);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        let mapping = LogMapping {
+            log_ref,
+            src_ref: link_to_source(&log_ref, &src_refs).cloned(),
+            variables: vec![],
+            exception_trace: vec![],
+            level_mismatch: false,
+            confidence: 0.0,
+            root: None,
+            file_id: None,
+        };
+        let json = serde_json::to_value(&mapping).unwrap();
+        assert_eq!(json["logRef"]["details"]["level"], "WARN");
+    }
+
+    #[test]
+    fn test_extract_var_punctuation() {
+        let lf =
+            r"^(?<timestamp>\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}) (?<level>\w+)\s+ (?<file>[\w$.]+):(?<line>\d+) - (?<body>.*)$".try_into().unwrap();
+        let log_ref = from_log_format_and_line(
+            "2025-04-10 22:12:52 INFO  JvmPauseMonitor:146 - JvmPauseMonitor-n0: Started",
+            lf,
+        );
+        let code = CodeSource::from_string(&PathBuf::from("in-mem.java"), TEST_PUNC_SRC);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 2);
+        let vars = extract_variables(&log_ref, &src_refs[0]);
+        assert_eq!(
+            vars,
+            vec![VariablePair {
+                expr: "this".to_string(),
+                value: "JvmPauseMonitor-n0".to_string(),
+                truncated: false,
+                placeholder_span: Some((1, 3)),
+                inferred_type: Some(VarType::String),
+            },]
+        );
+    }
+
+    const CPP_SOURCE: &str = r#"
+    #include <stdio.h>
+
+    int main(int argc, char* argv[]) {
+        printf("Hello, %s!", argv[1]);
+    }
+    "#;
+
+    #[test]
+    fn test_basic_cpp() {
+        let log_ref = LogRefBuilder::new().build("Hello, Steve!");
+        let code = CodeSource::from_string(&Path::new("in-mem.cc"), CPP_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 1);
+        let vars = extract_variables(&log_ref, &src_refs[0]);
+        assert_eq!(
+            vars,
+            vec![VariablePair {
+                expr: "argv[1]".to_string(),
+                value: "Steve".to_string(),
+                truncated: false,
+                placeholder_span: Some((8, 10)),
+                inferred_type: Some(VarType::String),
+            },]
+        );
+    }
+
+    #[test]
+    fn test_cpp_missing_args() {
+        let source = r#"
+    int main(int argc, char* argv[]) {
+        printf("%s took %d ms, status %d\n", argv[0]);
+    }
+    "#;
+        let log_ref = LogRefBuilder::new().build("bench took 12 ms, status 0\n");
+        let code = CodeSource::from_string(&Path::new("in-mem.cc"), source);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 1);
+        assert_eq!(src_refs[0].vars, ["argv[0]"]);
+        let vars = extract_variables(&log_ref, &src_refs[0]);
+        assert_eq!(
+            vars.iter()
+                .map(|pair| (pair.expr.as_str(), pair.value.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                ("argv[0]", "bench"),
+                ("<unknown>", "12"),
+                ("<unknown>", "0")
+            ]
+        );
+    }
+
+    const CPP_THROW_SOURCE: &str = r#"
+    #include <stdexcept>
+
+    void check(int rc) {
+        char buf[32];
+        if (rc < 0) {
+            throw std::runtime_error("bad return code");
+        }
+        strcpy(buf, "not a log message");
+        spdlog::info("return code is {}", rc);
+    }
+    "#;
+
+    #[test]
+    fn test_cpp_log_functions() {
+        let code = CodeSource::from_string(&Path::new("in-mem.cc"), CPP_THROW_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 1);
+        assert_eq!(src_refs[0].text, "\"return code is {}\"");
+
+        let options = ExtractOptions {
+            cpp_log_functions: vec!["strcpy".to_string()],
+            ..Default::default()
+        };
+        let code = CodeSource::from_string(&Path::new("in-mem.cc"), CPP_THROW_SOURCE);
+        let src_refs = extract_logging_with_options(&[code], &options, &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 1);
+        assert_eq!(src_refs[0].text, "\"not a log message\"");
+    }
+
+    #[test]
+    fn test_set_query() {
+        let source = r#"
+fn save(id: u32) {
+    debug!("not targeted");
+    MyLog::write!("user {} saved", id);
+}
+"#;
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("save.rs"), source).unwrap();
+        let mut log_matcher = LogMatcher::new();
+        log_matcher.add_root(dir.path()).unwrap();
+        assert!(matches!(
+            log_matcher.set_query(SourceLanguage::Rust, "(not_a_node) @log".to_string()),
+            Err(LogError::InvalidQuery { .. })
+        ));
+        assert!(matches!(
+            log_matcher.set_extract_options(ExtractOptions {
+                detect_cpp_log_macros: false,
+                queries: HashMap::from([(SourceLanguage::Rust, "(log".to_string())]),
+                ..Default::default()
+            }),
+            Err(LogError::InvalidQuery { .. })
+        ));
+        assert!(log_matcher.extract_options.detect_cpp_log_macros);
+        log_matcher
+            .set_query(
+                SourceLanguage::Rust,
+                r#"
+                (macro_invocation macro: (scoped_identifier) @macro-name
+                    (token_tree . (string_literal) @log)
+                    (#eq? @macro-name "MyLog::write")
+                )
+                "#
+                .to_string(),
+            )
+            .unwrap();
+        let tracker = ProgressTracker::new();
+        assert!(log_matcher.rescan_changed(&tracker).is_empty());
+        let src_refs = log_matcher.all_statements().collect::<Vec<_>>();
+        assert_eq!(src_refs.len(), 1);
+        assert_eq!(src_refs[0].text, r#""user {} saved""#);
+        assert_eq!(src_refs[0].vars, ["id"]);
+        let log_ref = LogRefBuilder::new().build("user 7 saved");
+        let mapping = log_matcher.match_log_statement(&log_ref).unwrap();
+        assert_eq!(mapping.variables[0].value, "7");
+    }
+
+    #[test]
+    fn test_set_cpp_log_functions() {
+        let source = r#"
+    void login(int uid) {
+        LOG("user %d logged in", uid);
+        spdlog::info("session started for {}", uid);
+        info("not logged by this project");
+        if (uid < 0) {
+            throw std::runtime_error("invalid user");
+        }
+    }
+    "#;
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("login.cc"), source).unwrap();
+        let mut log_matcher = LogMatcher::new();
+        log_matcher.add_root(dir.path()).unwrap();
+        log_matcher.set_cpp_log_functions(&["LOG", "spdlog::info"]);
+        let tracker = ProgressTracker::new();
+        assert!(log_matcher.rescan_changed(&tracker).is_empty());
+        assert_eq!(
+            log_matcher
+                .all_statements()
+                .map(|src_ref| src_ref.line_no)
+                .collect::<Vec<_>>(),
+            [3, 4]
+        );
+    }
+
+    #[test]
+    fn test_add_identifiers() {
+        let source = r#"
+    void login(int uid) {
+        emit("user %d logged in", uid, audit);
+    }
+    "#;
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("login.cc"), source).unwrap();
+        let mut log_matcher = LogMatcher::new();
+        log_matcher.add_root(dir.path()).unwrap();
+        log_matcher
+            .set_extract_options(ExtractOptions {
+                cpp_log_functions: vec!["emit".to_string()],
+                ..Default::default()
+            })
+            .unwrap();
+        log_matcher.add_identifiers(SourceLanguage::Cpp, &["Audit"]);
+        let tracker = ProgressTracker::new();
+        assert!(log_matcher.rescan_changed(&tracker).is_empty());
+        let src_ref = log_matcher.all_statements().next().unwrap();
+        assert_eq!(src_ref.vars, ["uid"]);
+
+        let code = CodeSource::from_string(&Path::new("login.cc"), source);
+        let options = ExtractOptions {
+            cpp_log_functions: vec!["emit".to_string()],
+            ..Default::default()
+        };
+        let src_refs = extract_logging_with_options(&[code], &options, &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs[0].vars, ["uid", "audit"]);
+    }
+
+    #[test]
+    fn test_duplicate_header() {
+        let header = r#"
+    inline void cache_miss(const char *key) {
+        printf("cache miss for %s", key);
+    }
+    "#;
+        for backend in [MatchBackend::RegexSet, MatchBackend::LiteralIndex] {
+            let dir = tempfile::tempdir().unwrap();
+            fs::create_dir(dir.path().join("include")).unwrap();
+            fs::write(dir.path().join("include/cache.h"), header).unwrap();
+            let tracker = ProgressTracker::new();
+            let mut log_matcher = LogMatcher::with_options(MatchOptions {
+                backend,
+                ..MatchOptions::default()
+            });
+            log_matcher.add_root(&dir.path().join("include")).unwrap();
+            assert!(log_matcher.rescan_changed(&tracker).is_empty());
+            // Extract the header a second time, like for another translation unit, and
+            // keep the statements separately.
+            let code = CodeSource::from_string(&dir.path().join("include/cache.h"), header);
+            let copy = extract_logging(&[code], &tracker).pop().unwrap();
+            log_matcher.roots.insert(
+                dir.path().join("unit2"),
+                SourceTree {
+                    tree: SourceHierTree::from(&dir.path().join("unit2")),
+                    files_with_statements: HashMap::from([(copy.id, copy)]),
+                },
+            );
+            if backend == MatchBackend::LiteralIndex {
+                log_matcher.literal_index = Some(LiteralIndex::new(&log_matcher).unwrap());
+            }
+            assert_eq!(log_matcher.all_statements().count(), 2);
+            let log_ref = LogRefBuilder::new().build("cache miss for user:42");
+            let mappings = log_matcher.match_log_statement_all(&log_ref);
+            assert_eq!(mappings.len(), 1, "{:?}", backend);
+            assert_eq!(mappings[0].variables[0].value, "user:42");
+        }
+    }
+
+    const CPP_MACRO_SOURCE: &str = r#"
+    #define LOG_INFO(fmt, ...) \
+        fprintf(stderr, "[INFO] " fmt "\n", ##__VA_ARGS__)
+    #define LOG_ONCE(fmt, ...) do { \
+        static bool logged = false; \
+        if (!logged) { LOG_INFO(fmt, __VA_ARGS__); logged = true; } \
+    } while (0)
+    #define MAX(a, b) ((a) > (b) ? (a) : (b))
+
+    void update(int x) {
+        LOG_INFO("x=%d", x);
+        LOG_ONCE("first update to %d", x);
+        MAX("not a log message", x);
+    }
+    "#;
+
+    #[test]
+    fn test_cpp_log_macros() {
+        let code = CodeSource::from_string(&Path::new("in-mem.cc"), CPP_MACRO_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(
+            src_refs
+                .iter()
+                .map(|src_ref| src_ref.text.as_str())
+                .collect::<Vec<_>>(),
+            ["\"x=%d\"", "\"first update to %d\""]
+        );
+        let log_ref = LogRefBuilder::new().build("x=42");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(vars[0].expr, "x");
+        assert_eq!(vars[0].value, "42");
+
+        let options = ExtractOptions {
+            detect_cpp_log_macros: false,
+            ..Default::default()
+        };
+        let code = CodeSource::from_string(&Path::new("in-mem.cc"), CPP_MACRO_SOURCE);
+        assert!(
+            extract_logging_with_options(&[code], &options, &ProgressTracker::new()).is_empty()
+        );
+
+        // The macros from a header are not followed into the files that include it.
+        let (header, source) = CPP_MACRO_SOURCE.split_once("void update").unwrap();
+        let sources = [
+            CodeSource::from_string(&Path::new("log.h"), header),
+            CodeSource::from_string(&Path::new("in-mem.cc"), &format!("void update{source}")),
+        ];
+        assert!(extract_logging(&sources, &ProgressTracker::new()).is_empty());
+        let mut options = ExtractOptions::default();
+        options.cpp_log_functions.push("LOG_INFO".to_string());
+        let src_refs = extract_logging_with_options(&sources, &options, &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs[0].text, "\"x=%d\"");
+    }
+
+    const CPP_CONCAT_SOURCE: &str = r#"
+    void login(int n) {
+        LOG("user " "logged "
+            "in %d", n);
+    }
+    "#;
+
+    #[test]
+    fn test_cpp_concatenated_string() {
+        let options = ExtractOptions {
+            cpp_log_functions: vec!["LOG".to_string()],
+            ..Default::default()
+        };
+        let code = CodeSource::from_string(&Path::new("in-mem.cc"), CPP_CONCAT_SOURCE);
+        let src_refs = extract_logging_with_options(&[code], &options, &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 1);
+        assert_eq!(src_refs[0].pattern, r"(?s)^user logged in ([-+ ]?\d+)$");
+        assert_eq!(src_refs[0].end_line_no, 4);
+        let log_ref = LogRefBuilder::new().build("user logged in 3");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(vars[0].expr, "n");
+        assert_eq!(vars[0].value, "3");
+        assert_eq!(vars[0].inferred_type, Some(VarType::Integer));
+
+        let source = r#"
+    void sent(uint64_t n, int fd) {
+        LOG("sent %" PRIu64 " bytes to %d", n, fd);
+        LOG("[" MODULE "] closed %d", fd);
+    }
+    "#;
+        let code = CodeSource::from_string(&Path::new("in-mem.cc"), source);
+        let src_refs = extract_logging_with_options(&[code], &options, &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 1);
+        assert_eq!(src_refs[0].vars, ["n", "fd"]);
+        let log_ref = LogRefBuilder::new().build("sent 1024 bytes to 3");
+        let vars = extract_variables(&log_ref, link_to_source(&log_ref, &src_refs).unwrap());
+        assert_eq!(vars[0].expr, "n");
+        assert_eq!(vars[0].value, "1024");
+        assert_eq!(vars[1].expr, "fd");
+        assert_eq!(vars[1].value, "3");
+    }
+
+    const CPP_GLOBAL_SOURCE: &str = r#"
+namespace audit {
+static bool registered = info("audit module registered");
+}
+
+int main() {
+    return error("exiting with %d", 1) ? 1 : 0;
+}
+
+void format(char *buf) {
+    sprintf(buf, "not a log message %d", 1);
+}
+"#;
+
+    #[test]
+    fn test_cpp_outside_statement() {
+        let code = CodeSource::from_string(&Path::new("in-mem.cc"), CPP_GLOBAL_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 2);
+        assert_eq!(src_refs[0].line_no, 3);
+        assert_eq!(src_refs[0].name, GLOBAL_NAME);
+        assert_eq!(src_refs[1].line_no, 7);
+        assert_eq!(src_refs[1].name, "main");
+        let log_ref = LogRefBuilder::new().build("audit module registered");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[0]));
+    }
+
+    const GO_SLOG_SOURCE: &str = r#"
+package main
+
+import "log/slog"
+
+func handle(user string, n int) {
+	slog.Info("request handled", "user", user, "attempt", n+1, slog.Int("n", n))
+	logger.Warn(`slow request`, "elapsed", elapsed, extra)
+	log.Printf("handled %d requests for %s", n, user)
+	logrus.WithFields(logrus.Fields{"user": user}).Info("session closed")
+	err := fmt.Errorf("bad request from %s", user)
+	fmt.Println("handled request")
+	t.Fatalf("unexpected count %d", n)
+}
+"#;
+
+    #[test]
+    fn test_go_slog() {
+        let code = CodeSource::from_string(&Path::new("in-mem.go"), GO_SLOG_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 4);
+        let log_ref = LogRefBuilder::new().build("bad request from bob");
+        assert!(link_to_source(&log_ref, &src_refs).is_none());
+        assert_eq!(src_refs[0].name, "handle");
+        assert_eq!(src_refs[0].text, "\"request handled\"");
+        assert_eq!(src_refs[0].vars, ["user", "n+1", "n"]);
+        let fields = |src_ref: &SourceRef| {
+            src_ref
+                .context_fields
+                .iter()
+                .map(|field| (field.key.clone(), field.expr.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            fields(&src_refs[0]),
+            [
+                ("user".to_string(), "user".to_string()),
+                ("attempt".to_string(), "n+1".to_string()),
+                ("n".to_string(), "n".to_string()),
+            ]
+        );
+        assert_eq!(
+            fields(&src_refs[1]),
+            [
+                ("elapsed".to_string(), "elapsed".to_string()),
+                ("!BADKEY".to_string(), "extra".to_string()),
+            ]
+        );
+
+        let log_ref = LogRefBuilder::new().build("request handled user=bob attempt=2 n=1");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[0]));
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(
+            vars.iter()
+                .map(|pair| (pair.expr.as_str(), pair.value.as_str()))
+                .collect::<Vec<_>>(),
+            [("user", "bob"), ("attempt", "2"), ("n", "1")]
+        );
+
+        let log_ref = LogRefBuilder::new().build("slow request");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[1]));
+        let extracted = extract_variables_checked(&log_ref, result);
+        assert!(extracted.variables.is_empty());
+        assert_eq!(extracted.missing, ["elapsed", "!BADKEY"]);
+
+        let log_ref = LogRefBuilder::new().build("slow request elapsed=1.5s !BADKEY=oops");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[1]));
+
+        let log_ref = LogRefBuilder::new().build("handled 3 requests for bob");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[2]));
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(
+            vars.iter()
+                .map(|pair| (pair.expr.as_str(), pair.value.as_str()))
+                .collect::<Vec<_>>(),
+            [("n", "3"), ("user", "bob")]
+        );
+
+        let log_ref = LogRefBuilder::new().build("session closed");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[3]));
+    }
+
     #[test]
-    fn test_extract_var_punctuation() {
-        let lf =
-            r"^(?<timestamp>\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}) (?<level>\w+)\s+ (?<file>[\w$.]+):(?<line>\d+) - (?<body>.*)$".try_into().unwrap();
-        let log_ref = from_log_format_and_line(
-            "2025-04-10 22:12:52 INFO  JvmPauseMonitor:146 - JvmPauseMonitor-n0: Started",
-            lf,
+    fn test_escaped_braces() {
+        let code = CodeSource::from_string(
+            &Path::new("in-mem.rs"),
+            "fn main() {\n    debug!(\"set {{key}} to {}\", v);\n}\n",
         );
-        let code = CodeSource::from_string(&PathBuf::from("in-mem.java"), TEST_PUNC_SRC);
         let src_refs = extract_logging(&[code], &ProgressTracker::new())
             .pop()
             .unwrap()
             .log_statements;
-        assert_eq!(src_refs.len(), 2);
+        let log_ref = LogRefBuilder::new().build("set {key} to 5");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(
+            vars.iter()
+                .map(|pair| (pair.expr.as_str(), pair.value.as_str()))
+                .collect::<Vec<_>>(),
+            [("v", "5")]
+        );
+    }
+
+    #[test]
+    fn test_slf4j_placeholders() {
+        let code = CodeSource::from_string(
+            &Path::new("in-mem.java"),
+            r#"
+class Audit {
+    void record() {
+        log.info("user {} did {} to {}", user.getName(), action, target);
+    }
+}
+"#,
+        );
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs[0].pattern, "(?s)^user (.+) did (.+) to (.+)$");
+        let log_ref = LogRefBuilder::new().build("user bob did delete to /tmp/x");
         let vars = extract_variables(&log_ref, &src_refs[0]);
         assert_eq!(
-            vars,
-            vec![VariablePair {
-                expr: "this".to_string(),
-                value: "JvmPauseMonitor-n0".to_string()
-            },]
+            vars.iter()
+                .map(|pair| (pair.expr.as_str(), pair.value.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                ("user.getName()", "bob"),
+                ("action", "delete"),
+                ("target", "/tmp/x")
+            ]
         );
     }
 
-    const CPP_SOURCE: &str = r#"
-    #include <stdio.h>
+    #[test]
+    fn test_slf4j_throwable() {
+        let code = CodeSource::from_string(
+            &Path::new("in-mem.java"),
+            r#"
+class Fetcher {
+    void fetch() {
+        log.error("failed for {}", id, ex);
+        log.warn("retrying {} of {}", attempt, max);
+    }
+}
+"#,
+        );
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs[0].vars, ["id"]);
+        assert_eq!(src_refs[0].exception.as_deref(), Some("ex"));
+        assert_eq!(src_refs[1].exception, None);
+        let log_ref = LogRefBuilder::new().build("failed for 42");
+        let vars = extract_variables(&log_ref, &src_refs[0]);
+        assert_eq!(vars.len(), 1);
+        assert_eq!(vars[0].expr, "id");
+    }
 
-    int main(int argc, char* argv[]) {
-        printf("Hello, %s!", argv[1]);
+    const CSHARP_SOURCE: &str = r#"
+public class UserService
+{
+    public void Login(int userId)
+    {
+        _logger.LogInformation("User {UserId} logged in", userId);
+        _logger.LogError(ex, "Login failed for {0}", userId);
+        Log.Warning("Disk at {Percent:P1}", used);
     }
-    "#;
+}
+"#;
 
     #[test]
-    fn test_basic_cpp() {
-        let log_ref = LogRefBuilder::new().build("Hello, Steve!");
-        let code = CodeSource::from_string(&Path::new("in-mem.cc"), CPP_SOURCE);
+    fn test_csharp() {
+        let code = CodeSource::from_string(&Path::new("in-mem.cs"), CSHARP_SOURCE);
         let src_refs = extract_logging(&[code], &ProgressTracker::new())
             .pop()
             .unwrap()
             .log_statements;
-        assert_eq!(src_refs.len(), 1);
-        let vars = extract_variables(&log_ref, &src_refs[0]);
+        assert_eq!(src_refs.len(), 3);
+        assert_eq!(src_refs[0].language, SourceLanguage::CSharp);
+        assert_eq!(src_refs[0].name, "Login");
         assert_eq!(
-            vars,
-            vec![VariablePair {
-                expr: "argv[1]".to_string(),
-                value: "Steve".to_string()
-            },]
+            src_refs[0].args,
+            [FormatArgument::Named("UserId".to_string())]
+        );
+        assert_eq!(src_refs[0].vars, ["userId"]);
+        assert_eq!(src_refs[0].level.as_deref(), Some("info"));
+        assert_eq!(src_refs[1].level.as_deref(), Some("error"));
+        assert_eq!(src_refs[1].vars, ["userId"]);
+
+        let log_ref = LogRefBuilder::new().build("User 42 logged in");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[0]));
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(vars[0].expr, "UserId");
+        assert_eq!(vars[0].value, "42");
+
+        let log_ref = LogRefBuilder::new().build("Login failed for 42");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert_eq!(extract_variables(&log_ref, result)[0].expr, "userId");
+    }
+
+    const SWIFT_SOURCE: &str = r#"
+import os
+
+class Sync {
+    let logger = Logger(subsystem: "com.example", category: "sync")
+
+    func finish(count: Int) {
+        logger.info("Synced \(count) items")
+        logger.fault("sync failed")
+        print("not a log message \(count)")
+    }
+}
+"#;
+
+    #[test]
+    fn test_swift() {
+        let code = CodeSource::from_string(&Path::new("in-mem.swift"), SWIFT_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 2);
+        assert_eq!(src_refs[0].language, SourceLanguage::Swift);
+        assert_eq!(src_refs[0].name, "finish");
+        assert_eq!(src_refs[0].line_no, 8);
+        assert_eq!(
+            src_refs[0].args,
+            [FormatArgument::Named("count".to_string())]
+        );
+        assert_eq!(src_refs[0].level.as_deref(), Some("info"));
+
+        let log_ref = LogRefBuilder::new().build("Synced 12 items");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[0]));
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(vars[0].expr, "count");
+        assert_eq!(vars[0].value, "12");
+    }
+
+    const JS_SOURCE: &str = r#"
+function login(id, attempts) {
+    console.log(`user ${id} logged in`);
+    logger.info({ attempts }, "login took %d tries", attempts);
+    console.warn('session expired');
+    format(`not a log message ${id}`);
+}
+"#;
+
+    #[test]
+    fn test_javascript() {
+        for (name, language) in [
+            ("in-mem.js", SourceLanguage::JavaScript),
+            ("in-mem.ts", SourceLanguage::TypeScript),
+        ] {
+            let code = CodeSource::from_string(&Path::new(name), JS_SOURCE);
+            let src_refs = extract_logging(&[code], &ProgressTracker::new())
+                .pop()
+                .unwrap()
+                .log_statements;
+            assert_eq!(src_refs.len(), 3);
+            assert_eq!(src_refs[0].language, language);
+            assert_eq!(src_refs[0].line_no, 3);
+            assert_eq!(src_refs[0].name, "login");
+            assert_eq!(src_refs[0].args, [FormatArgument::Named("id".to_string())]);
+            assert_eq!(src_refs[1].level.as_deref(), Some("info"));
+            assert_eq!(src_refs[2].level.as_deref(), Some("warn"));
+
+            let log_ref = LogRefBuilder::new().build("user 42 logged in");
+            let result = link_to_source(&log_ref, &src_refs).unwrap();
+            assert!(ptr::eq(result, &src_refs[0]));
+            let vars = extract_variables(&log_ref, result);
+            assert_eq!(vars[0].expr, "id");
+            assert_eq!(vars[0].value, "42");
+
+            let log_ref = LogRefBuilder::new().build("login took 3 tries");
+            let result = link_to_source(&log_ref, &src_refs).unwrap();
+            assert_eq!(extract_variables(&log_ref, result)[0].expr, "attempts");
+        }
+    }
+
+    #[test]
+    fn test_enclosing_name() {
+        for (name, source, expected) in [
+            (
+                "Server.java",
+                r#"
+class Server {
+    Server() {
+        logger.info("created");
+    }
+
+    void start() {
+        logger.info("started");
+    }
+}
+"#,
+                &["Server", "start"][..],
+            ),
+            (
+                "server.cpp",
+                r#"
+static void helper() {
+    printf("helping\n");
+}
+
+void Server::start(int port) {
+    info("listening on %d", port);
+}
+"#,
+                &["helper", "Server::start"][..],
+            ),
+            (
+                "server.py",
+                r#"
+logging.info("module loaded")
+
+def start():
+    logging.info("started")
+"#,
+                &[GLOBAL_NAME, "start"][..],
+            ),
+            (
+                "server.js",
+                r#"
+console.log("module loaded");
+
+const start = (port) => {
+    console.log(`listening on ${port}`);
+};
+
+class Server {
+    stop() {
+        console.log("stopped");
+    }
+}
+"#,
+                &[GLOBAL_NAME, "start", "stop"][..],
+            ),
+            (
+                "server.go",
+                r#"
+package main
+
+func (s *Server) Start() {
+    log.Printf("started %d", s.port)
+}
+"#,
+                &["Start"][..],
+            ),
+        ] {
+            let code = CodeSource::from_string(&Path::new(name), source);
+            let src_refs = extract_logging(&[code], &ProgressTracker::new())
+                .pop()
+                .unwrap()
+                .log_statements;
+            assert_eq!(
+                src_refs
+                    .iter()
+                    .map(|src_ref| src_ref.name.as_str())
+                    .collect::<Vec<_>>(),
+                expected,
+                "{name}"
+            );
+        }
+    }
+
+    const RUBY_SOURCE: &str = r#"
+class SessionsController < ApplicationController
+  def create
+    Rails.logger.info("user #{user.id} logged in from #{request.remote_ip}")
+    logger.warn "session expired"
+    puts "not a log message #{id}"
+  end
+end
+"#;
+
+    #[test]
+    fn test_ruby() {
+        let code = CodeSource::from_string(&Path::new("in-mem.rb"), RUBY_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 2);
+        assert_eq!(src_refs[0].language, SourceLanguage::Ruby);
+        assert_eq!(src_refs[0].line_no, 4);
+        assert_eq!(src_refs[0].name, "create");
+        assert_eq!(
+            src_refs[0].args,
+            [
+                FormatArgument::Named("user.id".to_string()),
+                FormatArgument::Named("request.remote_ip".to_string())
+            ]
+        );
+        assert_eq!(src_refs[0].level.as_deref(), Some("info"));
+        assert_eq!(src_refs[1].line_no, 5);
+        assert_eq!(src_refs[1].level.as_deref(), Some("warn"));
+
+        let log_ref = LogRefBuilder::new().build("user 42 logged in from 10.0.0.1");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[0]));
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(
+            vars.iter()
+                .map(|pair| (pair.expr.as_str(), pair.value.as_str()))
+                .collect::<Vec<_>>(),
+            [("user.id", "42"), ("request.remote_ip", "10.0.0.1")]
+        );
+        let log_ref = LogRefBuilder::new().build("session expired");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[1]));
+    }
+
+    const KOTLIN_SOURCE: &str = r#"
+class MainActivity : AppCompatActivity() {
+    fun greet(name: String) {
+        Log.d(TAG, "Hello %s", name)
+        Timber.w("user $name has ${user.unread} messages")
+        Log.e("Log.e", "cannot reach $host")
+        Timber.i("""cache at C:\tmp\$name""")
+        println("not a log message $name")
+    }
+}
+"#;
+
+    #[test]
+    fn test_kotlin() {
+        let code = CodeSource::from_string(&Path::new("in-mem.kt"), KOTLIN_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 4);
+        assert_eq!(src_refs[0].language, SourceLanguage::Kotlin);
+        assert_eq!(src_refs[0].line_no, 4);
+        assert_eq!(src_refs[0].name, "greet");
+        assert_eq!(src_refs[0].text, r#""Hello %s""#);
+        assert_eq!(src_refs[0].vars, ["name"]);
+        assert_eq!(src_refs[0].level.as_deref(), Some("debug"));
+        assert_eq!(
+            src_refs[1].args,
+            [
+                FormatArgument::Named("name".to_string()),
+                FormatArgument::Named("user.unread".to_string())
+            ]
+        );
+        assert_eq!(src_refs[1].level.as_deref(), Some("warn"));
+        assert_eq!(
+            src_refs[2].args,
+            [FormatArgument::Named("host".to_string())]
+        );
+        assert_eq!(
+            src_refs[3].args,
+            [FormatArgument::Named("name".to_string())]
+        );
+
+        let log_ref = LogRefBuilder::new().build("Hello Tim");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[0]));
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(vars[0].expr, "name");
+        assert_eq!(vars[0].value, "Tim");
+
+        let log_ref = LogRefBuilder::new().build("user Tim has 3 messages");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[1]));
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(
+            vars.iter()
+                .map(|pair| (pair.expr.as_str(), pair.value.as_str()))
+                .collect::<Vec<_>>(),
+            [("name", "Tim"), ("user.unread", "3")]
+        );
+        let log_ref = LogRefBuilder::new().build(r"cache at C:\tmp\Tim");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[3]));
+    }
+
+    const SCALA_SOURCE: &str = r#"
+object Loader {
+  def load(count: Int): Unit = {
+    logger.info(s"processed $count rows")
+    logger.warn(s"took ${timer.secs}s, $$${cost} spent")
+    logger.debug("loaded {} tables", tables.size)
+    println(s"not a log message $count")
+  }
+}
+"#;
+
+    #[test]
+    fn test_scala() {
+        let code = CodeSource::from_string(&Path::new("in-mem.scala"), SCALA_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 3);
+        assert_eq!(src_refs[0].language, SourceLanguage::Scala);
+        assert_eq!(src_refs[0].line_no, 4);
+        assert_eq!(src_refs[0].name, "load");
+        assert_eq!(
+            src_refs[0].args,
+            [FormatArgument::Named("count".to_string())]
+        );
+        assert_eq!(src_refs[0].level.as_deref(), Some("info"));
+        assert_eq!(
+            src_refs[1].args,
+            [
+                FormatArgument::Named("timer.secs".to_string()),
+                FormatArgument::Named("cost".to_string())
+            ]
+        );
+        assert_eq!(src_refs[2].vars, ["tables.size"]);
+
+        let log_ref = LogRefBuilder::new().build("processed 1024 rows");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[0]));
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(vars[0].expr, "count");
+        assert_eq!(vars[0].value, "1024");
+
+        let log_ref = LogRefBuilder::new().build("took 3s, $12 spent");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[1]));
+        let log_ref = LogRefBuilder::new().build("loaded 7 tables");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[2]));
+    }
+
+    const TRACING_SOURCE: &str = r#"
+fn handle(id: u64, peer: &str, n: usize) {
+    tracing::info!(user = %id, peer, "handled {} requests", n);
+    tracing::event!(Level::WARN, retries = ?n, target: "net", "connection reset");
+    debug!("plain {}", "literal");
+}
+"#;
+
+    #[test]
+    fn test_tracing() {
+        let code = CodeSource::from_string(&Path::new("in-mem.rs"), TRACING_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(
+            src_refs
+                .iter()
+                .map(|src_ref| src_ref.text.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "\"handled {} requests\"",
+                "\"connection reset\"",
+                "\"plain {}\""
+            ]
+        );
+        assert_eq!(
+            src_refs[0].args,
+            [
+                FormatArgument::Positional(0),
+                FormatArgument::Named("user".to_string()),
+                FormatArgument::Named("peer".to_string()),
+            ]
         );
+        assert_eq!(src_refs[0].vars, ["n", "id", "peer"]);
+        assert_eq!(src_refs[0].level.as_deref(), Some("info"));
+        assert_eq!(src_refs[1].level.as_deref(), Some("warn"));
+
+        let log_ref = LogRefBuilder::new().build("handled 3 requests user=42 peer=10.0.0.1");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[0]));
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(
+            vars.iter()
+                .map(|pair| (pair.expr.as_str(), pair.value.as_str()))
+                .collect::<Vec<_>>(),
+            [("n", "3"), ("user", "42"), ("peer", "10.0.0.1")]
+        );
+
+        let log_ref = LogRefBuilder::new().build("connection reset retries=2");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[1]));
+
+        let log_ref = LogRefBuilder::new().build("handled 3 requests");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[0]));
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(vars.len(), 1);
+        assert_eq!(vars[0].value, "3");
+
+        let code = CodeSource::from_string(
+            &Path::new("in-mem.rs"),
+            "fn run() {\n    info!(user = %id, \"processing {}\", job);\n}\n",
+        );
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        for (line, expected) in [
+            (
+                "processing job-7 user=42",
+                vec![("job", "job-7"), ("user", "42")],
+            ),
+            ("processing job-7", vec![("job", "job-7")]),
+        ] {
+            let log_ref = LogRefBuilder::new().build(line);
+            let vars = extract_variables(&log_ref, link_to_source(&log_ref, &src_refs).unwrap());
+            assert_eq!(
+                vars.iter()
+                    .map(|pair| (pair.expr.as_str(), pair.value.as_str()))
+                    .collect::<Vec<_>>(),
+                expected
+            );
+        }
     }
 
     const PYTHON_SOURCE: &str = r#"
@@ -1152,11 +6693,97 @@ processing \started -- {args[0]}""")
             vars,
             vec![VariablePair {
                 expr: "test_var".to_string(),
-                value: "bar".to_string()
+                value: "bar".to_string(),
+                truncated: false,
+                placeholder_span: None,
+                inferred_type: Some(VarType::String),
             },]
         );
     }
 
+    const PYTHON_FSTRING_SOURCE: &str = r#"
+import logging
+
+logger = logging.getLogger(__name__)
+
+def login(uid, attempts):
+    print("not a log message")
+    logger.info(f"user {uid} logged in")
+    logging.debug("user %s took %d attempts", uid, attempts)
+"#;
+
+    #[test]
+    fn test_python_fstring() {
+        let code = CodeSource::from_string(&Path::new("in-mem.pyi"), PYTHON_FSTRING_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 2);
+        assert_eq!(src_refs[0].name, "login");
+        assert_eq!(src_refs[0].args, [FormatArgument::Named("uid".to_string())]);
+
+        let log_ref = LogRefBuilder::new().build("user alice logged in");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        assert!(ptr::eq(result, &src_refs[0]));
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(vars[0].expr, "uid");
+        assert_eq!(vars[0].value, "alice");
+
+        let log_ref = LogRefBuilder::new().build("user bob took 3 attempts");
+        let result = link_to_source(&log_ref, &src_refs).unwrap();
+        let vars = extract_variables(&log_ref, result);
+        assert_eq!(
+            vars.iter()
+                .map(|pair| (pair.expr.as_str(), pair.value.as_str()))
+                .collect::<Vec<_>>(),
+            [("uid", "bob"), ("attempts", "3")]
+        );
+    }
+
+    const PYTHON_EXTRA_SOURCE: &str = r#"
+def handle(request, rid):
+    logger.info("handling %s", request.path, extra={"request_id": rid, 'peer': request.peer()}, exc_info=True)
+    logger.warning("retrying %s", request.path, extra=context)
+"#;
+
+    #[test]
+    fn test_python_extra() {
+        let code = CodeSource::from_string(&Path::new("in-mem.py"), PYTHON_EXTRA_SOURCE);
+        let src_refs = extract_logging(&[code], &ProgressTracker::new())
+            .pop()
+            .unwrap()
+            .log_statements;
+        assert_eq!(src_refs.len(), 2);
+        assert_eq!(src_refs[0].vars, ["request.path"]);
+        assert_eq!(
+            src_refs[0].context_fields,
+            [
+                ContextField {
+                    key: "request_id".to_string(),
+                    expr: "rid".to_string(),
+                },
+                ContextField {
+                    key: "peer".to_string(),
+                    expr: "request.peer()".to_string(),
+                },
+            ]
+        );
+        assert_eq!(src_refs[1].vars, ["request.path"]);
+        assert_eq!(
+            src_refs[1].context_fields,
+            [ContextField {
+                key: "extra".to_string(),
+                expr: "context".to_string(),
+            }]
+        );
+
+        let log_ref = LogRefBuilder::new().build("handling /index.html");
+        let vars = extract_variables(&log_ref, &src_refs[0]);
+        assert_eq!(vars[0].expr, "request.path");
+        assert_eq!(vars[0].value, "/index.html");
+    }
+
     const TRACE: &str = r#"JvmPauseMonitor-n0: Started
 java.lang.IllegalStateException: simulated failure for demo
     at org.example.Main.simulateError(Main.java:50)
@@ -0,0 +1,297 @@
+use aho_corasick::AhoCorasick;
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::source_hier::SourceFileID;
+use crate::{line_distance, LogDetails, LogError, LogMatcher, LogRef, SourceRef};
+
+/// The location of a log statement within a LogMatcher.
+struct StatementLocation {
+    root: PathBuf,
+    file: SourceFileID,
+    index: usize,
+}
+
+/// An index over the longest literal run of every statement in a LogMatcher.  A single
+/// Aho-Corasick automaton is used to quickly narrow down the statements that could match
+/// a log message across all files.  The candidates are then verified using the precise
+/// regex for the statement.
+pub(crate) struct LiteralIndex {
+    automaton: AhoCorasick,
+    /// The statement for each pattern in the automaton.
+    locations: Vec<StatementLocation>,
+    /// Statements without any literal text, which always need to be checked.
+    unindexed: Vec<StatementLocation>,
+    /// True if the literals and messages are compared with their case folded.
+    case_insensitive: bool,
+}
+
+impl LiteralIndex {
+    pub(crate) fn new(log_matcher: &LogMatcher) -> Result<Self, LogError> {
+        let case_insensitive = log_matcher.options.case_insensitive;
+        let mut literals = Vec::new();
+        let mut locations = Vec::new();
+        let mut unindexed = Vec::new();
+        for (root, coll) in &log_matcher.roots {
+            for (file, stmts) in &coll.files_with_statements {
+                for (index, src_ref) in stmts.log_statements.iter().enumerate() {
+                    let location = StatementLocation {
+                        root: root.clone(),
+                        file: *file,
+                        index,
+                    };
+                    if src_ref.literal.is_empty() {
+                        unindexed.push(location);
+                    } else if case_insensitive {
+                        literals.push(fold_case(&src_ref.literal));
+                        locations.push(location);
+                    } else {
+                        literals.push(src_ref.literal.clone());
+                        locations.push(location);
+                    }
+                }
+            }
+        }
+        let automaton =
+            AhoCorasick::new(literals).map_err(|source| LogError::CannotBuildLiteralIndex {
+                source: Arc::new(source),
+            })?;
+        Ok(Self {
+            automaton,
+            locations,
+            unindexed,
+            case_insensitive,
+        })
+    }
+
+    /// The number of bytes of memory used by the index.
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.automaton.memory_usage()
+            + (self.locations.capacity() + self.unindexed.capacity())
+                * size_of::<StatementLocation>()
+            + self
+                .locations
+                .iter()
+                .chain(self.unindexed.iter())
+                .map(|location| location.root.capacity())
+                .sum::<usize>()
+    }
+
+    /// Find the statements that match the given log message.
+    pub(crate) fn candidates<'m>(
+        &self,
+        log_matcher: &'m LogMatcher,
+        log_ref: &LogRef,
+    ) -> Vec<&'m SourceRef> {
+        let body = log_ref.body();
+        let filename = match log_ref.details {
+            Some(LogDetails {
+                file: Some(filename),
+                body: Some(_),
+                ..
+            }) => Some(filename),
+            _ => None,
+        };
+        let folded = self.case_insensitive.then(|| fold_case(body));
+        let hits = self
+            .automaton
+            .find_overlapping_iter(folded.as_deref().unwrap_or(body))
+            .map(|m| m.pattern().as_usize())
+            .collect::<BTreeSet<usize>>();
+        let lineno = log_ref.details.and_then(|details| details.lineno);
+        // The statements are ranked the same as by the RegexSet backend, by the distance to
+        // the line number hint and then by how specific they are.
+        let mut matches = Vec::new();
+        for location in hits
+            .into_iter()
+            .map(|hit| &self.locations[hit])
+            .chain(self.unindexed.iter())
+        {
+            let Some(stmts) = log_matcher
+                .roots
+                .get(&location.root)
+                .and_then(|coll| coll.files_with_statements.get(&location.file))
+            else {
+                continue;
+            };
+            if filename.is_some_and(|filename| !stmts.matches_file_hint(filename)) {
+                continue;
+            }
+            let src_ref = &stmts.log_statements[location.index];
+            if !src_ref.is_match(body) {
+                continue;
+            }
+            let rank = (
+                lineno.map_or(0, |lineno| line_distance(src_ref, lineno)),
+                Reverse(src_ref.quality),
+                location.index,
+            );
+            matches.push((rank, src_ref));
+        }
+        matches.sort_by_key(|(rank, _src_ref)| *rank);
+        matches
+            .into_iter()
+            .map(|(_rank, src_ref)| src_ref)
+            .collect()
+    }
+}
+
+/// Fold the case of each character in the given text, like the simple case folding that
+/// the regexes use for case-insensitive patterns, so that a literal is found in a message
+/// regardless of case, even outside of ASCII.  For example, `Σ`, `σ`, and `ς` are all
+/// folded to `σ`.  Characters that only change case as a sequence, like `ß`, are kept.
+fn fold_case(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            single_char(c.to_uppercase())
+                .and_then(|upper| single_char(upper.to_lowercase()))
+                .unwrap_or(c)
+        })
+        .collect()
+}
+
+/// Get the only character from the given case mapping, if it is a single character.
+fn single_char(mut chars: impl Iterator<Item = char>) -> Option<char> {
+    let c = chars.next()?;
+    chars.next().is_none().then_some(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::matcher_for_roots;
+    use crate::{LogMatcher, LogRefBuilder, MatchBackend, MatchOptions};
+    use std::fmt::Write;
+    use std::time::Instant;
+    use tempfile::TempDir;
+
+    const SOURCE: &str = r#"
+fn main() {
+    debug!("you're only as funky as your last cut");
+    debug!("this won't match i={}; j={}", i, j);
+    debug!("{salutation}, {name}!");
+    debug!("Hello, {name}!");
+    warn!("low disk space\ton {}", disk);
+}
+"#;
+
+    fn build_matcher(sources: &[(&str, &str)], backend: MatchBackend) -> (TempDir, LogMatcher) {
+        matcher_for_roots(
+            sources,
+            &[""],
+            MatchOptions {
+                backend,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_same_as_regex_set() {
+        let other = SOURCE.replace("Hello", "Goodbye");
+        let sources = [("main.rs", SOURCE), ("other.rs", other.as_str())];
+        let (_regex_set_dir, regex_set) = build_matcher(&sources, MatchBackend::RegexSet);
+        let (_literal_index_dir, literal_index) =
+            build_matcher(&sources, MatchBackend::LiteralIndex);
+        for line in [
+            "you're only as funky as your last cut",
+            "this won't match i=1; j=2",
+            "Howdy, Tim!",
+            "Hello, Tim!",
+            "Goodbye, Tim!",
+            "low disk space\ton /dev/sda1",
+            "low disk space",
+            "not in the source",
+        ] {
+            let log_ref = LogRefBuilder::new().build(line);
+            let expected = regex_set
+                .match_log_statement(&log_ref)
+                .and_then(|mapping| mapping.src_ref)
+                .map(|src_ref| (src_ref.text, src_ref.quality));
+            let actual = literal_index
+                .match_log_statement(&log_ref)
+                .and_then(|mapping| mapping.src_ref)
+                .map(|src_ref| (src_ref.text, src_ref.quality));
+            assert_eq!(expected, actual, "line: {}", line);
+        }
+    }
+
+    #[test]
+    fn test_case_insensitive_same_as_regex_set() {
+        let source = r#"
+fn main() {
+    warn!("Größe überschritten für {}", name);
+    info!("ΟΔΟΣ κλειστή");
+    debug!("task {} is OK", id);
+}
+"#;
+        let matchers = [MatchBackend::RegexSet, MatchBackend::LiteralIndex].map(|backend| {
+            matcher_for_roots(
+                &[("main.rs", source)],
+                &[""],
+                MatchOptions {
+                    backend,
+                    case_insensitive: true,
+                    ..Default::default()
+                },
+            )
+        });
+        for line in [
+            "GRÖSSE ÜBERSCHRITTEN FÜR x",
+            "GRÖẞE ÜBERSCHRITTEN FÜR x",
+            "größe überschritten für x",
+            "οδος ΚΛΕΙΣΤΉ",
+            "οδοσ κλειστή",
+            "TASK 1 IS O\u{212A}",
+        ] {
+            let log_ref = LogRefBuilder::new().build(line);
+            let [expected, actual] = matchers.each_ref().map(|(_dir, log_matcher)| {
+                log_matcher
+                    .match_log_statement(&log_ref)
+                    .and_then(|mapping| mapping.src_ref)
+                    .map(|src_ref| src_ref.line_no)
+            });
+            assert_eq!(expected, actual, "line: {}", line);
+        }
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with --ignored --nocapture"]
+    fn bench_large_index() {
+        let mut sources = Vec::new();
+        for file_index in 0..200 {
+            let mut source = String::from("fn main() {\n");
+            for stmt_index in 0..100 {
+                writeln!(
+                    source,
+                    "    debug!(\"file {file_index} statement {stmt_index} value={{}}\", v);"
+                )
+                .unwrap();
+            }
+            source.push_str("}\n");
+            sources.push((format!("file{}.rs", file_index), source));
+        }
+        let sources = sources
+            .iter()
+            .map(|(name, source)| (name.as_str(), source.as_str()))
+            .collect::<Vec<_>>();
+        let lines = (0..1000)
+            .map(|n| format!("file {} statement {} value={}", n % 200, n % 100, n))
+            .collect::<Vec<_>>();
+        for backend in [MatchBackend::RegexSet, MatchBackend::LiteralIndex] {
+            let (_dir, log_matcher) = build_matcher(&sources, backend);
+            let start = Instant::now();
+            for line in &lines {
+                let log_ref = LogRefBuilder::new().build(line);
+                assert!(log_matcher.match_log_statement(&log_ref).is_some());
+            }
+            eprintln!(
+                "{:?}: matched {} lines in {:?}",
+                backend,
+                lines.len(),
+                start.elapsed()
+            );
+        }
+    }
+}
@@ -1,27 +1,228 @@
 use regex::{Captures, Regex, RegexBuilder};
+use serde_json::value::RawValue;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 
-use crate::LogError;
+use crate::{LogError, LogRef, LogRefBuilder, VariablePair};
 
 #[derive(Clone, Debug)]
 pub struct LogFormat {
-    regex: Regex,
+    kind: FormatKind,
+    /// True if the body capture of a regex format is the contents of a quoted string
+    /// whose escape sequences need to be decoded.
+    escaped_body: bool,
 }
 
+#[derive(Clone, Debug)]
+enum FormatKind {
+    /// A regex with named captures for the parts of a message.
+    Regex(Regex),
+    /// A JSON object per line with the parts of a message in the given keys.
+    Json {
+        body_key: String,
+        file_key: String,
+        line_key: String,
+    },
+}
+
+/// The names and regexes of the formats for common logging libraries, along with whether
+/// the body is a quoted string.
+const PRESETS: &[(&str, &str, bool)] = &[
+    (
+        "log4j",
+        r"^(?<timestamp>\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}(?:,\d{3})?) (?<level>\w+)\s+(?<file>[\w$.]+):(?<line>\d+) - (?<body>.*)$",
+        false,
+    ),
+    (
+        "env_logger",
+        r"^\[(?:(?<timestamp>\S+) )?(?<level>[A-Z]+)\s+(?:(?<file>[^\s:\]]+):(?<line>\d+)|[^\s\]]+)\] (?<body>.*)$",
+        false,
+    ),
+    (
+        "logrus-text",
+        r#"^time="(?<timestamp>[^"]*)" level=(?<level>\w+) msg="(?<body>(?:[^"\\]|\\.)*)"(?:.*? func=(?<method>\S+))?(?:.*? file="(?<file>[^"]+):(?<line>\d+)")?"#,
+        true,
+    ),
+    (
+        "syslog",
+        r"^(?<timestamp>\w{3} [ \d]\d \d{2}:\d{2}:\d{2}) \S+ [^\s:]+: (?<body>.*)$",
+        false,
+    ),
+];
+
 impl LogFormat {
-    pub fn has_src_hint(self: LogFormat) -> bool {
-        let mut flatten = self.regex.capture_names().flatten();
-        flatten.any(|name| name == "line") && flatten.any(|name| name == "file")
+    /// Get the format for a common logging library by name, one of `log4j`, `env_logger`,
+    /// `logrus-text`, or `syslog`.
+    pub fn preset(name: &str) -> Option<Self> {
+        PRESETS
+            .iter()
+            .find(|(preset, _regex, _escaped)| *preset == name)
+            .map(|(_preset, regex, escaped)| LogFormat {
+                escaped_body: *escaped,
+                ..LogFormat::new(regex).expect("preset is valid")
+            })
+    }
+
+    /// The names of the formats that are available through [`preset`](LogFormat::preset).
+    pub fn preset_names() -> impl Iterator<Item = &'static str> {
+        PRESETS.iter().map(|(name, _regex, _escaped)| *name)
+    }
+
+    /// Create a format from a regex with named captures for the parts of a message.  The
+    /// `body` capture is required, the others are: timestamp, thread, level, file, line,
+    /// and method.
+    pub fn new(pattern: &str) -> Result<Self, LogError> {
+        Self::try_from(pattern)
+    }
+
+    /// Create a format for logs that are written as one JSON object per line, like
+    /// `{"msg":"user logged in","file":"auth.rs","line":42}`.
+    pub fn json(body_key: &str, file_key: &str, line_key: &str) -> Self {
+        LogFormat {
+            kind: FormatKind::Json {
+                body_key: body_key.to_string(),
+                file_key: file_key.to_string(),
+                line_key: line_key.to_string(),
+            },
+            escaped_body: true,
+        }
+    }
+
+    pub fn has_src_hint(&self) -> bool {
+        self.missing_optional_groups().is_empty()
+    }
+
+    /// Get the names of the `file` and `line` groups that this format does not have.  The
+    /// format is still valid without them, but the messages can then only be matched by
+    /// their body, so callers may want to warn about it.
+    pub fn missing_optional_groups(&self) -> Vec<&'static str> {
+        match &self.kind {
+            FormatKind::Regex(regex) => ["file", "line"]
+                .into_iter()
+                .filter(|group| !regex.capture_names().flatten().any(|name| name == *group))
+                .collect(),
+            FormatKind::Json { .. } => Vec::new(),
+        }
+    }
+
+    /// Check that messages can be parsed with this format, which needs a `body` group.
+    /// A format without `file` and `line` groups is still valid, see
+    /// [`missing_optional_groups`](LogFormat::missing_optional_groups).
+    pub fn validate(&self) -> Result<(), LogError> {
+        match &self.kind {
+            FormatKind::Regex(regex)
+                if !regex.capture_names().flatten().any(|name| name == "body") =>
+            {
+                Err(LogError::LogFormatMissingGroup {
+                    name: "body".to_string(),
+                })
+            }
+            _ => Ok(()),
+        }
     }
 
     pub fn is_match(&self, line: &str) -> bool {
-        self.regex.is_match(line)
+        match &self.kind {
+            FormatKind::Regex(regex) => regex.is_match(line),
+            FormatKind::Json { .. } => self.parse(line).is_some(),
+        }
     }
 
+    /// Get the regex captures for the parts of a message.  This is always `None` for a
+    /// JSON format, use [`parse`](LogFormat::parse) instead.
     pub fn captures<'a>(&self, line: &'a str) -> Option<Captures<'a>> {
-        self.regex.captures(line)
+        match &self.kind {
+            FormatKind::Regex(regex) => regex.captures(line),
+            FormatKind::Json { .. } => None,
+        }
+    }
+
+    /// Parse a log message into its parts, if it is in this format.  Since the result
+    /// borrows from the message, escape sequences in a quoted body, like in JSON, are left
+    /// as they are and decoded when the message is matched, see
+    /// [`LogRef::unescaped_body`].
+    pub fn parse<'a>(&self, line: &'a str) -> Option<LogRef<'a>> {
+        match &self.kind {
+            FormatKind::Regex(regex) => regex
+                .captures(line)
+                .filter(|captures| captures.name("body").is_some())
+                .map(|captures| {
+                    LogRefBuilder::new()
+                        .with_escaped_body(self.escaped_body)
+                        .build_from_captures(captures, line)
+                }),
+            FormatKind::Json {
+                body_key,
+                file_key,
+                line_key,
+            } => {
+                let fields = json_fields(line)?;
+                let body = fields.get(body_key).and_then(|raw| json_str(raw))?;
+                Some(
+                    LogRefBuilder::new()
+                        .with_file(fields.get(file_key).and_then(|raw| json_str(raw)))
+                        .with_lineno(
+                            fields
+                                .get(line_key)
+                                .and_then(|raw| json_str(raw).unwrap_or(raw.get()).parse().ok()),
+                        )
+                        .with_body(Some(body))
+                        .with_escaped_body(true)
+                        .build(line),
+                )
+            }
+        }
+    }
+
+    /// Get the fields of a JSON log message, other than the body, file, and line, as
+    /// variables.  String values are unescaped and other values are kept as JSON text.
+    pub fn extra_fields(&self, line: &str) -> Vec<VariablePair> {
+        let FormatKind::Json {
+            body_key,
+            file_key,
+            line_key,
+        } = &self.kind
+        else {
+            return Vec::new();
+        };
+        let Some(fields) = json_fields(line) else {
+            return Vec::new();
+        };
+        fields
+            .into_iter()
+            .filter(|(key, _raw)| ![body_key, file_key, line_key].contains(&key))
+            .map(|(key, raw)| VariablePair {
+                expr: key,
+                value: serde_json::from_str::<String>(raw.get())
+                    .unwrap_or_else(|_| raw.get().to_string()),
+                truncated: false,
+                placeholder_span: None,
+                inferred_type: None,
+            })
+            .collect()
     }
 }
 
+/// Split a JSON object into its fields without parsing the values.
+fn json_fields(line: &str) -> Option<BTreeMap<String, &RawValue>> {
+    serde_json::from_str(line.trim()).ok()
+}
+
+/// Get the contents of a JSON string value, without the quotes.  The escape sequences are
+/// left as they are, use [`unescape`] to decode them.
+fn json_str(raw: &RawValue) -> Option<&str> {
+    raw.get().strip_prefix('"')?.strip_suffix('"')
+}
+
+/// Decode the escape sequences in the contents of a quoted string, like `\"` and `\n`.
+/// The text is returned as it is if it has no escapes or they are not valid.
+pub(crate) fn unescape(text: &str) -> Cow<'_, str> {
+    if !text.contains('\\') {
+        return Cow::Borrowed(text);
+    }
+    serde_json::from_str::<String>(&format!("\"{text}\"")).map_or(Cow::Borrowed(text), Cow::Owned)
+}
+
 impl TryFrom<&str> for LogFormat {
     type Error = LogError;
 
@@ -54,19 +255,27 @@ impl TryFrom<&str> for LogFormat {
             // so this case would never come up
             .dot_matches_new_line(true)
             .build()
-            .map_err(|source| LogError::InvalidFormatRegex { source })
+            .map_err(|source| LogError::InvalidLogFormat {
+                pattern: value.to_string(),
+                source,
+            })
             .and_then(|regex| {
                 check_captures(&regex)?;
-                Ok(LogFormat { regex })
+                Ok(LogFormat {
+                    kind: FormatKind::Regex(regex),
+                    escaped_body: false,
+                })
             })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::LogFormat;
+    use super::FormatKind;
+    use crate::{LogError, LogFormat};
     use insta::assert_snapshot;
     use miette::{IntoDiagnostic, NarratableReportHandler, Report};
+    use regex::Regex;
 
     fn get_pretty_report_string(error: Report) -> String {
         let mut buffer = String::new();
@@ -80,6 +289,97 @@ mod tests {
         buffer
     }
 
+    #[test]
+    fn test_json() {
+        let format = LogFormat::json("msg", "file", "line");
+        let line = r#"{"msg":"user logged in","file":"auth.rs","line":42,"uid":7,"who":"a\"b"}"#;
+        assert!(format.is_match(line));
+        let log_ref = format.parse(line).unwrap();
+        let details = log_ref.details.unwrap();
+        assert_eq!(details.file, Some("auth.rs"));
+        assert_eq!(details.lineno, Some(42));
+        assert_eq!(log_ref.body(), "user logged in");
+        assert_eq!(
+            format
+                .extra_fields(line)
+                .iter()
+                .map(|pair| (pair.expr.as_str(), pair.value.as_str()))
+                .collect::<Vec<_>>(),
+            [("uid", "7"), ("who", "a\"b")]
+        );
+
+        let line = r#"{"msg":"saved \"notes.txt\"\n\u00e9t\u00e9 \\o/","file":"io.rs"}"#;
+        let log_ref = format.parse(line).unwrap();
+        assert_eq!(log_ref.body(), r#"saved \"notes.txt\"\n\u00e9t\u00e9 \\o/"#);
+        assert_eq!(log_ref.unescaped_body(), "saved \"notes.txt\"\nété \\o/");
+
+        let log_ref = format.parse(r#"{"msg":"no location","line":"7"}"#).unwrap();
+        assert_eq!(log_ref.details.unwrap().file, None);
+        assert_eq!(log_ref.details.unwrap().lineno, Some(7));
+        assert!(!format.is_match("not json"));
+        assert!(!format.is_match(r#"{"message":"wrong key"}"#));
+    }
+
+    #[test]
+    fn test_presets() {
+        for (name, line, file, lineno, body) in [
+            (
+                "log4j",
+                "2025-04-10 22:12:52 INFO  JvmPauseMonitor:146 - JvmPauseMonitor-n0: Started",
+                Some("JvmPauseMonitor"),
+                Some(146),
+                "JvmPauseMonitor-n0: Started",
+            ),
+            (
+                "env_logger",
+                "[2024-05-01T12:00:00Z INFO  main.rs:12] server started",
+                Some("main.rs"),
+                Some(12),
+                "server started",
+            ),
+            (
+                "env_logger",
+                "[2024-05-01T12:00:00Z WARN  my_app::net] connection reset",
+                None,
+                None,
+                "connection reset",
+            ),
+            (
+                "logrus-text",
+                r#"time="2024-05-01T12:00:00Z" level=info msg="user \"bob\" logged in" func=main.login file="/src/auth.go:42""#,
+                Some("/src/auth.go"),
+                Some(42),
+                r#"user \"bob\" logged in"#,
+            ),
+            (
+                "syslog",
+                "May  1 12:00:00 web01 sshd[4321]: Accepted publickey for bob",
+                None,
+                None,
+                "Accepted publickey for bob",
+            ),
+        ] {
+            let format = LogFormat::preset(name).unwrap();
+            let log_ref = format.parse(line).unwrap();
+            let details = log_ref.details.unwrap();
+            assert_eq!(details.file, file, "{name}: {line}");
+            assert_eq!(details.lineno, lineno, "{name}: {line}");
+            assert_eq!(log_ref.body(), body, "{name}: {line}");
+        }
+        let log_ref = LogFormat::preset("logrus-text")
+            .unwrap()
+            .parse(r#"time="2024-05-01T12:00:00Z" level=info msg="user \"bob\" logged in""#)
+            .unwrap();
+        assert_eq!(log_ref.unescaped_body(), r#"user "bob" logged in"#);
+        let log_ref = LogFormat::preset("syslog")
+            .unwrap()
+            .parse(r"May  1 12:00:00 web01 app[1]: path is C:\tmp")
+            .unwrap();
+        assert_eq!(log_ref.unescaped_body(), r"path is C:\tmp");
+        assert_eq!(LogFormat::preset_names().count(), 4);
+        assert!(LogFormat::preset("log5j").is_none());
+    }
+
     #[test]
     fn test_invalid_regex() {
         let res = Report::from(LogFormat::try_from("abc(").unwrap_err());
@@ -87,6 +387,14 @@ mod tests {
         assert_snapshot!(rep);
     }
 
+    #[test]
+    fn test_unbalanced_paren() {
+        match LogFormat::new("^(?<body>.*") {
+            Err(LogError::InvalidLogFormat { pattern, .. }) => assert_eq!(pattern, "^(?<body>.*"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
     #[test]
     fn test_no_body() {
         let res = LogFormat::try_from("abc").into_diagnostic();
@@ -94,6 +402,30 @@ mod tests {
         assert_snapshot!(rep);
     }
 
+    #[test]
+    fn test_validate() {
+        let format = LogFormat {
+            kind: FormatKind::Regex(
+                Regex::new(r"^(?<level>\w+) (?<file>\S+):(?<line>\d+)").unwrap(),
+            ),
+            escaped_body: false,
+        };
+        match format.validate() {
+            Err(LogError::LogFormatMissingGroup { name }) => assert_eq!(name, "body"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert!(format.has_src_hint());
+
+        let format = LogFormat::preset("syslog").unwrap();
+        assert!(format.validate().is_ok());
+        assert!(!format.has_src_hint());
+        assert_eq!(format.missing_optional_groups(), ["file", "line"]);
+        let format = LogFormat::try_from(r"^(?<file>\S+) (?<body>.*)$").unwrap();
+        assert_eq!(format.missing_optional_groups(), ["line"]);
+        assert!(LogFormat::preset("log4j").unwrap().has_src_hint());
+        assert!(LogFormat::json("msg", "file", "line").validate().is_ok());
+    }
+
     #[test]
     fn test_unknown_cap() {
         let res = LogFormat::try_from("abc(?<extra>def)").into_diagnostic();
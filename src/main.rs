@@ -2,8 +2,8 @@ use clap::Parser as ClapParser;
 use colored_json::{ColoredFormatter, CompactFormatter, Styler};
 use indicatif::{ProgressBar, ProgressStyle};
 use log2src::{
-    LogError, LogFormat, LogMapping, LogMatcher, LogRef, LogRefBuilder, ProgressTracker,
-    ProgressUpdate,
+    strip_ansi, LogError, LogFormat, LogMapping, LogMatcher, LogRef, LogRefBuilder, MatchOptions,
+    ProgressTracker, ProgressUpdate,
 };
 use miette::{IntoDiagnostic, Report};
 use serde::Serialize;
@@ -25,9 +25,10 @@ struct Cli {
     #[arg(short, long, value_name = "LOG")]
     log: Option<PathBuf>,
 
-    /// The regex of a log format being used
+    /// The regex of a log format being used, can be given more than once if the log
+    /// contains messages in different formats
     #[arg(short, long, value_name = "FORMAT")]
-    format: Option<String>,
+    format: Vec<String>,
 
     /// The first line in the log to use (0 based)
     #[arg(short, long, value_name = "START")]
@@ -37,7 +38,12 @@ struct Cli {
     #[arg(short, long, value_name = "COUNT")]
     count: Option<usize>,
 
-    /// Print progress information to standard error
+    /// Remove ANSI escape sequences, like the colors added by a console logger, from the
+    /// log before it is matched
+    #[arg(long)]
+    strip_ansi: bool,
+
+    /// Print progress information and warnings about the log formats to standard error
     #[arg(short, long)]
     verbose: bool,
 }
@@ -51,20 +57,27 @@ fn get_colored_formatter() -> ColoredFormatter<CompactFormatter> {
 #[must_use]
 struct MessageAccumulator {
     log_matcher: LogMatcher,
-    log_format: Option<LogFormat>,
+    log_formats: Vec<LogFormat>,
     content: String,
     message_count: usize,
     limit: usize,
+    strip_ansi: bool,
 }
 
 impl MessageAccumulator {
-    fn new(log_matcher: LogMatcher, log_format: Option<LogFormat>, limit: usize) -> Self {
+    fn new(
+        log_matcher: LogMatcher,
+        log_formats: Vec<LogFormat>,
+        limit: usize,
+        strip_ansi: bool,
+    ) -> Self {
         Self {
             log_matcher,
-            log_format,
+            log_formats,
             content: String::new(),
             message_count: 0,
             limit,
+            strip_ansi,
         }
     }
 
@@ -76,15 +89,23 @@ impl MessageAccumulator {
                 src_ref: None,
                 variables: vec![],
                 exception_trace: vec![],
+                level_mismatch: false,
+                confidence: 0.0,
+                root: None,
+                file_id: None,
             })
     }
 
     fn process_msg(&mut self) {
-        if let Some(captures) = self.log_format.as_ref().unwrap().captures(&self.content) {
+        if self
+            .log_formats
+            .iter()
+            .any(|format| format.is_match(&self.content))
+        {
             self.message_count += 1;
-            let log_ref = LogRefBuilder::new().build_from_captures(captures, &self.content);
+            let log_ref = LogRef::with_formats(&self.content, &self.log_formats);
             let log_mapping = self.get_log_mapping(log_ref);
-            let serialized = get_colored_formatter().to_colored_json_auto(&log_mapping);
+            let serialized = get_colored_formatter().to_colored_json_auto(&log_mapping.versioned());
             println!("{}", serialized.unwrap());
         }
         self.content.clear();
@@ -112,23 +133,23 @@ impl MessageAccumulator {
         println!(
             "{}",
             get_colored_formatter()
-                .to_colored_json_auto(&log_mapping)
+                .to_colored_json_auto(&log_mapping.versioned())
                 .unwrap()
         );
     }
 
     fn consume_line(&mut self, line: &str) {
-        match &self.log_format {
-            Some(format) => {
-                if format.is_match(&line) {
-                    self.new_msg(&line);
-                } else {
-                    self.continued_line(&line);
-                }
-            }
-            None => {
-                self.process_bare_msg(&line);
-            }
+        let line = if self.strip_ansi {
+            strip_ansi(line)
+        } else {
+            line.into()
+        };
+        if self.log_formats.is_empty() {
+            self.process_bare_msg(&line);
+        } else if self.log_formats.iter().any(|format| format.is_match(&line)) {
+            self.new_msg(&line);
+        } else {
+            self.continued_line(&line);
         }
     }
 
@@ -145,7 +166,7 @@ impl MessageAccumulator {
     fn eof(mut self) -> miette::Result<()> {
         self.flush();
 
-        if self.log_format.is_some() && self.message_count == 0 {
+        if !self.log_formats.is_empty() && self.message_count == 0 {
             Err(LogError::NoLogMessages.into())
         } else {
             Ok(())
@@ -223,11 +244,21 @@ fn main() -> miette::Result<()> {
         });
     }
 
-    let log_format: Option<LogFormat> = if let Some(format) = args.format {
-        Some(format.as_str().try_into()?)
-    } else {
-        None
-    };
+    let log_formats = args
+        .format
+        .iter()
+        .map(|format| LogFormat::new(format))
+        .collect::<Result<Vec<_>, _>>()?;
+    for (pattern, format) in args.format.iter().zip(&log_formats) {
+        let missing = format.missing_optional_groups();
+        if args.verbose && !missing.is_empty() {
+            eprintln!(
+                "warning: log format \"{}\" has no '{}' captures, messages will only be matched by their body",
+                pattern,
+                missing.join("' and '")
+            );
+        }
+    }
 
     let reader: Box<dyn io::Read> = match args.log {
         None => Box::new(io::stdin()),
@@ -246,7 +277,10 @@ fn main() -> miette::Result<()> {
         }
     };
 
-    let mut log_matcher = LogMatcher::new();
+    let mut log_matcher = LogMatcher::with_options(MatchOptions {
+        strip_ansi: args.strip_ansi,
+        ..MatchOptions::default()
+    });
     for source in &args.sources {
         log_matcher
             .add_root(&PathBuf::from(source))
@@ -257,13 +291,16 @@ fn main() -> miette::Result<()> {
         .discover_sources(&tracker)
         .into_iter()
         .for_each(|err| eprintln!("{:?}", Report::new(err)));
-    log_matcher.extract_log_statements(&tracker);
+    log_matcher
+        .extract_log_statements(&tracker)
+        .into_iter()
+        .for_each(|err| eprintln!("{:?}", Report::new(err)));
     if log_matcher.is_empty() {
         return Err(LogError::NoLogStatements.into());
     }
     let start = args.start.unwrap_or(0);
     let count = args.count.unwrap_or(usize::MAX);
-    let mut accumulator = MessageAccumulator::new(log_matcher, log_format, count);
+    let mut accumulator = MessageAccumulator::new(log_matcher, log_formats, count, args.strip_ansi);
 
     let reader = BufReader::new(reader);
     for (lineno, line_res) in reader.lines().skip(start).enumerate() {
@@ -3,6 +3,7 @@ use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Arc;
 use std::time::Duration;
 
+#[derive(Debug)]
 pub struct WorkInfo {
     pub completed: AtomicU64,
     pub total: u64,
@@ -17,6 +18,7 @@ impl WorkInfo {
 }
 
 /// A notification of progress for subscribers to a ProgressTracker
+#[derive(Debug)]
 pub enum ProgressUpdate {
     /// A description of a large amount of work.
     Step(String),
@@ -120,6 +122,12 @@ impl Iterator for ProgressListener {
 }
 
 impl ProgressListener {
+    /// Get the underlying channel, like to wait on it alongside other channels.  The
+    /// updates are sent in the order that the steps happen.
+    pub fn into_receiver(self) -> Receiver<ProgressUpdate> {
+        self.receiver
+    }
+
     pub fn try_next_for(&self, timeout: Duration) -> Option<ProgressUpdate> {
         self.receiver.recv_timeout(timeout).ok()
     }
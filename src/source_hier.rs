@@ -1,5 +1,5 @@
 use crate::{LogError, SourceLanguage};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::ffi::{OsStr, OsString};
@@ -11,11 +11,76 @@ fn is_ignored_dir(name: &OsStr) -> bool {
     name == ".git" || name == ".hg" || name == ".svn" || name == ".vscode"
 }
 
+/// Check that a path for an in-memory file names a file under the root, so it has to be
+/// relative and cannot contain any `..` components.
+pub fn is_virtual_path(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        && path
+            .components()
+            .any(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Patterns for paths that should be left out of a hierarchy, like `target` or
+/// `/src/generated`.  As in a `.gitignore`, a pattern without a slash is matched against
+/// the name of every file and directory, while other patterns are matched against the
+/// path relative to the root of the tree.  A trailing slash is ignored.
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreList {
+    names: Vec<glob::Pattern>,
+    paths: Vec<glob::Pattern>,
+}
+
+impl IgnoreList {
+    /// Add a pattern to the list.
+    pub fn add(&mut self, pattern: &str) -> Result<(), glob::PatternError> {
+        let pattern = pattern.trim_end_matches('/');
+        if pattern.contains('/') {
+            self.paths
+                .push(glob::Pattern::new(pattern.trim_start_matches('/'))?);
+        } else {
+            self.names.push(glob::Pattern::new(pattern)?);
+        }
+        Ok(())
+    }
+
+    /// Check if the entry with the given name in a directory should be skipped.
+    fn is_ignored(&self, root: &Path, dir: &Path, name: &OsStr) -> bool {
+        if is_ignored_dir(name) {
+            return true;
+        }
+        let Some(name) = name.to_str() else {
+            return false;
+        };
+        if self.names.iter().any(|pattern| pattern.matches(name)) {
+            return true;
+        }
+        let path = dir.join(name);
+        let Ok(relative) = path.strip_prefix(root) else {
+            return false;
+        };
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..glob::MatchOptions::new()
+        };
+        self.paths
+            .iter()
+            .any(|pattern| pattern.matches_path_with(relative, options))
+    }
+}
+
+/// The state of the tree that is needed while syncing its nodes.
+struct SyncContext<'a> {
+    root: &'a Path,
+    ignore: &'a IgnoreList,
+}
+
 /// Result of a shallow check of a file system path.  Mainly interested in getting a directory
 /// listing without descending into the child trees.
 enum ShallowCheckResult {
     File {
         latest_modified_time: SystemTime,
+        latest_len: u64,
     },
     Directory {
         latest_entries: BTreeMap<OsString, Result<fs::Metadata, io::Error>>,
@@ -24,7 +89,7 @@ enum ShallowCheckResult {
 }
 
 /// A unique identifier for a file that can be used instead of retaining the full path.
-#[derive(Copy, Clone, Debug, Serialize, Hash, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, Hash, Eq, PartialEq)]
 pub struct SourceFileID(usize);
 
 /// A summary of a source code file
@@ -56,6 +121,9 @@ pub enum SourceHierContent {
     File {
         info: SourceFileInfo,
         last_modified_time: SystemTime,
+        /// The size of the file, which is also checked since the resolution of the modified
+        /// time might be too coarse to notice a quick edit.
+        len: u64,
     },
     UnsupportedFile {},
     Directory {
@@ -79,16 +147,16 @@ impl SourceHierContent {
             .collect())
     }
 
-    fn from_dir(path: &Path) -> Self {
+    fn from_dir(path: &Path, ctx: &SyncContext) -> Self {
         match Self::entries_of(path) {
             Ok(entries) => Self::Directory {
                 entries: entries
                     .into_iter()
-                    .filter(|entry| !is_ignored_dir(&entry.0))
+                    .filter(|entry| !ctx.ignore.is_ignored(ctx.root, path, &entry.0))
                     .map(|(entry_name, meta)| {
                         (
                             entry_name.to_os_string(),
-                            SourceHierNode::from_int(&path.join(entry_name), meta),
+                            SourceHierNode::from_int(&path.join(entry_name), meta, ctx),
                         )
                     })
                     .collect(),
@@ -102,17 +170,18 @@ impl SourceHierContent {
         }
     }
 
-    fn from(path: &Path, metadata: Result<fs::Metadata, io::Error>) -> Self {
+    fn from(path: &Path, metadata: Result<fs::Metadata, io::Error>, ctx: &SyncContext) -> Self {
         match metadata {
             Ok(meta) => {
                 if meta.is_dir() {
-                    Self::from_dir(path)
+                    Self::from_dir(path, ctx)
                 } else if meta.is_file() {
                     match SourceLanguage::from_path(&path) {
                         Some(language) => match meta.modified() {
                             Ok(last_modified_time) => Self::File {
                                 info: SourceFileInfo::new(language),
                                 last_modified_time,
+                                len: meta.len(),
                             },
                             Err(err) => Self::Error {
                                 source: LogError::CannotAccessPath {
@@ -146,6 +215,7 @@ impl SourceHierContent {
                     match meta.modified() {
                         Ok(latest_modified_time) => ShallowCheckResult::File {
                             latest_modified_time,
+                            latest_len: meta.len(),
                         },
                         Err(_) => ShallowCheckResult::Error,
                     }
@@ -166,7 +236,8 @@ impl SourceHierContent {
     ///
     /// # Cases
     /// ## Files
-    /// If a file exists and has the same modified time as the last sync, nothing is done.
+    /// If a file exists and has the same modified time and size as the last sync, nothing is
+    /// done.
     /// Otherwise, a new content value is created from the file system state and self is
     /// overwritten with that value.
     ///
@@ -182,30 +253,32 @@ impl SourceHierContent {
         path: &Path,
         latest_meta: Result<fs::Metadata, io::Error>,
         deleted_events: &mut Vec<ScanEvent>,
+        ctx: &SyncContext,
     ) -> bool {
         let latest_content = Self::shallow_check(path, &latest_meta);
         *self = match self {
             SourceHierContent::File {
                 last_modified_time,
                 info,
-                ..
+                len,
             } => match latest_content {
                 ShallowCheckResult::File {
                     latest_modified_time,
-                    ..
-                } if *last_modified_time == latest_modified_time => {
+                    latest_len,
+                } if *last_modified_time == latest_modified_time && *len == latest_len => {
                     return false;
                 }
                 _ => {
                     deleted_events.push(ScanEvent::DeletedFile(PathBuf::from(path), info.id));
-                    Self::from(path, latest_meta)
+                    Self::from(path, latest_meta, ctx)
                 }
             },
             SourceHierContent::Directory { ref mut entries } => match latest_content {
                 ShallowCheckResult::Directory { latest_entries } => {
                     let mut changed = false;
                     entries.retain(|name, node| {
-                        let exists = latest_entries.contains_key(name);
+                        let exists = latest_entries.contains_key(name)
+                            && !ctx.ignore.is_ignored(ctx.root, path, name);
                         if !exists {
                             node.deleted(path, name, deleted_events);
                             changed = true;
@@ -215,23 +288,23 @@ impl SourceHierContent {
                     let mut new_entries: Vec<(OsString, Result<fs::Metadata, io::Error>)> =
                         Vec::new();
                     for (name, meta) in latest_entries {
-                        if is_ignored_dir(&name.as_os_str()) {
+                        if ctx.ignore.is_ignored(ctx.root, path, &name) {
                         } else if let Some(existing_entry) = entries.get_mut(&name) {
-                            existing_entry.sync(&path.join(&name), meta, deleted_events)
+                            existing_entry.sync(&path.join(&name), meta, deleted_events, ctx)
                         } else {
                             new_entries.push((name, meta));
                             changed = true;
                         }
                     }
                     new_entries.into_iter().for_each(|(name, meta)| {
-                        let node = SourceHierNode::from_int(&path.join(&name), meta);
+                        let node = SourceHierNode::from_int(&path.join(&name), meta, ctx);
                         entries.insert(name, node);
                     });
                     return changed;
                 }
-                _ => Self::from(path, latest_meta),
+                _ => Self::from(path, latest_meta, ctx),
             },
-            _ => Self::from(path, latest_meta),
+            _ => Self::from(path, latest_meta, ctx),
         };
         true
     }
@@ -249,10 +322,14 @@ impl SourceHierContent {
             SourceHierContent::Directory { ref entries } => {
                 let mut components = desired_path.components();
                 if let Some(Component::Normal(name)) = components.next() {
+                    let found = accum.len();
                     if let Some(node) = entries.get(name) {
                         node.content
                             .find_file(&self_path.join(name), components.as_path(), accum);
-                    } else {
+                    }
+                    // The path might be relative to a subdirectory, so keep looking if it
+                    // was not found directly under this one.
+                    if accum.len() == found {
                         for (name, entry) in entries {
                             let sub_path = self_path.join(name);
                             entry.content.find_file(&sub_path, desired_path, accum);
@@ -274,13 +351,13 @@ pub struct SourceHierNode {
 }
 
 impl SourceHierNode {
-    fn from_int(path: &Path, metadata: Result<fs::Metadata, io::Error>) -> Self {
+    fn from_int(path: &Path, metadata: Result<fs::Metadata, io::Error>, ctx: &SyncContext) -> Self {
         match metadata {
             Ok(meta) => {
                 if meta.is_dir() {
                     Self {
                         last_scan_time: None,
-                        content: SourceHierContent::from_dir(path),
+                        content: SourceHierContent::from_dir(path, ctx),
                     }
                 } else if meta.is_file() {
                     match SourceLanguage::from_path(&path) {
@@ -290,6 +367,7 @@ impl SourceHierNode {
                                 content: SourceHierContent::File {
                                     info: SourceFileInfo::new(language),
                                     last_modified_time,
+                                    len: meta.len(),
                                 },
                             },
                             Err(err) => Self {
@@ -356,8 +434,9 @@ impl SourceHierNode {
         path: &Path,
         meta: Result<fs::Metadata, io::Error>,
         deleted_events: &mut Vec<ScanEvent>,
+        ctx: &SyncContext,
     ) {
-        if self.content.sync_int(path, meta, deleted_events) {
+        if self.content.sync_int(path, meta, deleted_events, ctx) {
             self.last_scan_time = None;
         }
     }
@@ -426,6 +505,8 @@ pub struct SourceHierStats {
 pub struct SourceHierTree {
     pub root_path: PathBuf,
     pub root_node: SourceHierNode,
+    /// The paths under the root that are skipped when syncing.
+    pub ignore: IgnoreList,
     next_id: usize,
     deleted_events: Vec<ScanEvent>,
     stats: SourceHierStats,
@@ -436,6 +517,7 @@ impl SourceHierTree {
         SourceHierTree {
             root_path: path.to_path_buf(),
             root_node: SourceHierNode::stub(),
+            ignore: IgnoreList::default(),
             next_id: 0,
             deleted_events: Vec::new(),
             stats: SourceHierStats::default(),
@@ -447,10 +529,15 @@ impl SourceHierTree {
         SourceFileInfo::NEXT_ID.with(|id_opt| {
             *id_opt.borrow_mut() = self.next_id;
         });
+        let ctx = SyncContext {
+            root: &self.root_path,
+            ignore: &self.ignore,
+        };
         self.root_node.sync(
             &self.root_path,
             fs::metadata(&self.root_path),
             &mut self.deleted_events,
+            &ctx,
         );
         self.next_id = SourceFileInfo::NEXT_ID.with(|id_opt| *id_opt.borrow());
         self.stats = self.compute_stats();
@@ -485,6 +572,89 @@ impl SourceHierTree {
         retval
     }
 
+    /// Mark the file at the given path as scanned, so it is not reported by
+    /// [`scan()`](SourceHierTree::scan()), if it still has the given modified time and
+    /// size.  Returns the info for the file if it was marked.
+    pub fn mark_scanned(
+        &mut self,
+        path: &Path,
+        modified_time: SystemTime,
+        size: u64,
+    ) -> Option<SourceFileInfo> {
+        let mut node = &mut self.root_node;
+        for component in path.strip_prefix(&self.root_path).ok()?.components() {
+            match &mut node.content {
+                SourceHierContent::Directory { entries } => {
+                    node = entries.get_mut(component.as_os_str())?;
+                }
+                _ => return None,
+            }
+        }
+        match node.content {
+            SourceHierContent::File {
+                info,
+                last_modified_time,
+                len,
+            } if last_modified_time == modified_time && len == size => {
+                node.last_scan_time = Some(SystemTime::now());
+                Some(info)
+            }
+            _ => None,
+        }
+    }
+
+    /// Add a file that only exists in memory, like an unsaved editor buffer, at the given
+    /// path under the root.  The file is marked as scanned, so it is never reported by
+    /// [`scan()`](SourceHierTree::scan()), and this tree should not be synced since the
+    /// file would be removed.  The path has to pass [`is_virtual_path`], so that different
+    /// paths cannot end up at the same node.  Returns the info for the new file along with
+    /// the ID of the file that it replaced, if any.
+    pub fn insert_virtual(
+        &mut self,
+        path: &Path,
+        language: SourceLanguage,
+        len: u64,
+    ) -> (SourceFileInfo, Option<SourceFileID>) {
+        debug_assert!(is_virtual_path(path));
+        SourceFileInfo::NEXT_ID.with(|id_opt| {
+            *id_opt.borrow_mut() = self.next_id;
+        });
+        let info = SourceFileInfo::new(language);
+        self.next_id = SourceFileInfo::NEXT_ID.with(|id_opt| *id_opt.borrow());
+
+        let mut node = &mut self.root_node;
+        for component in path.components() {
+            let Component::Normal(name) = component else {
+                continue;
+            };
+            if !matches!(node.content, SourceHierContent::Directory { .. }) {
+                node.content = SourceHierContent::Directory {
+                    entries: BTreeMap::new(),
+                };
+            }
+            let SourceHierContent::Directory { entries } = &mut node.content else {
+                unreachable!();
+            };
+            node = entries
+                .entry(name.to_os_string())
+                .or_insert_with(SourceHierNode::stub);
+        }
+        let replaced = match node.content {
+            SourceHierContent::File { info, .. } => Some(info.id),
+            _ => None,
+        };
+        *node = SourceHierNode {
+            last_scan_time: Some(SystemTime::now()),
+            content: SourceHierContent::File {
+                info,
+                last_modified_time: SystemTime::UNIX_EPOCH,
+                len,
+            },
+        };
+        self.stats = self.compute_stats();
+        (info, replaced)
+    }
+
     /// Visit every node in the hierarchy, depth-first, calling `f` on each.
     pub fn visit<F>(&self, mut f: F)
     where
@@ -504,6 +674,25 @@ impl SourceHierTree {
         walk(&self.root_node, &mut f);
     }
 
+    /// Visit every node in the hierarchy, depth-first, calling `f` on each with its path.
+    pub fn visit_with_path<F>(&self, mut f: F)
+    where
+        F: FnMut(&Path, &SourceHierNode),
+    {
+        fn walk<F>(path: &Path, node: &SourceHierNode, f: &mut F)
+        where
+            F: FnMut(&Path, &SourceHierNode),
+        {
+            f(path, node);
+            if let SourceHierContent::Directory { entries } = &node.content {
+                for (name, child) in entries {
+                    walk(&path.join(name), child, f);
+                }
+            }
+        }
+        walk(&self.root_path, &self.root_node, &mut f);
+    }
+
     fn compute_stats(&self) -> SourceHierStats {
         let mut retval = SourceHierStats::default();
 
@@ -1,24 +1,35 @@
+use std::collections::HashSet;
 use std::ops::Range;
 use tree_sitter::{
     Language, Node, Parser, Point, Query, QueryCursor, Range as TSRange, StreamingIterator, Tree,
 };
 
-use crate::source_ref::FormatArgument;
-use crate::CodeSource;
+use crate::source_ref::{ContextField, FormatArgument};
+use crate::{normalize_level, CodeSource, SourceLanguage};
 
 pub struct SourceQuery<'a> {
     pub source: &'a str,
     tree: Tree,
     language: Language,
+    /// The language of the source, for the grammars that share node kinds with others.
+    source_language: SourceLanguage,
 }
 
 pub(crate) struct QueryResult {
     pub kind: String,
     pub range: TSRange,
-    pub name_range: Range<usize>,
+    /// The range of the name of the enclosing function, if there is one.
+    pub name_range: Option<Range<usize>>,
+    /// The byte range of the whole logging call that contains the capture.
+    pub call_range: Range<usize>,
     pub pattern: Option<String>,
     pub args: Vec<FormatArgument>,
     pub raw: bool,
+    /// The level of the logging call, based on the name of the function or macro.
+    pub level: Option<&'static str>,
+    /// The structured fields that come before the format string, like in a call to one of
+    /// the `tracing` crate's macros.
+    pub context_fields: Vec<ContextField>,
 }
 
 impl<'a> SourceQuery<'a> {
@@ -35,6 +46,7 @@ impl<'a> SourceQuery<'a> {
             source,
             tree,
             language,
+            source_language: code.info.language,
         }
     }
 
@@ -44,18 +56,47 @@ impl<'a> SourceQuery<'a> {
         let mut cursor = QueryCursor::new();
         let mut results = Vec::new();
         let matches = cursor.matches(&query, self.tree.root_node(), self.source.as_bytes());
+        // More than one pattern in a query can match the same format string.
+        let mut seen = HashSet::new();
         matches.for_each(|m| {
             let mut got_string_literal = false;
+            let mut level = None;
+            let mut context_fields = Vec::new();
             for capture in m.captures {
                 let mut child = capture.node;
                 match child.kind() {
-                    "string_literal" | "string" => {
+                    "string_literal"
+                    | "raw_string_literal"
+                    | "interpreted_string_literal"
+                    | "concatenated_string"
+                    | "line_string_literal"
+                    | "multiline_string_literal"
+                    | "interpolated_string"
+                    | "template_string"
+                    | "string" => {
+                        if !seen.insert(child.id()) {
+                            return;
+                        }
+                        if child
+                            .parent()
+                            .is_some_and(|parent| parent.kind() == "token_tree")
+                        {
+                            let Some((fields, field_level)) = self.macro_fields(child) else {
+                                return;
+                            };
+                            context_fields = fields;
+                            level = field_level.or(level);
+                        }
                         // only return results after the format string literal, other captures
                         // are not relevant.
                         got_string_literal = true;
                     }
                     _ => {
                         if !got_string_literal {
+                            // The captures before the string are the names of the logging
+                            // function or object, which might tell us the level.
+                            let range = capture.node.start_byte()..capture.node.end_byte();
+                            level = normalize_level(&self.source[range]).or(level);
                             continue;
                         }
                     }
@@ -68,12 +109,19 @@ impl<'a> SourceQuery<'a> {
                         kind: capture.node.kind().to_string(),
                         range: capture.node.range(),
                         name_range: Self::find_fn_range(child),
+                        call_range: Self::find_call_range(child),
                         pattern: None,
                         args: vec![],
                         raw: false,
+                        level,
+                        context_fields: std::mem::take(&mut context_fields),
                     });
                     let mut pattern = String::new();
-                    if child.kind() == "string" {
+                    if child.kind() == "string"
+                        && child
+                            .child(0)
+                            .is_some_and(|start| start.kind() == "string_start")
+                    {
                         // The Python tree-sitter outputs string nodes that contain details about
                         // the string, like interpolation expressions.
                         let mut child_cursor = child.walk();
@@ -101,6 +149,205 @@ impl<'a> SourceQuery<'a> {
                             }
                         }
                         results[qr_index].pattern = Some(pattern);
+                    } else if child.kind() == "raw_string_literal" {
+                        // Rust raw strings can be delimited by any number of hashes, so use the
+                        // content node instead of trimming a fixed number of characters.  Go raw
+                        // strings have a similar content node.
+                        let mut child_cursor = child.walk();
+                        if let Some(content) =
+                            child.children(&mut child_cursor).find(|string_child| {
+                                matches!(
+                                    string_child.kind(),
+                                    "string_content" | "raw_string_literal_content"
+                                )
+                            })
+                        {
+                            pattern
+                                .push_str(&self.source[content.start_byte()..content.end_byte()]);
+                        }
+                        results[qr_index].raw = true;
+                        results[qr_index].pattern = Some(pattern);
+                    } else if child.kind() == "line_string_literal" {
+                        // Swift interpolations are swapped for a placeholder, like the
+                        // expressions in a Python f-string.
+                        let mut child_cursor = child.walk();
+                        for string_child in child.children(&mut child_cursor) {
+                            let range = string_child.start_byte()..string_child.end_byte();
+                            match string_child.kind() {
+                                "line_str_text" | "str_escaped_char" => {
+                                    pattern.push_str(&self.source[range])
+                                }
+                                "interpolated_expression" => {
+                                    pattern.push_str("\\()");
+                                    let expr = string_child
+                                        .child_by_field_name("value")
+                                        .unwrap_or(string_child);
+                                    results[qr_index].args.push(FormatArgument::Named(
+                                        self.source[expr.start_byte()..expr.end_byte()].to_string(),
+                                    ))
+                                }
+                                _ => {}
+                            }
+                        }
+                        results[qr_index].pattern = Some(pattern);
+                    } else if child.kind() == "string" && Self::is_ruby_string(child) {
+                        // Ruby interpolations are swapped for a placeholder, the same as a
+                        // JavaScript template literal.
+                        let mut child_cursor = child.walk();
+                        for string_child in child.children(&mut child_cursor) {
+                            let range = string_child.start_byte()..string_child.end_byte();
+                            match string_child.kind() {
+                                "string_content" | "escape_sequence" => {
+                                    pattern.push_str(&self.source[range])
+                                }
+                                "interpolation" => {
+                                    pattern.push_str("#{}");
+                                    let expr = string_child.named_child(0).unwrap_or(string_child);
+                                    results[qr_index].args.push(FormatArgument::Named(
+                                        self.source[expr.start_byte()..expr.end_byte()].to_string(),
+                                    ))
+                                }
+                                _ => {}
+                            }
+                        }
+                        results[qr_index].pattern = Some(pattern);
+                    } else if self.source_language == SourceLanguage::Kotlin
+                        && matches!(child.kind(), "string_literal" | "multiline_string_literal")
+                    {
+                        // Kotlin string templates are swapped for a placeholder, the same as
+                        // a JavaScript template literal.  The grammar does not parse a
+                        // `$name` template in a single-line string, it is a lone `$` that is
+                        // followed by the text starting with the name instead.
+                        let mut child_cursor = child.walk();
+                        let mut dollar = false;
+                        for string_child in child.children(&mut child_cursor) {
+                            let mut text =
+                                &self.source[string_child.start_byte()..string_child.end_byte()];
+                            if std::mem::take(&mut dollar) {
+                                let name_len = text
+                                    .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                                    .unwrap_or(text.len());
+                                if string_child.kind() == "string_content"
+                                    && text.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                                {
+                                    pattern.push_str("${}");
+                                    results[qr_index]
+                                        .args
+                                        .push(FormatArgument::Named(text[..name_len].to_string()));
+                                    text = &text[name_len..];
+                                } else {
+                                    pattern.push('$');
+                                }
+                            }
+                            match string_child.kind() {
+                                "string_content" if text == "$" => dollar = true,
+                                "string_content" => pattern.push_str(text),
+                                "escape_sequence" if text == "\\$" => pattern.push('$'),
+                                "escape_sequence" => pattern.push_str(text),
+                                "interpolation" => {
+                                    pattern.push_str("${}");
+                                    let expr = string_child.named_child(0).unwrap_or(string_child);
+                                    results[qr_index].args.push(FormatArgument::Named(
+                                        self.source[expr.start_byte()..expr.end_byte()].to_string(),
+                                    ))
+                                }
+                                _ => {}
+                            }
+                        }
+                        // Backslashes are not escapes in a multiline string.
+                        results[qr_index].raw = child.kind() == "multiline_string_literal";
+                        results[qr_index].pattern = Some(pattern);
+                    } else if child.kind() == "interpolated_string" {
+                        // The text of a Scala interpolated string is not in nodes of its own,
+                        // so it is taken from between the escapes and the interpolations,
+                        // which are swapped for a placeholder.  There is no node for the
+                        // closing quote either, so it is assumed to match the opening one.
+                        let mut child_cursor = child.walk();
+                        let mut children = child.children(&mut child_cursor);
+                        let quote_len = children.next().map_or(0, |quote| quote.byte_range().len());
+                        let mut last_end = child.start_byte() + quote_len;
+                        for string_child in children {
+                            pattern.push_str(&self.source[last_end..string_child.start_byte()]);
+                            last_end = string_child.end_byte();
+                            let text = &self.source[string_child.byte_range()];
+                            match string_child.kind() {
+                                "escape_sequence" if text == "$$" => pattern.push('$'),
+                                "escape_sequence" => pattern.push_str(text),
+                                "interpolation" => {
+                                    pattern.push_str("${}");
+                                    // The expression in `${expr}` is wrapped in a block.
+                                    let expr = string_child
+                                        .named_child(0)
+                                        .map(|expr| match expr.kind() {
+                                            "block" => expr.named_child(0).unwrap_or(expr),
+                                            _ => expr,
+                                        })
+                                        .unwrap_or(string_child);
+                                    results[qr_index].args.push(FormatArgument::Named(
+                                        self.source[expr.start_byte()..expr.end_byte()].to_string(),
+                                    ))
+                                }
+                                _ => {}
+                            }
+                        }
+                        let end = child.end_byte().saturating_sub(quote_len).max(last_end);
+                        pattern.push_str(&self.source[last_end..end]);
+                        results[qr_index].pattern = Some(pattern);
+                    } else if child.kind() == "template_string" {
+                        // The expressions in a JavaScript template literal are swapped for a
+                        // placeholder, the same as a Swift interpolation.
+                        let mut child_cursor = child.walk();
+                        for string_child in child.children(&mut child_cursor) {
+                            let range = string_child.start_byte()..string_child.end_byte();
+                            match string_child.kind() {
+                                "string_fragment" | "escape_sequence" => {
+                                    pattern.push_str(&self.source[range])
+                                }
+                                "template_substitution" => {
+                                    pattern.push_str("${}");
+                                    let expr = string_child.named_child(0).unwrap_or(string_child);
+                                    results[qr_index].args.push(FormatArgument::Named(
+                                        self.source[expr.start_byte()..expr.end_byte()].to_string(),
+                                    ))
+                                }
+                                _ => {}
+                            }
+                        }
+                        results[qr_index].pattern = Some(pattern);
+                    } else if child.kind() == "concatenated_string" {
+                        // Adjacent C/C++ string literals are joined by the compiler, so
+                        // join their contents into a single format string.  The text of a
+                        // macro between them is not known, unless it is one of the
+                        // `<cinttypes>` conversions, so the call is skipped in that case.
+                        let mut child_cursor = child.walk();
+                        for string_child in child.children(&mut child_cursor) {
+                            let text =
+                                &self.source[string_child.start_byte()..string_child.end_byte()];
+                            match string_child.kind() {
+                                "string_literal" => {
+                                    if let Some(start) = text.find('"') {
+                                        pattern.push_str(&text[start + 1..text.len() - 1]);
+                                    }
+                                }
+                                "comment" => {}
+                                _ => match inttypes_conversion(text) {
+                                    Some(conversion) => pattern.push(conversion),
+                                    None => {
+                                        results.pop();
+                                        return;
+                                    }
+                                },
+                            }
+                        }
+                        results[qr_index].pattern = Some(pattern);
+                    }
+                    // Some grammars, like the ones for C# and Kotlin, wrap each argument in a
+                    // node.
+                    if let Some(parent) = child
+                        .parent()
+                        .filter(|parent| matches!(parent.kind(), "argument" | "value_argument"))
+                    {
+                        child = parent;
                     }
                     while let Some(next_child) = child.next_sibling() {
                         if matches!(next_child.kind(), "," | ")") {
@@ -115,9 +362,12 @@ impl<'a> SourceQuery<'a> {
                                             end_point: next_child.start_position(),
                                         },
                                         name_range: Self::find_fn_range(child),
+                                        call_range: start.0..next_child.start_byte(),
                                         pattern: None,
                                         args: vec![],
                                         raw: false,
+                                        level: None,
+                                        context_fields: vec![],
                                     });
                                 }
                             }
@@ -132,52 +382,151 @@ impl<'a> SourceQuery<'a> {
         results
     }
 
-    fn find_fn_range(node: Node) -> Range<usize> {
-        // println!("node.kind()={:?}", node.kind());
-        match node.kind() {
-            "function_item" => {
-                let range = node.child_by_field_name("name").unwrap().range();
-                range.start_byte..range.end_byte
-            }
-            "function_definition" => {
-                let range = if let Some(decl) = node.child_by_field_name("declarator") {
-                    decl.range()
-                } else if let Some(name) = node.child_by_field_name("name") {
-                    name.range()
-                } else {
-                    unreachable!();
-                };
-                range.start_byte..range.end_byte
-            }
-            "method_declaration" => {
-                let range = node.child_by_field_name("name").unwrap().range();
-                range.start_byte..range.end_byte
-            }
-            "constructor_declaration" => {
-                let range = node.child_by_field_name("name").unwrap().range();
-                range.start_byte..range.end_byte
-            }
-            "class_declaration" => {
-                let range = node.child_by_field_name("name").unwrap().range();
-                range.start_byte..range.end_byte
+    /// Find the structured fields that come before the message in a Rust macro call, like
+    /// `user = %id` in `info!(user = %id, "logged in")`, which is the syntax used by the
+    /// `tracing` crate.  A level, like `Level::INFO` in an `event!` call, is also returned.
+    /// The message is the first argument that is a lone string literal, so `None` is
+    /// returned for any other string literal in the macro call.
+    fn macro_fields(&self, literal: Node) -> Option<(Vec<ContextField>, Option<&'static str>)> {
+        let token_tree = literal.parent()?;
+        let mut cursor = token_tree.walk();
+        let tokens = token_tree.children(&mut cursor).collect::<Vec<_>>();
+        let inner = tokens.get(1..tokens.len().saturating_sub(1))?;
+        let mut fields = Vec::new();
+        let mut level = None;
+        for segment in inner.split(|token| token.kind() == ",") {
+            let (Some(first), Some(last)) = (segment.first(), segment.last()) else {
+                continue;
+            };
+            if segment.len() == 1 && matches!(first.kind(), "string_literal" | "raw_string_literal")
+            {
+                return (first.id() == literal.id()).then_some((fields, level));
             }
-            "declaration_list" | "static_item" | "attribute_item" => {
-                let range = node.range();
-                range.start_byte..range.end_byte
+            let text = &self.source[first.start_byte()..last.end_byte()];
+            if let Some(field) = parse_tracing_field(text) {
+                fields.push(field);
+            } else if text.contains("::") {
+                level = normalize_level(text).or(level);
             }
-            _ => {
-                if let Some(parent) = node.parent() {
-                    if parent.kind() == "translation_unit" {
-                        let range = parent.range();
-                        return range.start_byte..range.end_byte;
-                    }
-                    Self::find_fn_range(parent)
-                } else {
-                    let range = node.range();
+        }
+        None
+    }
 
-                    range.start_byte..range.end_byte
-                }
+    /// Check if a `string` node is from the Ruby grammar, which splits the string into
+    /// content and interpolation nodes, unlike the JavaScript one.  Python strings are
+    /// handled before this check.
+    fn is_ruby_string(node: Node) -> bool {
+        let mut cursor = node.walk();
+        let is_ruby = node
+            .children(&mut cursor)
+            .any(|child| matches!(child.kind(), "string_content" | "interpolation"));
+        is_ruby
+    }
+
+    /// Find the byte range of the call or macro invocation that contains the given node,
+    /// like the whole of `debug!("x={}", x)` for its format string.  The range of the node
+    /// itself is returned if it is not inside a call.
+    fn find_call_range(node: Node) -> Range<usize> {
+        let call = std::iter::successors(Some(node), |current| current.parent()).find(|current| {
+            matches!(
+                current.kind(),
+                "macro_invocation"
+                    | "call_expression"
+                    | "method_invocation"
+                    | "invocation_expression"
+                    | "call"
+            )
+        });
+        let call = call.unwrap_or(node);
+        call.start_byte()..call.end_byte()
+    }
+
+    /// Find the name of the function or method that encloses the given node by walking up
+    /// its ancestors.  For code outside of any function, the name of the enclosing class is
+    /// used if there is one, otherwise `None` is returned.
+    fn find_fn_range(node: Node) -> Option<Range<usize>> {
+        let mut current = node;
+        loop {
+            let name = match current.kind() {
+                "function_item"
+                | "function_declaration"
+                | "method_declaration"
+                | "constructor_declaration"
+                | "local_function_statement"
+                | "method_definition"
+                | "method"
+                | "singleton_method"
+                | "class_declaration"
+                | "class_definition"
+                | "object_definition"
+                | "trait_definition" => current.child_by_field_name("name"),
+                "function_definition" => match current.child_by_field_name("name") {
+                    Some(name) => Some(name),
+                    // C/C++ functions are named by the innermost declarator, like the
+                    // `Server::start` in `void Server::start(int port)`.
+                    None => {
+                        let mut declarator = current.child_by_field_name("declarator");
+                        while let Some(inner) =
+                            declarator.and_then(|decl| decl.child_by_field_name("declarator"))
+                        {
+                            declarator = Some(inner);
+                        }
+                        declarator
+                    }
+                },
+                // An anonymous function is named by the variable it is assigned to, like
+                // `const login = () => {...}`.
+                "arrow_function" | "function_expression" => current
+                    .parent()
+                    .filter(|parent| parent.kind() == "variable_declarator")
+                    .and_then(|parent| parent.child_by_field_name("name")),
+                _ => None,
+            };
+            if let Some(name) = name {
+                return Some(name.start_byte()..name.end_byte());
             }
+            current = current.parent()?;
+        }
+    }
+}
+
+/// Get the conversion for one of the `<cinttypes>` macros for printing a fixed-width
+/// integer, like the `u` for `PRIu64`.  The length modifiers that the macros expand to do
+/// not change the text of the value, so they are left out.
+fn inttypes_conversion(name: &str) -> Option<char> {
+    let rest = name.strip_prefix("PRI")?;
+    let conversion = rest.chars().next().filter(|c| "diouxX".contains(*c))?;
+    let width = &rest[1..];
+    (!width.is_empty() && width.chars().all(|c| c.is_ascii_alphanumeric())).then_some(conversion)
+}
+
+/// Parse a field of a `tracing` macro call, like `user = %id`, or the shorthand for a field
+/// with the same name as the variable, like `?user`.  Other arguments, like `target: "x"`,
+/// are ignored.
+fn parse_tracing_field(text: &str) -> Option<ContextField> {
+    let is_path = |s: &str| {
+        !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '.')
+    };
+    match text.split_once('=') {
+        Some((key, expr)) => {
+            let key = key.trim().trim_matches('"');
+            is_path(key).then(|| ContextField {
+                key: key.to_string(),
+                expr: expr
+                    .trim()
+                    .trim_start_matches(['%', '?'])
+                    .trim()
+                    .to_string(),
+            })
+        }
+        None => {
+            let expr = text.trim_start_matches(['%', '?']).trim();
+            is_path(expr).then(|| ContextField {
+                key: expr.to_string(),
+                expr: expr.to_string(),
+            })
         }
     }
 }
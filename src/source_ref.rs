@@ -1,16 +1,28 @@
 use crate::{CodeSource, QueryResult, SourceLanguage};
 use core::fmt;
 use regex::{Captures, Regex};
-use serde::Serialize;
-use std::sync::LazyLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::{LazyLock, OnceLock};
 
-#[derive(Clone, Debug, Serialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub enum FormatArgument {
     Named(String),
     Positional(usize),
     Placeholder,
 }
 
+/// The type of a value in a log message, as hinted by the format specifier of its
+/// placeholder, like `%d` for an integer.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum VarType {
+    Integer,
+    Float,
+    Bool,
+    String,
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct CallSite {
     pub name: String,
@@ -21,7 +33,18 @@ pub struct CallSite {
     pub line_no: usize,
 }
 
+/// A key/value attribute passed to a structured logging call, separate from the arguments
+/// that are substituted into the format string.
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ContextField {
+    pub key: String,
+    pub expr: String,
+}
+
 // TODO: get rid of this clone?
+/// A log statement that was found in a source file.  The serialized form only has the
+/// parts that are useful for a reader, so use a [`CachedStatement`] to store a statement
+/// and load it back.
 #[derive(Clone, Debug, Serialize)]
 pub struct SourceRef {
     #[serde(rename(serialize = "sourcePath"))]
@@ -32,6 +55,13 @@ pub struct SourceRef {
     #[serde(rename(serialize = "endLineNumber"))]
     pub end_line_no: usize,
     pub column: usize,
+    /// The byte offset of the start of the whole logging call, like a `debug!(...)`
+    /// invocation, in the source file.
+    #[serde(rename(serialize = "startByte"))]
+    pub start_byte: usize,
+    /// The byte offset of the end of the whole logging call in the source file.
+    #[serde(rename(serialize = "endByte"))]
+    pub end_byte: usize,
     pub name: String,
     pub text: String,
     pub quality: usize,
@@ -40,6 +70,135 @@ pub struct SourceRef {
     pub pattern: String,
     pub(crate) args: Vec<FormatArgument>,
     pub(crate) vars: Vec<String>,
+    #[serde(rename(serialize = "contextFields"))]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub context_fields: Vec<ContextField>,
+    /// The expression for an exception that is passed after the arguments for the
+    /// placeholders, like the `ex` in SLF4J's `log.error("failed for {}", id, ex)`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exception: Option<String>,
+    /// The canonical level of the logging call, if it could be determined from the name of
+    /// the function or macro.
+    #[serde(skip_serializing)]
+    pub level: Option<String>,
+    /// The escaped literal text that surrounds each placeholder in the pattern.
+    #[serde(skip_serializing)]
+    pub(crate) segments: Vec<String>,
+    /// The longest run of text that will appear verbatim in a rendered message.
+    #[serde(skip_serializing)]
+    pub(crate) literal: String,
+    /// The precision declared in the format spec of each placeholder, if any.
+    #[serde(skip_serializing)]
+    pub(crate) precisions: Vec<Option<usize>>,
+    /// The byte range of each placeholder within `text`.  This is empty if the format
+    /// string was not taken verbatim from the source, like an f-string.
+    #[serde(skip_serializing)]
+    pub(crate) placeholder_spans: Vec<(usize, usize)>,
+    /// The type of value hinted by the format specifier of each placeholder, if any.
+    #[serde(skip_serializing)]
+    pub(crate) value_types: Vec<Option<VarType>>,
+    /// The patterns used by [`prefix_captures`](SourceRef::prefix_captures), which are
+    /// compiled the first time a cut off message is checked against this statement.
+    #[serde(skip_serializing)]
+    prefix_matchers: OnceLock<Vec<PrefixMatcher>>,
+}
+
+/// The patterns for a message that was cut off in one of the segments of a statement or in
+/// the value before it.
+#[derive(Clone, Debug)]
+struct PrefixMatcher {
+    in_segment: Option<Regex>,
+    in_value: Option<Regex>,
+}
+
+/// All the parts of a [`SourceRef`], except for the compiled regex, for storing log
+/// statements in a cache.  Unlike the serialized form of a SourceRef, which only has the
+/// parts that are useful for a reader, this can be deserialized and converted back into a
+/// SourceRef.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CachedStatement {
+    source_path: String,
+    language: SourceLanguage,
+    line_no: usize,
+    end_line_no: usize,
+    column: usize,
+    start_byte: usize,
+    end_byte: usize,
+    name: String,
+    text: String,
+    quality: usize,
+    pattern: String,
+    args: Vec<FormatArgument>,
+    vars: Vec<String>,
+    context_fields: Vec<ContextField>,
+    exception: Option<String>,
+    level: Option<String>,
+    segments: Vec<String>,
+    literal: String,
+    precisions: Vec<Option<usize>>,
+    placeholder_spans: Vec<(usize, usize)>,
+    value_types: Vec<Option<VarType>>,
+}
+
+impl From<&SourceRef> for CachedStatement {
+    fn from(src_ref: &SourceRef) -> Self {
+        let src_ref = src_ref.clone();
+        Self {
+            source_path: src_ref.source_path,
+            language: src_ref.language,
+            line_no: src_ref.line_no,
+            end_line_no: src_ref.end_line_no,
+            column: src_ref.column,
+            start_byte: src_ref.start_byte,
+            end_byte: src_ref.end_byte,
+            name: src_ref.name,
+            text: src_ref.text,
+            quality: src_ref.quality,
+            pattern: src_ref.pattern,
+            args: src_ref.args,
+            vars: src_ref.vars,
+            context_fields: src_ref.context_fields,
+            exception: src_ref.exception,
+            level: src_ref.level,
+            segments: src_ref.segments,
+            literal: src_ref.literal,
+            precisions: src_ref.precisions,
+            placeholder_spans: src_ref.placeholder_spans,
+            value_types: src_ref.value_types,
+        }
+    }
+}
+
+impl TryFrom<CachedStatement> for SourceRef {
+    type Error = regex::Error;
+
+    fn try_from(cached: CachedStatement) -> Result<Self, Self::Error> {
+        Ok(SourceRef {
+            matcher: Regex::new(&cached.pattern)?,
+            source_path: cached.source_path,
+            language: cached.language,
+            line_no: cached.line_no,
+            end_line_no: cached.end_line_no,
+            column: cached.column,
+            start_byte: cached.start_byte,
+            end_byte: cached.end_byte,
+            name: cached.name,
+            text: cached.text,
+            quality: cached.quality,
+            pattern: cached.pattern,
+            args: cached.args,
+            vars: cached.vars,
+            context_fields: cached.context_fields,
+            exception: cached.exception,
+            level: cached.level,
+            segments: cached.segments,
+            literal: cached.literal,
+            precisions: cached.precisions,
+            placeholder_spans: cached.placeholder_spans,
+            value_types: cached.value_types,
+            prefix_matchers: OnceLock::new(),
+        })
+    }
 }
 
 struct MessageMatcher {
@@ -47,6 +206,11 @@ struct MessageMatcher {
     quality: usize,
     pattern: String,
     args: Vec<FormatArgument>,
+    segments: Vec<String>,
+    literal: String,
+    precisions: Vec<Option<usize>>,
+    spans: Vec<(usize, usize)>,
+    value_types: Vec<Option<VarType>>,
 }
 
 impl SourceRef {
@@ -57,11 +221,18 @@ impl SourceRef {
         let line_no = range.start_point.row + 1;
         let end_line_no = range.end_point.row + 1;
         let col = range.start_point.column;
-        let start = range.start_byte + 1;
+        // Skip any prefix on the literal, like the `b` in a Rust byte string or the `L` in
+        // a C++ wide string.
+        let prefix = text
+            .find(['"', '\'', '`'])
+            .filter(|index| text[..*index].chars().all(|c| c.is_ascii_alphanumeric()))
+            .unwrap_or(0);
+        let start = range.start_byte + prefix + 1;
         let mut end = range.end_byte - 1;
         if start == range.end_byte {
             end = range.end_byte;
         }
+        let verbatim = result.pattern.is_none();
         let unquoted = if let Some(pat) = result.pattern {
             pat
         } else {
@@ -72,9 +243,17 @@ impl SourceRef {
             pattern,
             mut args,
             quality,
+            segments,
+            literal,
+            precisions,
+            spans,
+            value_types,
         }) = build_matcher(result.raw, &unquoted, code.info.language)
         {
-            let name = source[result.name_range].to_string();
+            let name = result.name_range.map_or_else(
+                || GLOBAL_NAME.to_string(),
+                |range| source[range].to_string(),
+            );
             if !result.args.is_empty() {
                 args = result.args;
             }
@@ -84,6 +263,8 @@ impl SourceRef {
                 line_no,
                 end_line_no,
                 column: col,
+                start_byte: result.call_range.start,
+                end_byte: result.call_range.end,
                 name,
                 text,
                 quality,
@@ -91,6 +272,23 @@ impl SourceRef {
                 pattern,
                 args,
                 vars: vec![],
+                context_fields: result.context_fields,
+                exception: None,
+                level: result.level.map(str::to_string),
+                segments,
+                literal,
+                precisions,
+                placeholder_spans: if verbatim {
+                    let offset = start - range.start_byte;
+                    spans
+                        .into_iter()
+                        .map(|(lo, hi)| (lo + offset, hi + offset))
+                        .collect()
+                } else {
+                    vec![]
+                },
+                value_types,
+                prefix_matchers: OnceLock::new(),
             })
         } else {
             None
@@ -98,10 +296,547 @@ impl SourceRef {
     }
 
     pub fn captures<'a>(&self, line: &'a str) -> Option<Captures<'a>> {
-        self.matcher.captures(line)
+        self.matcher
+            .captures(line)
+            .filter(|captures| self.repeats_agree(captures))
+    }
+
+    /// Check if the given log message matches this statement.
+    pub(crate) fn is_match(&self, line: &str) -> bool {
+        if self.has_repeats() {
+            self.captures(line).is_some()
+        } else {
+            self.matcher.is_match(line)
+        }
+    }
+
+    /// Get the index of the first placeholder that refers to the same argument as the
+    /// placeholder at the given index, like the first `{0}` in `"{0} then {1} then {0}"`.
+    pub(crate) fn first_reference(&self, index: usize) -> Option<usize> {
+        let arg = &self.args[index];
+        if *arg == FormatArgument::Placeholder {
+            return None;
+        }
+        self.args[..index].iter().position(|prev| prev == arg)
+    }
+
+    fn has_repeats(&self) -> bool {
+        (1..self.args.len()).any(|index| self.first_reference(index).is_some())
+    }
+
+    /// Check that the values captured for an argument that is referenced more than once
+    /// are the same, since the regex cannot check that itself.
+    fn repeats_agree(&self, captures: &Captures) -> bool {
+        (1..self.args.len().min(captures.len() - 1)).all(|index| {
+            match self.first_reference(index) {
+                Some(first) => {
+                    captures.get(first + 1).map(|m| m.as_str())
+                        == captures.get(index + 1).map(|m| m.as_str())
+                }
+                None => true,
+            }
+        })
+    }
+
+    /// Match a log message that was cut off anywhere in this statement, either in a value
+    /// or in the literal text.  The cut is searched for from the end of the statement to
+    /// the start.  Along with the captures for the values before the cut, a flag is
+    /// returned that is true if the cut was in the last value.
+    pub(crate) fn prefix_captures<'a>(&self, line: &'a str) -> Option<(Captures<'a>, bool)> {
+        if !self.could_be_prefix(line) {
+            return None;
+        }
+        self.prefix_matchers().iter().rev().find_map(|matcher| {
+            if let Some(captures) = matcher
+                .in_segment
+                .as_ref()
+                .and_then(|regex| regex.captures(line))
+            {
+                return Some((captures, false));
+            }
+            matcher
+                .in_value
+                .as_ref()?
+                .captures(line)
+                .map(|captures| (captures, true))
+        })
+    }
+
+    /// Get the patterns for [`prefix_captures`](SourceRef::prefix_captures), one for each
+    /// segment, compiling them if this is the first time they are needed.
+    fn prefix_matchers(&self) -> &[PrefixMatcher] {
+        self.prefix_matchers.get_or_init(|| {
+            let flags = self.flags();
+            // The values before the cut are matched lazily so that a value does not swallow
+            // the literal text that follows it when only part of that text was logged.
+            let prefix = |count: usize| {
+                let mut pattern = format!("{}^", flags);
+                for segment in &self.segments[..count] {
+                    pattern.push_str(segment);
+                    pattern.push_str("(.+?)");
+                }
+                pattern
+            };
+            (0..self.segments.len())
+                .map(|count| {
+                    let segment = &self.segments[count];
+                    let in_segment = (!segment.is_empty())
+                        .then(|| format!("{}{}$", prefix(count), segment_prefix_pattern(segment)));
+                    // A cut in a value at the start of the statement, with no literal text
+                    // before it, would match any message.
+                    let text_before = count > 1 || (count == 1 && !self.segments[0].is_empty());
+                    let in_value = text_before
+                        .then(|| format!("{}{}(.+)$", prefix(count - 1), self.segments[count - 1]));
+                    PrefixMatcher {
+                        in_segment: in_segment.and_then(|pattern| Regex::new(&pattern).ok()),
+                        in_value: in_value.and_then(|pattern| Regex::new(&pattern).ok()),
+                    }
+                })
+                .collect()
+        })
+    }
+
+    /// The flags at the start of the pattern, like case-insensitivity, that need to be kept
+    /// by the patterns derived from it.
+    fn flags(&self) -> &str {
+        self.pattern
+            .split_once('^')
+            .map_or("(?s)", |(flags, _)| flags)
+    }
+
+    /// A quick check of the literal text at the start of the statement to avoid matching
+    /// the patterns in [`prefix_captures`](SourceRef::prefix_captures) for most statements.
+    /// If the statement starts with a value, the message has to contain at least the start
+    /// of the text that follows it.
+    fn could_be_prefix(&self, line: &str) -> bool {
+        let Some((index, head)) = self
+            .segments
+            .iter()
+            .enumerate()
+            .find(|(_index, segment)| !segment.is_empty())
+        else {
+            return false;
+        };
+        // Whitespace is matched flexibly, so the text cannot be compared directly.
+        if head.contains("\\s") {
+            return true;
+        }
+        let ignore_case = self.flags().contains('i');
+        let head = unescape_segment(head);
+        let same = |(lhs, rhs): (char, char)| {
+            lhs == rhs || (ignore_case && lhs.to_lowercase().eq(rhs.to_lowercase()))
+        };
+        if index == 0 {
+            return head.chars().zip(line.chars()).all(same);
+        }
+        head.chars()
+            .next()
+            .is_some_and(|first| line.chars().skip(1).any(|c| same((first, c))))
     }
 }
 
+/// The most characters of a segment that are considered when matching a message that was
+/// cut off in the middle of it, to keep the nesting of the pattern shallow.
+const MAX_PREFIX_ATOMS: usize = 64;
+
+/// Build a pattern that matches any non-empty prefix of the given segment of a pattern,
+/// like `a(?:b(?:c)?)?` for `abc`.  Escape sequences and a quantifier that follows an atom,
+/// like the `\s+` for flexible whitespace, are kept together.
+fn segment_prefix_pattern(segment: &str) -> String {
+    let mut atoms = Vec::new();
+    let mut chars = segment.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c == '\\' && chars.next().is_some_and(|(_, escaped)| escaped == 'x') {
+            chars.next();
+            chars.next();
+        }
+        chars.next_if(|(_, c)| matches!(c, '+' | '*' | '?'));
+        let end = chars.peek().map_or(segment.len(), |(index, _)| *index);
+        atoms.push(&segment[start..end]);
+    }
+    atoms.truncate(MAX_PREFIX_ATOMS);
+    let mut retval = atoms.join("(?:");
+    retval.push_str(&")?".repeat(atoms.len().saturating_sub(1)));
+    retval
+}
+
+/// The name given to a statement that is not inside of any function, like one in the
+/// top-level code of a Python module.
+pub const GLOBAL_NAME: &str = "<global>";
+
+/// A rough multiplier for the size of a compiled regex relative to the length of its pattern.
+pub(crate) const REGEX_SIZE_FACTOR: usize = 32;
+
+impl SourceRef {
+    /// Estimate the number of bytes of memory used by this reference, including the
+    /// compiled regex.
+    pub(crate) fn memory_estimate(&self) -> usize {
+        fn strings(values: &[String]) -> usize {
+            values
+                .iter()
+                .map(|value| size_of::<String>() + value.capacity())
+                .sum()
+        }
+
+        size_of::<SourceRef>()
+            + self.source_path.capacity()
+            + self.name.capacity()
+            + self.text.capacity()
+            + self.pattern.capacity() * (1 + REGEX_SIZE_FACTOR)
+            + self.args.capacity() * size_of::<FormatArgument>()
+            + strings(&self.vars)
+            + self
+                .context_fields
+                .iter()
+                .map(|field| {
+                    size_of::<ContextField>() + field.key.capacity() + field.expr.capacity()
+                })
+                .sum::<usize>()
+            + self.exception.as_ref().map_or(0, |expr| expr.capacity())
+            + self.level.as_ref().map_or(0, |level| level.capacity())
+            + strings(&self.segments)
+            + self.literal.capacity()
+            + self.precisions.capacity() * size_of::<Option<usize>>()
+            + self.placeholder_spans.capacity() * size_of::<(usize, usize)>()
+            + self.value_types.capacity() * size_of::<Option<VarType>>()
+    }
+
+    /// Move the arguments that are not consumed by the format string into the context
+    /// fields.  The arguments are expected to alternate between a string key and a value,
+    /// like Go's slog, although an attribute can also be built by a call like
+    /// `slog.Int("n", n)`.  Like slog, a value without a key is given the key `!BADKEY`.
+    pub(crate) fn split_context_fields(&mut self) {
+        let consumed = self.args.len().min(self.vars.len());
+        let mut rest = self.vars.split_off(consumed).into_iter();
+        while let Some(arg) = rest.next() {
+            let field = if let Some(key) = unquote(&arg) {
+                ContextField {
+                    key: key.to_string(),
+                    expr: rest.next().unwrap_or_default(),
+                }
+            } else if let Some(cap) = ATTR_CALL_REGEX.captures(&arg) {
+                ContextField {
+                    key: cap[1].to_string(),
+                    expr: cap[2].trim().to_string(),
+                }
+            } else {
+                ContextField {
+                    key: "!BADKEY".to_string(),
+                    expr: arg,
+                }
+            };
+            self.context_fields.push(field);
+        }
+    }
+
+    /// Render the format string of this statement with each placeholder replaced by the
+    /// given text.
+    pub(crate) fn template(&self, placeholder: &str) -> String {
+        self.segments
+            .iter()
+            .map(|segment| unescape_segment(segment))
+            .collect::<Vec<_>>()
+            .join(placeholder)
+    }
+
+    /// Get the literal text around each placeholder in the format string.
+    pub(crate) fn literal_segments(&self) -> Vec<String> {
+        self.segments
+            .iter()
+            .map(|segment| unescape_segment(segment))
+            .collect()
+    }
+
+    /// Render an example of a message that would be logged by this statement.  Each
+    /// placeholder is replaced by the value for its variable, which is looked up by the
+    /// name or the expression for the argument, or the index for a positional one.
+    /// Placeholders without a value are rendered as `<var>`.
+    pub fn render_example(&self, values: &HashMap<String, String>) -> String {
+        let mut placeholder_index = 0;
+        let mut retval = String::new();
+        for (index, segment) in self.segments.iter().enumerate() {
+            retval.push_str(&unescape_segment(segment));
+            let Some(arg) = self.args.get(index) else {
+                break;
+            };
+            let value = match arg {
+                FormatArgument::Named(name) => values.get(name),
+                FormatArgument::Positional(pos) => self
+                    .vars
+                    .get(*pos)
+                    .and_then(|expr| values.get(expr))
+                    .or_else(|| values.get(&pos.to_string())),
+                FormatArgument::Placeholder => {
+                    placeholder_index += 1;
+                    self.vars
+                        .get(placeholder_index - 1)
+                        .and_then(|expr| values.get(expr))
+                }
+            };
+            retval.push_str(value.map_or("<var>", |value| value.as_str()));
+        }
+        retval
+    }
+
+    /// Add a placeholder for each context field to the end of the pattern so that a message
+    /// that was rendered as `msg key=value ...`, like slog's text handler does, can be
+    /// matched.  The keys become named arguments and the value expressions are added to
+    /// the variables.  The fields are optional, since other handlers, like slog's JSON
+    /// handler, keep them out of the message, in which case the values are missing.
+    pub(crate) fn append_context_placeholders(&mut self) {
+        let Some(pattern) = self.pattern.strip_suffix('$') else {
+            return;
+        };
+        if self.context_fields.is_empty() {
+            return;
+        }
+        // A value at the end of the message would take the fields as well if it was greedy.
+        let mut pattern = match pattern.strip_suffix("(.+)") {
+            Some(rest) => format!("{rest}(.+?)(?:"),
+            None => format!("{pattern}(?:"),
+        };
+        for field in &self.context_fields {
+            let separator = regex::escape(&format!(" {}=", field.key));
+            pattern.push_str(&separator);
+            pattern.push_str("(.+)");
+            self.quality += field.key.chars().filter(|c| !c.is_whitespace()).count() + 1;
+            if let Some(last) = self.segments.last_mut() {
+                last.push_str(&separator);
+            }
+            self.segments.push(String::new());
+            self.args.push(FormatArgument::Named(field.key.clone()));
+            self.precisions.push(None);
+            self.value_types.push(None);
+            self.vars.push(field.expr.clone());
+        }
+        pattern.push_str(")?$");
+        self.matcher = Regex::new(&pattern).unwrap();
+        self.pattern = pattern;
+        self.prefix_matchers.take();
+    }
+
+    /// Change the pattern so that it matches messages regardless of case.
+    pub(crate) fn ignore_case(&mut self) {
+        if let Some(rest) = self.pattern.strip_prefix("(?s)") {
+            self.pattern = format!("(?si){}", rest);
+            self.matcher = Regex::new(&self.pattern).unwrap();
+            self.prefix_matchers.take();
+        }
+    }
+
+    /// Change the pattern so that runs of whitespace in the literal text match any amount
+    /// of whitespace, while the patterns for the values are left as they are.
+    pub(crate) fn flexible_whitespace(&mut self) {
+        self.pattern = flexible_whitespace(&self.pattern);
+        self.matcher = Regex::new(&self.pattern).unwrap();
+        self.segments = self
+            .segments
+            .iter()
+            .map(|segment| flexible_whitespace(segment))
+            .collect();
+        // The literal is searched for verbatim, so it cannot contain any whitespace.
+        self.literal = self
+            .literal
+            .split_whitespace()
+            .max_by_key(|run| run.len())
+            .unwrap_or_default()
+            .to_string();
+        self.prefix_matchers.take();
+    }
+
+    /// Change the patterns for the values of placeholders with a precision but no type, like
+    /// `{:.2}`, to only match numbers when the argument is known to be a number, like a
+    /// literal or a cast with `as f64`.  Otherwise the value could be a truncated string.
+    pub(crate) fn numeric_precisions(&mut self) {
+        let mut pattern = self.pattern.clone();
+        let groups = value_groups(&self.pattern);
+        for (index, range) in groups.into_iter().enumerate().rev() {
+            if self.precisions.get(index).is_none_or(Option::is_none)
+                || self.value_types.get(index).is_none_or(Option::is_some)
+            {
+                continue;
+            }
+            let expr = match &self.args[index] {
+                FormatArgument::Positional(position) => self.vars.get(*position),
+                _ => None,
+            };
+            let Some(value_type) = expr.and_then(|expr| numeric_expr_type(expr)) else {
+                continue;
+            };
+            let value = &pattern[range.clone()];
+            if value == ".+" || value == ".+?" {
+                pattern.replace_range(range, PRECISION_VALUE);
+                self.value_types[index] = Some(value_type);
+            }
+        }
+        if pattern != self.pattern {
+            self.matcher = Regex::new(&pattern).unwrap();
+            self.pattern = pattern;
+            self.prefix_matchers.take();
+        }
+    }
+
+    /// Change the patterns for integer and floating-point values so that the digits before
+    /// any decimal point can be grouped, like `1,000` or `1 000`.
+    pub(crate) fn grouped_numbers(&mut self) {
+        let mut pattern = self.pattern.clone();
+        let groups = value_groups(&self.pattern);
+        for (range, value_type) in groups.into_iter().zip(&self.value_types).rev() {
+            if !matches!(value_type, Some(VarType::Integer | VarType::Float)) {
+                continue;
+            }
+            // The integer part always comes first in the patterns for numbers.
+            if let Some(offset) = pattern[range.clone()].find(r"\d+") {
+                let start = range.start + offset;
+                pattern.replace_range(start..start + 3, GROUPED_DIGITS);
+            }
+        }
+        if pattern != self.pattern {
+            self.matcher = Regex::new(&pattern).unwrap();
+            self.pattern = pattern;
+            self.prefix_matchers.take();
+        }
+    }
+
+    /// Move the last argument of an SLF4J call into the exception if there is one more
+    /// argument than there are placeholders, since SLF4J treats it as a `Throwable`.
+    pub(crate) fn split_throwable(&mut self) {
+        if self.vars.len() == self.args.len() + 1 {
+            self.exception = self.vars.pop();
+        }
+    }
+
+    /// Remove the keyword arguments of a Python logging call, like `exc_info=True`, from
+    /// the variables since they are not substituted into the message.  The entries of an
+    /// `extra` dict are kept as the context fields.
+    pub(crate) fn split_keyword_arguments(&mut self) {
+        let mut vars = Vec::with_capacity(self.vars.len());
+        for var in std::mem::take(&mut self.vars) {
+            let Some((name, value)) = split_keyword_argument(&var) else {
+                vars.push(var);
+                continue;
+            };
+            if name != "extra" {
+                continue;
+            }
+            let entries = value
+                .strip_prefix('{')
+                .and_then(|rest| rest.strip_suffix('}'));
+            match entries {
+                Some(entries) => {
+                    for entry in split_top_level(entries, ',') {
+                        let mut parts = split_top_level(entry, ':').into_iter();
+                        let (Some(key), Some(expr)) = (parts.next(), parts.next()) else {
+                            continue;
+                        };
+                        self.context_fields.push(ContextField {
+                            key: unquote(key.trim()).unwrap_or(key.trim()).to_string(),
+                            expr: expr.trim().to_string(),
+                        });
+                    }
+                }
+                // The dict is built elsewhere, so we can only record the expression.
+                None => self.context_fields.push(ContextField {
+                    key: name.to_string(),
+                    expr: value.to_string(),
+                }),
+            }
+        }
+        self.vars = vars;
+    }
+}
+
+/// Turn a segment of a pattern back into the literal text that it matches.  Escapes that
+/// match a class of characters, like the one for a Python named-Unicode escape, are turned
+/// into a question mark.
+fn unescape_segment(segment: &str) -> String {
+    let mut retval = String::with_capacity(segment.len());
+    let mut chars = segment.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            retval.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => retval.push('\n'),
+            Some('r') => retval.push('\r'),
+            Some('t') => retval.push('\t'),
+            Some('w') => retval.push('?'),
+            Some('x') => {
+                let hex = chars.by_ref().take(2).collect::<String>();
+                if let Some(c) = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                    retval.push(c);
+                }
+            }
+            Some(c) => retval.push(c),
+            None => {}
+        }
+    }
+    retval
+}
+
+/// Split a Python keyword argument, like `extra={...}`, into the name and value.
+fn split_keyword_argument(arg: &str) -> Option<(&str, &str)> {
+    let (name, value) = arg.split_once('=')?;
+    let name = name.trim();
+    let is_identifier = name
+        .chars()
+        .enumerate()
+        .all(|(index, c)| c == '_' || c.is_alphabetic() || (index > 0 && c.is_numeric()));
+    if name.is_empty() || !is_identifier || value.starts_with('=') {
+        return None;
+    }
+    Some((name, value.trim()))
+}
+
+/// Split an expression on a separator that is not nested inside brackets or a string.
+fn split_top_level(expr: &str, separator: char) -> Vec<&str> {
+    let mut retval = Vec::new();
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut start = 0;
+    for (index, c) in expr.char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            _ if c == separator && depth == 0 => {
+                retval.push(&expr[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    if !expr[start..].trim().is_empty() {
+        retval.push(&expr[start..]);
+    }
+    retval
+}
+
+/// Regex for a call that builds a key/value attribute, like `slog.Int("n", n)`.
+static ATTR_CALL_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?s)^[\w.]+\(\s*["`]([^"`]*)["`]\s*,(.*)\)$"#).unwrap());
+
+/// Strip the quotes from a simple string-literal expression.
+fn unquote(expr: &str) -> Option<&str> {
+    ['"', '\'', '`'].into_iter().find_map(|quote| {
+        expr.strip_prefix(quote)
+            .and_then(|rest| rest.strip_suffix(quote))
+            .filter(|inner| !inner.contains(quote))
+    })
+}
+
 impl fmt::Display for SourceRef {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -125,33 +860,354 @@ impl PartialEq for SourceRef {
 fn build_matcher(raw: bool, text: &str, language: SourceLanguage) -> Option<MessageMatcher> {
     let mut args = Vec::new();
     let mut last_end = 0;
+    // Values can contain newlines, like a pretty-printed struct, so the value groups need
+    // to match them as well.
     let mut pattern = "(?s)^".to_string();
     let mut quality = 0;
+    let mut segments = Vec::new();
+    let mut literal = "";
+    let mut next_implicit = 0;
+    let mut precisions = Vec::new();
+    let mut spans = Vec::new();
+    let mut value_types = Vec::new();
+    let mut subtext = String::new();
     for cap in language.get_placeholder_regex().captures_iter(text) {
         let placeholder = cap.get(0).unwrap();
-        let subtext = escape_ignore_newlines(raw, &text[last_end..placeholder.start()]);
+        let run = longest_literal_run(raw, &text[last_end..placeholder.start()]);
+        if run.len() > literal.len() {
+            literal = run;
+        }
+        subtext.push_str(&escape_ignore_newlines(
+            raw,
+            &text[last_end..placeholder.start()],
+        ));
+        last_end = placeholder.end();
+        if let Some(escaped) = unescape_placeholder(placeholder.as_str()) {
+            subtext.push_str(&regex::escape(escaped));
+            continue;
+        }
         quality += subtext.chars().filter(|c| !c.is_whitespace()).count();
         pattern.push_str(subtext.as_str());
-        last_end = placeholder.end();
-        pattern.push_str("(.+)");
-        args.push(language.captures_to_format_arg(&cap));
+        segments.push(std::mem::take(&mut subtext));
+        if language == SourceLanguage::Rust {
+            pattern.push_str(&rust_value_pattern(placeholder.as_str()));
+        } else if language == SourceLanguage::Cpp && placeholder.as_str().starts_with('%') {
+            pattern.push_str(&printf_value_pattern(placeholder.as_str()));
+        } else {
+            pattern.push_str("(.+)");
+        }
+        let mut arg = language.captures_to_format_arg(&cap);
+        if language == SourceLanguage::Rust {
+            // Follow the rules from std::fmt for selecting arguments: a "{}" takes the next
+            // implicit argument, an explicit index does not change the counter, and a ".*"
+            // precision consumes an implicit argument before the value.
+            if placeholder.as_str().contains(".*") {
+                next_implicit += 1;
+            }
+            if arg == FormatArgument::Placeholder {
+                arg = FormatArgument::Positional(next_implicit);
+                next_implicit += 1;
+            }
+        }
+        args.push(arg);
+        spans.push((placeholder.start(), placeholder.end()));
+        value_types.push(placeholder_type(placeholder.as_str()));
+        precisions.push(
+            PRECISION_REGEX
+                .captures(placeholder.as_str())
+                .and_then(|caps| caps[1].parse().ok()),
+        );
+    }
+    let run = longest_literal_run(raw, &text[last_end..]);
+    if run.len() > literal.len() {
+        literal = run;
     }
-    let subtext = escape_ignore_newlines(raw, &text[last_end..]);
+    subtext.push_str(&escape_ignore_newlines(raw, &text[last_end..]));
     quality += subtext.chars().filter(|c| !c.is_whitespace()).count();
     if quality == 0 {
         None
     } else {
         pattern.push_str(subtext.as_str());
         pattern.push('$');
+        segments.push(subtext);
         Some(MessageMatcher {
             matcher: Regex::new(pattern.as_str()).unwrap(),
             quality,
             pattern,
             args,
+            segments,
+            literal: literal.to_string(),
+            precisions,
+            spans,
+            value_types,
         })
     }
 }
 
+/// Replace the runs of whitespace outside of any group in a pattern with `\s+`.  Runs
+/// that are followed by a quantifier, like the padding in ` *(\d+)`, are kept.
+fn flexible_whitespace(pattern: &str) -> String {
+    let mut result = String::with_capacity(pattern.len());
+    let mut run = String::new();
+    let mut depth = 0usize;
+    let mut in_class = false;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        let token = if c == '\\' {
+            chars
+                .next()
+                .map_or(c.to_string(), |next| format!("\\{}", next))
+        } else {
+            c.to_string()
+        };
+        if depth == 0 && matches!(token.as_str(), " " | "\\t" | "\\n" | "\\r") {
+            run.push_str(&token);
+            continue;
+        }
+        if !run.is_empty() {
+            if matches!(token.as_str(), "*" | "+" | "?" | "{") {
+                result.push_str(&run);
+            } else {
+                result.push_str("\\s+");
+            }
+            run.clear();
+        }
+        match token.as_str() {
+            "[" => in_class = true,
+            "]" => in_class = false,
+            "(" if !in_class => depth += 1,
+            ")" if !in_class => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        result.push_str(&token);
+    }
+    if !run.is_empty() {
+        result.push_str("\\s+");
+    }
+    result
+}
+
+/// A pattern for a run of digits that may be split into groups of three by a separator,
+/// like the thousands in `1,000`, `1.000`, or `1 000`.
+const GROUPED_DIGITS: &str = r"(?:\d{1,3}(?:[,.' \x{A0}\x{202F}]\d{3})+|\d+)";
+
+/// The pattern for a number formatted with a precision, like `{:.2}`.
+const PRECISION_VALUE: &str = r"[-+]?(?:\d+(?:\.\d+)?|inf|NaN)";
+
+/// The names of the Rust primitive number types.
+const RUST_INTEGER_TYPES: &[&str] = &[
+    "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64", "u128", "usize",
+];
+
+/// Get the type of a Rust expression that is known to be a number, like `2.5`, `10_u32`,
+/// or `total as f64`.
+fn numeric_expr_type(expr: &str) -> Option<VarType> {
+    let expr = expr.trim();
+    let is_float = |ty: &str| matches!(ty, "f32" | "f64");
+    if let Some((_value, ty)) = expr.rsplit_once(" as ") {
+        let ty = ty.trim();
+        return if is_float(ty) {
+            Some(VarType::Float)
+        } else {
+            RUST_INTEGER_TYPES.contains(&ty).then_some(VarType::Integer)
+        };
+    }
+    let digits = expr.strip_prefix('-').unwrap_or(expr);
+    if !digits.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let (literal, ty) = RUST_INTEGER_TYPES
+        .iter()
+        .chain(&["f32", "f64"])
+        .find_map(|ty| Some((digits.strip_suffix(ty)?, Some(*ty))))
+        .unwrap_or((digits, None));
+    let literal = literal.replace('_', "");
+    if ty.is_some_and(is_float) || (literal.contains('.') && ty.is_none()) {
+        literal.parse::<f64>().ok().map(|_| VarType::Float)
+    } else {
+        literal.parse::<u128>().ok().map(|_| VarType::Integer)
+    }
+}
+
+/// Find the contents of the top-level capture groups in a pattern, which hold the values
+/// for the placeholders.
+fn value_groups(pattern: &str) -> Vec<Range<usize>> {
+    let mut retval = Vec::new();
+    let mut depth = 0usize;
+    let mut in_class = false;
+    let mut start = None;
+    let mut chars = pattern.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '(' if !in_class => {
+                if depth == 0 && chars.peek().is_none_or(|(_, next)| *next != '?') {
+                    start = Some(index + 1);
+                }
+                depth += 1;
+            }
+            ')' if !in_class => {
+                depth = depth.saturating_sub(1);
+                if let Some(start) = start.take_if(|_| depth == 0) {
+                    retval.push(start..index);
+                }
+            }
+            _ => {}
+        }
+    }
+    retval
+}
+
+/// Infer the type of the value for a placeholder from its format specifier, like `%d` or
+/// `{:x}`.  A placeholder without a specifier, like `{}`, is assumed to be a string.
+/// Debug and generic formats, like `{:?}` and `%v`, and a precision without a type, like
+/// `{:.2}`, which can be a float or a truncated string, could be anything.
+fn placeholder_type(placeholder: &str) -> Option<VarType> {
+    if let Some(spec) = placeholder.strip_prefix('%') {
+        return match spec.chars().last()? {
+            'd' | 'i' | 'u' | 'x' | 'X' | 'o' => Some(VarType::Integer),
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' => Some(VarType::Float),
+            't' => Some(VarType::Bool),
+            's' | 'c' | 'q' => Some(VarType::String),
+            _ => None,
+        };
+    }
+    let inner = placeholder
+        .trim_start_matches(['$', '{'])
+        .trim_end_matches('}');
+    let Some((_arg, spec)) = inner.split_once(':') else {
+        // Other formats, like `{0,number}` in Java, are not handled.
+        return (!inner.contains(',')).then_some(VarType::String);
+    };
+    match spec.trim().chars().last() {
+        Some('d' | 'x' | 'X' | 'o' | 'b' | 'n') => Some(VarType::Integer),
+        Some('f' | 'F' | 'e' | 'E' | 'g' | 'G' | '%') => Some(VarType::Float),
+        Some('?') => None,
+        _ if spec.contains('.') => None,
+        _ => Some(VarType::String),
+    }
+}
+
+/// Get the literal text for a placeholder that is actually an escape sequence, like `{{`
+/// in a std::fmt string or `%%` in a printf-style one.
+fn unescape_placeholder(placeholder: &str) -> Option<&'static str> {
+    match placeholder {
+        "{{" => Some("{"),
+        "}}" => Some("}"),
+        "%%" => Some("%"),
+        _ => None,
+    }
+}
+
+/// Regex for the format spec of a std::fmt placeholder, like the `:>8.2` in `{x:>8.2}`.
+static RUST_SPEC_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"^[^:]*:(?:(?<fill>.)?(?<align>[<^>]))?[-+]?(?<alt>#)?(?<zero>0)?(?<width>\w+\$|\d+)?(?:\.(?<precision>\w+\$|\d+|\*))?(?<type>[a-zA-Z]?\??)\s*}$"#,
+    )
+    .unwrap()
+});
+
+/// Build the capture group for the value of a std::fmt placeholder.  The type in the
+/// format spec narrows down the characters in the value, like hex digits for `{:x}`, and
+/// the padding added for a width, like in `{:>8}`, is kept out of the capture.
+fn rust_value_pattern(placeholder: &str) -> String {
+    let Some(spec) = RUST_SPEC_REGEX.captures(placeholder) else {
+        return "(.+)".to_string();
+    };
+    let alt = spec.name("alt").is_some();
+    let radix =
+        |prefix: &str, digits: &str| format!("{}[{}]+", if alt { prefix } else { "" }, digits);
+    let value = match spec.name("type").map_or("", |m| m.as_str()) {
+        "x" => radix("0x", "0-9a-f"),
+        "X" => radix("0x", "0-9A-F"),
+        "o" => radix("0o", "0-7"),
+        "b" => radix("0b", "01"),
+        "e" | "E" => r"-?\d+(?:\.\d+)?[eE]-?\d+".to_string(),
+        // A precision without a type, like `{:.2}`, could also be truncating a string, so
+        // it is only narrowed down by `numeric_precisions` if the argument is a number.
+        _ => ".+".to_string(),
+    };
+    // The zero flag pads numbers with zeros, which are part of the value.
+    if spec.name("width").is_none() || spec.name("zero").is_some() {
+        return format!("({})", value);
+    }
+    let value = if value == ".+" { ".+?" } else { value.as_str() };
+    let fill = regex::escape(spec.name("fill").map_or(" ", |m| m.as_str()));
+    match spec.name("align").map(|m| m.as_str()) {
+        Some("<") => format!("({}){}*", value, fill),
+        Some(">") => format!("{}*({})", fill, value),
+        _ => format!("{}*({}){}*", fill, value, fill),
+    }
+}
+
+/// Regex for the parts of a printf conversion specification, like `%-10.2f` or `%lld`.
+static PRINTF_SPEC_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"^%(?<flags>[-+ #0]*)(?<width>\d*)(?:\.\d+)?[hlLzjt]*(?<type>.)$"#).unwrap()
+});
+
+/// Build the capture group for the value of a printf conversion.  The conversion narrows
+/// down the characters in the value, like digits for `%d`, and the padding added for a
+/// width, like in `%-10s`, is kept out of the capture.
+fn printf_value_pattern(placeholder: &str) -> String {
+    let Some(spec) = PRINTF_SPEC_REGEX.captures(placeholder) else {
+        return "(.+)".to_string();
+    };
+    let flags = spec.name("flags").map_or("", |m| m.as_str());
+    let alt = flags.contains('#');
+    let radix =
+        |prefix: &str, digits: &str| format!("{}[{}]+", if alt { prefix } else { "" }, digits);
+    let value = match spec.name("type").map_or("", |m| m.as_str()) {
+        "d" | "i" => r"[-+ ]?\d+".to_string(),
+        "u" => r"\d+".to_string(),
+        "x" => radix("0x", "0-9a-f"),
+        "X" => radix("0X", "0-9A-F"),
+        "o" => radix("0", "0-7"),
+        "f" | "F" => r"[-+ ]?(?:\d+(?:\.\d*)?|(?i:inf|infinity|nan))".to_string(),
+        "e" | "E" => r"[-+ ]?(?:\d(?:\.\d*)?[eE][-+]\d+|(?i:inf|infinity|nan))".to_string(),
+        "g" | "G" => r"[-+ ]?(?:\d+(?:\.\d*)?(?:[eE][-+]\d+)?|(?i:inf|infinity|nan))".to_string(),
+        "c" => ".".to_string(),
+        "p" => r"0x[0-9a-fA-F]+|\(nil\)|0".to_string(),
+        // A %n writes the number of characters so far instead of printing anything.
+        "n" => return "()".to_string(),
+        _ => ".+".to_string(),
+    };
+    if spec.name("width").is_none_or(|m| m.as_str().is_empty()) {
+        return format!("({})", value);
+    }
+    let value = if value == ".+" { ".+?" } else { value.as_str() };
+    if flags.contains('-') {
+        format!("({}) *", value)
+    } else {
+        format!(" *({})", value)
+    }
+}
+
+/// Regex for the precision in the format spec of a placeholder, like `{:.3}` or `%8.2f`.
+static PRECISION_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?::[^}]*?|^%[-+ #0]*\d*)\.(\d+)"#).unwrap());
+
+/// Regex for the escape sequences in a string-literal.  Text that contains these sequences
+/// will not appear verbatim in a rendered log message.
+static ESCAPE_SEQUENCE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"\\(?:N\{[^}]+}|u\{[^}]*}|u[0-9a-fA-F]{4}|x[0-9a-fA-F]{2}|[0-7]{1,3}|.)"#).unwrap()
+});
+
+/// Find the longest run of text in a segment of a string-literal that will appear verbatim
+/// in a rendered log message.
+fn longest_literal_run(raw: bool, segment: &str) -> &str {
+    if raw {
+        return segment;
+    }
+    ESCAPE_SEQUENCE_REGEX
+        .split(segment)
+        .max_by_key(|run| run.len())
+        .unwrap_or_default()
+}
+
 /// Regex for finding values that need to be escaped in a string-literal.  The components are
 /// as follows:
 ///
@@ -279,12 +1335,223 @@ mod tests {
         assert_eq!(args[0], FormatArgument::Positional(2));
     }
 
+    #[test]
+    fn test_build_matcher_implicit_and_explicit() {
+        let MessageMatcher { args, .. } =
+            build_matcher(false, "a={0} b={} c={1}", SourceLanguage::Rust).unwrap();
+        assert_eq!(
+            args,
+            [
+                FormatArgument::Positional(0),
+                FormatArgument::Positional(0),
+                FormatArgument::Positional(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_matcher_precision_star() {
+        let MessageMatcher { args, .. } =
+            build_matcher(false, "a={:.*} b={} c={0:.*}", SourceLanguage::Rust).unwrap();
+        assert_eq!(
+            args,
+            [
+                FormatArgument::Positional(1),
+                FormatArgument::Positional(2),
+                FormatArgument::Positional(0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_matcher_literal() {
+        let MessageMatcher { literal, .. } = build_matcher(
+            false,
+            "short {} the longest run\\tafter {}",
+            SourceLanguage::Rust,
+        )
+        .unwrap();
+        assert_eq!(literal, " the longest run");
+    }
+
+    #[test]
+    fn test_build_matcher_value_types() {
+        let MessageMatcher { value_types, .. } = build_matcher(
+            false,
+            "a={} b={:?} c={:x} d={:.2} e={x:>8}",
+            SourceLanguage::Rust,
+        )
+        .unwrap();
+        assert_eq!(
+            value_types,
+            [
+                Some(VarType::String),
+                None,
+                Some(VarType::Integer),
+                None,
+                Some(VarType::String)
+            ]
+        );
+        let MessageMatcher { value_types, .. } =
+            build_matcher(false, "n=%d avg=%5.1lf name=%s p=%p", SourceLanguage::Cpp).unwrap();
+        assert_eq!(
+            value_types,
+            [
+                Some(VarType::Integer),
+                Some(VarType::Float),
+                Some(VarType::String),
+                None
+            ]
+        );
+        let MessageMatcher { value_types, .. } =
+            build_matcher(false, "ok=%t v=%v", SourceLanguage::Go).unwrap();
+        assert_eq!(value_types, [Some(VarType::Bool), None]);
+    }
+
+    #[test]
+    fn test_build_matcher_precisions() {
+        let MessageMatcher { precisions, .. } = build_matcher(
+            false,
+            "a={:.3} b={x:8.2} c={} d={:.*} e={self.0}",
+            SourceLanguage::Rust,
+        )
+        .unwrap();
+        assert_eq!(precisions, [Some(3), Some(2), None, None, None]);
+        let MessageMatcher { precisions, .. } =
+            build_matcher(false, "took %.4f and %5.1lf and %d", SourceLanguage::Cpp).unwrap();
+        assert_eq!(precisions, [Some(4), Some(1), None]);
+    }
+
+    #[test]
+    fn test_build_matcher_format_specs() {
+        let MessageMatcher { matcher, args, .. } = build_matcher(
+            false,
+            "dbg={name:?} hex={:04x} alt={:#X} float={:.2} right={:>8}|",
+            SourceLanguage::Rust,
+        )
+        .unwrap();
+        assert_eq!(
+            matcher.as_str(),
+            r#"(?s)^dbg=(.+) hex=([0-9a-f]+) alt=(0x[0-9A-F]+) float=(.+) right= *(.+?)\|$"#
+        );
+        assert_eq!(args[0], FormatArgument::Named("name".to_string()));
+        assert_eq!(args[1], FormatArgument::Positional(0));
+        let caps = matcher
+            .captures("dbg=Some(1) hex=00ff alt=0xBEEF float=-3.14 right=    abc|")
+            .unwrap();
+        assert_eq!(
+            caps.iter()
+                .skip(1)
+                .map(|cap| cap.unwrap().as_str())
+                .collect::<Vec<_>>(),
+            ["Some(1)", "00ff", "0xBEEF", "-3.14", "abc"]
+        );
+        assert!(matcher
+            .captures("dbg=Some(1) hex=zz alt=0xBEEF float=-3.14 right=    abc|")
+            .is_none());
+
+        let MessageMatcher { matcher, .. } =
+            build_matcher(false, "[{:*<6}] [{:^5}]", SourceLanguage::Rust).unwrap();
+        let caps = matcher.captures("[ab****] [  c  ]").unwrap();
+        assert_eq!(&caps[1], "ab");
+        assert_eq!(&caps[2], "c");
+    }
+
+    #[test]
+    fn test_numeric_expr_type() {
+        assert_eq!(numeric_expr_type("2.5"), Some(VarType::Float));
+        assert_eq!(numeric_expr_type("-1_000.25_f64"), Some(VarType::Float));
+        assert_eq!(numeric_expr_type("10u32"), Some(VarType::Integer));
+        assert_eq!(numeric_expr_type("total as f64"), Some(VarType::Float));
+        assert_eq!(numeric_expr_type("len as usize"), Some(VarType::Integer));
+        assert_eq!(numeric_expr_type("name"), None);
+        assert_eq!(numeric_expr_type("inf"), None);
+        assert_eq!(numeric_expr_type("x as Name"), None);
+    }
+
+    #[test]
+    fn test_flexible_whitespace() {
+        assert_eq!(
+            flexible_whitespace(r"(?s)^a:  b\t([-+ ]?\d+) *( +)$"),
+            r"(?s)^a:\s+b\s+([-+ ]?\d+) *( +)$"
+        );
+        assert_eq!(flexible_whitespace(r"x\\ n"), r"x\\\s+n");
+    }
+
+    #[test]
+    fn test_value_groups() {
+        let pattern = r"(?s)^a\((.+)\) *([-+ ]?\d+) ([-+ ]?(?:\d+(?:\.\d*)?|(?i:inf))) [(]()$";
+        assert_eq!(
+            value_groups(pattern)
+                .into_iter()
+                .map(|range| &pattern[range])
+                .collect::<Vec<_>>(),
+            [".+", r"[-+ ]?\d+", r"[-+ ]?(?:\d+(?:\.\d*)?|(?i:inf))", ""]
+        );
+    }
+
+    #[test]
+    fn test_segment_prefix_pattern() {
+        assert_eq!(segment_prefix_pattern("abc"), "a(?:b(?:c)?)?");
+        assert_eq!(
+            segment_prefix_pattern(r"a\.\s+\x1B"),
+            r"a(?:\.(?:\s+(?:\x1B)?)?)?"
+        );
+        assert_eq!(segment_prefix_pattern("é!"), "é(?:!)?");
+    }
+
+    #[test]
+    fn test_build_matcher_printf() {
+        let MessageMatcher { matcher, .. } = build_matcher(
+            false,
+            "read %d bytes from %s at %p, flags=%#x, took %-10.2fms|%5lld|",
+            SourceLanguage::Cpp,
+        )
+        .unwrap();
+        let caps = matcher
+            .captures(
+                "read 42 bytes from /dev/sda1 at 0x7ffd1234, flags=0x1f, took 3.14      ms|  -17|",
+            )
+            .unwrap();
+        assert_eq!(&caps[1], "42");
+        assert_eq!(&caps[2], "/dev/sda1");
+        assert_eq!(&caps[3], "0x7ffd1234");
+        assert_eq!(&caps[4], "0x1f");
+        assert_eq!(&caps[5], "3.14");
+        assert_eq!(&caps[6], "-17");
+        assert!(!matcher
+            .is_match("read many bytes from /dev/sda1 at 0x7ffd1234, flags=0x1f, took 3.14ms|1|"));
+
+        let MessageMatcher { matcher, .. } =
+            build_matcher(false, "waited %d%s", SourceLanguage::Cpp).unwrap();
+        let caps = matcher.captures("waited 250ms").unwrap();
+        assert_eq!(&caps[1], "250");
+        assert_eq!(&caps[2], "ms");
+    }
+
+    #[test]
+    fn test_build_matcher_escaped_braces() {
+        let MessageMatcher {
+            matcher,
+            args,
+            segments,
+            ..
+        } = build_matcher(false, "set {{key}} to {}", SourceLanguage::Rust).unwrap();
+        assert_eq!(matcher.as_str(), r#"(?s)^set \{key\} to (.+)$"#);
+        assert_eq!(args, [FormatArgument::Positional(0)]);
+        assert_eq!(segments.len(), 2);
+        let MessageMatcher { matcher, args, .. } =
+            build_matcher(false, "{{{}}} is 100%% done", SourceLanguage::Cpp).unwrap();
+        assert_eq!(matcher.as_str(), r#"(?s)^\{(.+)\} is 100% done$"#);
+        assert_eq!(args.len(), 1);
+    }
+
     #[test]
     fn test_build_matcher_cpp() {
         let MessageMatcher { matcher, args, .. } =
             build_matcher(false, "they are %d years old", SourceLanguage::Cpp).unwrap();
         assert_eq!(
-            Regex::new(r#"(?s)^they are (.+) years old$"#)
+            Regex::new(r#"(?s)^they are ([-+ ]?\d+) years old$"#)
                 .unwrap()
                 .as_str(),
             matcher.as_str()
@@ -0,0 +1,228 @@
+use serde::Serialize;
+
+use crate::SourceRef;
+
+/// An edit that turns the template of a statement into a log message.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum DiffOp {
+    /// Text that is the same in both.
+    Equal(String),
+    /// Text in the template that is missing from the message.
+    Delete(String),
+    /// Text in the message that is missing from the template.
+    Insert(String),
+}
+
+/// A statement that is similar to, but does not match, a log message.  This usually means
+/// that the statement was changed after the log was written.
+#[derive(Clone, Debug, Serialize)]
+pub struct MatchSuggestion {
+    #[serde(rename(serialize = "srcRef"))]
+    pub src_ref: SourceRef,
+    /// The format string of the statement with the placeholders left in.
+    pub template: String,
+    /// The body of the log message.
+    pub body: String,
+    /// The fraction of the template and body that is the same, from zero to one.
+    pub similarity: f64,
+}
+
+impl MatchSuggestion {
+    pub(crate) fn new(src_ref: &SourceRef, body: &str) -> Self {
+        let template = src_ref.template("{}");
+        let template_chars = template.chars().collect::<Vec<_>>();
+        let body_chars = body.chars().collect::<Vec<_>>();
+        let total = template_chars.len() + body_chars.len();
+        let similarity = if total == 0 {
+            1.0
+        } else {
+            (2 * lcs_len(&template_chars, &body_chars)) as f64 / total as f64
+        };
+        Self {
+            src_ref: src_ref.clone(),
+            template,
+            body: body.to_string(),
+            similarity,
+        }
+    }
+
+    /// Compute a character-level diff from the template of the statement to the body of
+    /// the log message.  Short runs of equal characters in the middle of a changed word are
+    /// folded into the change so that the whole word is highlighted.
+    pub fn diff(&self) -> Vec<DiffOp> {
+        let template = self.template.chars().collect::<Vec<_>>();
+        let body = self.body.chars().collect::<Vec<_>>();
+        cleanup(raw_diff(&template, &body))
+    }
+}
+
+/// Compute the Levenshtein distance between the literal text of a statement and a log
+/// message.  Each placeholder can stand in for any run of text in the message at no cost,
+/// so only the edits to the literal text are counted.
+pub(crate) fn fuzzy_distance(segments: &[String], body: &str) -> usize {
+    let body = body.chars().collect::<Vec<_>>();
+    // The distance from the start of the template to each prefix of the body.
+    let mut prev = (0..=body.len()).collect::<Vec<_>>();
+    for (index, segment) in segments.iter().enumerate() {
+        if index > 0 {
+            // A placeholder can end anywhere after where the last literal text ended.
+            for pos in 1..prev.len() {
+                prev[pos] = prev[pos].min(prev[pos - 1]);
+            }
+        }
+        for lc in segment.chars() {
+            let mut curr = vec![prev[0] + 1; body.len() + 1];
+            for (pos, rc) in body.iter().enumerate() {
+                let substitution = prev[pos] + usize::from(lc != *rc);
+                curr[pos + 1] = substitution.min(prev[pos + 1] + 1).min(curr[pos] + 1);
+            }
+            prev = curr;
+        }
+    }
+    prev[body.len()]
+}
+
+/// Compute the length of the longest common subsequence of two strings.
+fn lcs_len(lhs: &[char], rhs: &[char]) -> usize {
+    let mut prev = vec![0; rhs.len() + 1];
+    let mut curr = vec![0; rhs.len() + 1];
+    for lc in lhs {
+        for (index, rc) in rhs.iter().enumerate() {
+            curr[index + 1] = if lc == rc {
+                prev[index] + 1
+            } else {
+                prev[index + 1].max(curr[index])
+            };
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+    prev[rhs.len()]
+}
+
+/// Compute a diff using the table of longest common subsequences of the suffixes.
+fn raw_diff(lhs: &[char], rhs: &[char]) -> Vec<DiffOp> {
+    let mut table = vec![vec![0usize; rhs.len() + 1]; lhs.len() + 1];
+    for li in (0..lhs.len()).rev() {
+        for ri in (0..rhs.len()).rev() {
+            table[li][ri] = if lhs[li] == rhs[ri] {
+                table[li + 1][ri + 1] + 1
+            } else {
+                table[li + 1][ri].max(table[li][ri + 1])
+            };
+        }
+    }
+
+    let mut retval = Vec::new();
+    let (mut li, mut ri) = (0, 0);
+    while li < lhs.len() || ri < rhs.len() {
+        if li < lhs.len() && ri < rhs.len() && lhs[li] == rhs[ri] {
+            push_op(&mut retval, DiffOp::Equal(lhs[li].to_string()));
+            li += 1;
+            ri += 1;
+        } else if ri < rhs.len() && (li == lhs.len() || table[li][ri + 1] >= table[li + 1][ri]) {
+            push_op(&mut retval, DiffOp::Insert(rhs[ri].to_string()));
+            ri += 1;
+        } else {
+            push_op(&mut retval, DiffOp::Delete(lhs[li].to_string()));
+            li += 1;
+        }
+    }
+    retval
+}
+
+/// Append an operation, merging it with the last one if they are the same kind.
+fn push_op(ops: &mut Vec<DiffOp>, op: DiffOp) {
+    match (ops.last_mut(), op) {
+        (Some(DiffOp::Equal(last)), DiffOp::Equal(text))
+        | (Some(DiffOp::Delete(last)), DiffOp::Delete(text))
+        | (Some(DiffOp::Insert(last)), DiffOp::Insert(text)) => last.push_str(&text),
+        (_, op) => ops.push(op),
+    }
+}
+
+/// Fold short runs of equal alphanumeric characters that are between two changes into the
+/// changes and group the deletes before the inserts.
+fn cleanup(ops: Vec<DiffOp>) -> Vec<DiffOp> {
+    let is_edit = |op: Option<&DiffOp>| matches!(op, Some(DiffOp::Delete(_) | DiffOp::Insert(_)));
+    let mut retval = Vec::new();
+    let mut deleted = String::new();
+    let mut inserted = String::new();
+    for (index, op) in ops.iter().enumerate() {
+        match op {
+            DiffOp::Delete(text) => deleted.push_str(text),
+            DiffOp::Insert(text) => inserted.push_str(text),
+            DiffOp::Equal(text)
+                if index > 0
+                    && is_edit(ops.get(index - 1))
+                    && is_edit(ops.get(index + 1))
+                    && text.chars().count() <= 2
+                    && text.chars().all(char::is_alphanumeric) =>
+            {
+                deleted.push_str(text);
+                inserted.push_str(text);
+            }
+            DiffOp::Equal(text) => {
+                flush(&mut retval, &mut deleted, &mut inserted);
+                retval.push(DiffOp::Equal(text.clone()));
+            }
+        }
+    }
+    flush(&mut retval, &mut deleted, &mut inserted);
+    retval
+}
+
+fn flush(ops: &mut Vec<DiffOp>, deleted: &mut String, inserted: &mut String) {
+    if !deleted.is_empty() {
+        ops.push(DiffOp::Delete(std::mem::take(deleted)));
+    }
+    if !inserted.is_empty() {
+        ops.push(DiffOp::Insert(std::mem::take(inserted)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(lhs: &str, rhs: &str) -> Vec<DiffOp> {
+        let lhs = lhs.chars().collect::<Vec<_>>();
+        let rhs = rhs.chars().collect::<Vec<_>>();
+        cleanup(raw_diff(&lhs, &rhs))
+    }
+
+    #[test]
+    fn test_diff_changed_word() {
+        assert_eq!(
+            diff("connection closed by peer", "connection dropped by peer"),
+            [
+                DiffOp::Equal("connection ".to_string()),
+                DiffOp::Delete("clos".to_string()),
+                DiffOp::Insert("dropp".to_string()),
+                DiffOp::Equal("ed by peer".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_distance() {
+        let segments = |parts: &[&str]| parts.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(fuzzy_distance(&segments(&["kitten"]), "sitting"), 3);
+        assert_eq!(
+            fuzzy_distance(&segments(&["user ", " logged in"]), "user bob logged in"),
+            0
+        );
+        assert_eq!(
+            fuzzy_distance(&segments(&["user ", " logged in"]), "usr bob logged on"),
+            2
+        );
+        assert_eq!(fuzzy_distance(&segments(&["", ""]), "anything"), 0);
+        assert_eq!(fuzzy_distance(&segments(&["abc"]), ""), 3);
+    }
+
+    #[test]
+    fn test_diff_same() {
+        assert_eq!(diff("abc", "abc"), [DiffOp::Equal("abc".to_string())]);
+        assert!(diff("", "").is_empty());
+        assert_eq!(diff("", "abc"), [DiffOp::Insert("abc".to_string())]);
+    }
+}
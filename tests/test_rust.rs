@@ -24,6 +24,27 @@ fn basic() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn colored() -> Result<(), Box<dyn std::error::Error>> {
+    let _guard = common_settings::enable_filters();
+    let mut cmd = Command::cargo_bin("log2src")?;
+    let source = Path::new("examples").join("basic.rs");
+    let log = Path::new("tests")
+        .join("resources")
+        .join("rust")
+        .join("colored.log");
+    cmd.arg("-d")
+        .arg(source.to_str().expect("test case path is valid"))
+        .arg("-l")
+        .arg(log.to_str().expect("test case log path is valid"))
+        .arg("-f")
+        .arg(r#"\[\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}Z \w+ \w+\]\s+(?<body>.*)"#)
+        .arg("--strip-ansi");
+
+    assert_cmd_snapshot!(cmd);
+    Ok(())
+}
+
 #[test]
 fn stack() -> Result<(), Box<dyn std::error::Error>> {
     let _guard = common_settings::enable_filters();